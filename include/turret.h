@@ -0,0 +1,26 @@
+#ifndef TURRET_H
+#define TURRET_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+typedef struct TurretClient TurretClient;
+
+typedef struct TurretResult {
+    bool ok;
+    uint8_t *data;  /* target stdout when ok */
+    size_t len;
+    char *error;    /* "code: message" when !ok */
+} TurretResult;
+
+/* Returns NULL if the daemon socket cannot be reached. */
+TurretClient *turret_connect(const char *sock_path);
+
+/* params_json is the fire payload; agent_id is always taken from rookie. */
+TurretResult *turret_invoke(const TurretClient *client, const char *rookie, const char *params_json);
+
+void turret_free_result(TurretResult *result);
+void turret_disconnect(TurretClient *client);
+
+#endif
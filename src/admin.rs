@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::approval::PendingApproval;
+use crate::client::{roundtrip, ClientError};
+use crate::bunker::same_key;
+use crate::history::HistoryEntry;
+use crate::metrics::TargetMetrics;
+use crate::usage::UsageSnapshot;
+
+/// Operator requests on `./<bunker-name>.admin.sock`. Access is gated by socket file permissions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AdminRequest {
+    Stats,
+    Recent { limit: usize },
+    /// Write a non-secret state dump to the daemon's dump path; never one the client picks.
+    Dump,
+    /// Re-read and re-decrypt the bunker file.
+    Reload,
+    /// Liveness and load at a glance.
+    Status,
+    /// Invokes of `require_approval` targets waiting on an operator.
+    Pending,
+    /// A single-use nonce for the next [`OperatorProof`].
+    Challenge,
+    /// Let a parked invoke run, once `proof` shows a bunker operator asked for it.
+    Approve { request_id: String, proof: OperatorProof },
+    /// Refuse every invoke with `frozen` until `Thaw`. `proof` is checked as for `Approve`.
+    Freeze { proof: OperatorProof },
+    Thaw { proof: OperatorProof },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AdminResponse {
+    pub ok: bool,
+    pub message: Option<String>,
+    pub stats: Option<BTreeMap<String, TargetMetrics>>,
+    pub recent: Option<Vec<HistoryEntry>>,
+    /// Cumulative counters; unlike `stats` these survive daemon restarts.
+    pub usage: Option<UsageSnapshot>,
+    #[serde(default)]
+    pub status: Option<DaemonStatus>,
+    #[serde(default)]
+    pub pending: Option<Vec<PendingApproval>>,
+    /// Answer to `Challenge`.
+    #[serde(default)]
+    pub nonce: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub uptime_ms: u64,
+    pub targets: usize,
+    /// Recruits with a shared secret or a key.
+    pub agents: usize,
+    /// Invocations currently running.
+    pub in_flight: usize,
+    /// Signed-invoke nonces still remembered.
+    pub replay_cache: usize,
+    /// Invokes parked until an operator approves them.
+    #[serde(default)]
+    pub awaiting_approval: usize,
+    pub shutting_down: bool,
+    #[serde(default)]
+    pub frozen: bool,
+}
+
+impl AdminResponse {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            message: Some(message.into()),
+            ..Self::default()
+        }
+    }
+}
+
+/// SSHSIG namespace for operator admin requests, so no invoke or bunker signature can stand in for one.
+pub const ADMIN_SIG_NAMESPACE: &str = "turret-admin@overyonder";
+
+/// How long a `Challenge` nonce can be answered.
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(60);
+
+/// Unanswered challenges beyond this are refused rather than remembered.
+const MAX_CHALLENGES: usize = 1024;
+
+/// An operator's signature over one admin action and a daemon nonce. The daemon checks it, not the client.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OperatorProof {
+    /// OpenSSH public key; must be one of the bunker's operators.
+    pub key: String,
+    /// From `Challenge`.
+    pub nonce: String,
+    /// PEM SSHSIG over the action, its subject and `nonce`.
+    pub signature: String,
+}
+
+impl OperatorProof {
+    /// `action` is the request's `op`; `subject` what it acts on, such as the request id, or empty.
+    pub fn sign(key: &ssh_key::PrivateKey, action: &str, subject: &str, nonce: &str) -> Result<Self, String> {
+        let signature = key
+            .sign(ADMIN_SIG_NAMESPACE, ssh_key::HashAlg::Sha512, &Self::signed_bytes(action, subject, nonce))
+            .and_then(|sig| sig.to_pem(ssh_key::LineEnding::LF))
+            .map_err(|e| format!("sign: {e}"))?;
+        let key = key.public_key().to_openssh().map_err(|e| e.to_string())?;
+        Ok(Self {
+            key,
+            nonce: nonce.to_string(),
+            signature,
+        })
+    }
+
+    /// The fingerprint of the key, once it is one of `operators` and its signature covers `action` and `subject`.
+    /// The nonce is the caller's to check.
+    pub fn verify(&self, operators: &BTreeSet<String>, action: &str, subject: &str) -> Result<String, String> {
+        let key = ssh_key::PublicKey::from_openssh(&self.key).map_err(|e| format!("operator key: {e}"))?;
+        if !operators.iter().any(|op| same_key(op, &key)) {
+            return Err("key is not an operator of this bunker".to_string());
+        }
+        let sig = ssh_key::SshSig::from_pem(&self.signature).map_err(|e| format!("operator signature: {e}"))?;
+        key.verify(ADMIN_SIG_NAMESPACE, &Self::signed_bytes(action, subject, &self.nonce), &sig)
+            .map_err(|_| "bad operator signature".to_string())?;
+        Ok(key.fingerprint(Default::default()).to_string())
+    }
+
+    fn signed_bytes(action: &str, subject: &str, nonce: &str) -> Vec<u8> {
+        serde_json::to_vec(&(action, subject, nonce)).unwrap_or_default()
+    }
+}
+
+/// Nonces handed out by `Challenge`, each good for one [`OperatorProof`] within [`CHALLENGE_TTL`].
+#[derive(Debug, Default)]
+pub struct Challenges(Mutex<BTreeMap<String, Instant>>);
+
+impl Challenges {
+    pub fn issue(&self) -> Result<String, String> {
+        let mut issued = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        issued.retain(|_, at| at.elapsed() < CHALLENGE_TTL);
+        if issued.len() >= MAX_CHALLENGES {
+            return Err("too many unanswered challenges".to_string());
+        }
+        let nonce = crate::invoke::random_nonce().map_err(|e| format!("nonce: {e}"))?;
+        issued.insert(nonce.clone(), Instant::now());
+        Ok(nonce)
+    }
+
+    /// Whether `nonce` was issued and is still fresh; either way it cannot be used again.
+    pub fn take(&self, nonce: &str) -> bool {
+        let mut issued = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        issued.remove(nonce).is_some_and(|at| at.elapsed() < CHALLENGE_TTL)
+    }
+}
+
+pub fn admin_call(
+    sock_path: &Path,
+    req: &AdminRequest,
+    timeout: Option<Duration>,
+) -> Result<AdminResponse, ClientError> {
+    let body = serde_json::to_vec(req).map_err(|e| ClientError::Payload(e.to_string()))?;
+    let resp = roundtrip(sock_path, timeout, &body)?;
+    let parsed: AdminResponse =
+        serde_json::from_slice(&resp).map_err(|e| ClientError::Response(e.to_string()))?;
+    if !parsed.ok {
+        return Err(ClientError::Rejected {
+            code: "admin".to_string(),
+            message: parsed.message.unwrap_or_else(|| "request failed".to_string()),
+        });
+    }
+    Ok(parsed)
+}
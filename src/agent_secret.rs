@@ -0,0 +1,37 @@
+//! Recruit shared secrets in `[agents]`: stored as argon2id PHC strings, or plaintext in older bunkers.
+
+use std::io::Read;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use subtle::ConstantTimeEq;
+
+/// Stored values with this prefix are hashes; anything else is a legacy plaintext secret.
+const HASH_PREFIX: &str = "$argon2";
+
+pub fn is_hashed(stored: &str) -> bool {
+    stored.starts_with(HASH_PREFIX)
+}
+
+/// argon2id with the crate's default cost and a fresh 16-byte salt.
+pub fn hash(secret: &str) -> Result<String, String> {
+    let mut salt = [0u8; 16];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut salt))
+        .map_err(|e| format!("read /dev/urandom: {e}"))?;
+    let salt = SaltString::encode_b64(&salt).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(secret.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Check a presented secret against its stored form without short-circuiting on content.
+pub fn verify(stored: &str, presented: &str) -> bool {
+    if !is_hashed(stored) {
+        return bool::from(stored.as_bytes().ct_eq(presented.as_bytes()));
+    }
+    PasswordHash::new(stored)
+        .and_then(|h| Argon2::default().verify_password(presented.as_bytes(), &h))
+        .is_ok()
+}
@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit::now_ms;
+
+/// `[alerts]` bunker table. Each event is delivered as JSON to `exec` (on stdin) and/or
+/// POSTed to `webhook` (via curl).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertConfig {
+    #[serde(default)]
+    pub exec: Option<Vec<String>>,
+    #[serde(default)]
+    pub webhook: Option<String>,
+    #[serde(default = "default_auth_failures")]
+    pub auth_failures: usize,
+    #[serde(default = "default_auth_window_secs")]
+    pub auth_window_secs: u64,
+}
+
+fn default_auth_failures() -> usize {
+    5
+}
+
+fn default_auth_window_secs() -> u64 {
+    60
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AlertEvent {
+    AuthFailures {
+        agent: String,
+        count: usize,
+        window_secs: u64,
+    },
+    OperatorAdded {
+        bunker: String,
+        key: String,
+    },
+    OperatorRemoved {
+        bunker: String,
+        key: String,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AlertError {
+    #[error("alert hook {0}: {1}")]
+    Hook(&'static str, String),
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    ts_ms: u64,
+    #[serde(flatten)]
+    event: &'a AlertEvent,
+}
+
+impl AlertConfig {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.exec.as_ref().is_some_and(|argv| argv.is_empty()) {
+            return Err("alerts exec is empty");
+        }
+        if self
+            .webhook
+            .as_ref()
+            .is_some_and(|u| !(u.starts_with("http://") || u.starts_with("https://")))
+        {
+            return Err("alerts webhook must be an http(s) url");
+        }
+        if self.auth_failures == 0 {
+            return Err("alerts auth_failures must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// Deliver to every configured hook, waiting for each to finish.
+    pub fn send(&self, event: &AlertEvent) -> Result<(), AlertError> {
+        let body = serde_json::to_vec(&Envelope {
+            ts_ms: now_ms(),
+            event,
+        })
+        .map_err(|e| AlertError::Hook("encode", e.to_string()))?;
+
+        let mut first_err = None;
+        if let Some(argv) = &self.exec {
+            if let Err(e) = run_hook(Command::new(&argv[0]).args(&argv[1..]), &body) {
+                first_err.get_or_insert(AlertError::Hook("exec", e));
+            }
+        }
+        if let Some(url) = &self.webhook {
+            let mut cmd = Command::new("curl");
+            cmd.args(["-fsS", "-m", "10", "-X", "POST"])
+                .args(["-H", "Content-Type: application/json"])
+                .args(["--data-binary", "@-"])
+                .arg(url);
+            if let Err(e) = run_hook(&mut cmd, &body) {
+                first_err.get_or_insert(AlertError::Hook("webhook", e));
+            }
+        }
+        first_err.map_or(Ok(()), Err)
+    }
+}
+
+fn run_hook(cmd: &mut Command, body: &[u8]) -> Result<(), String> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("spawn failed: {e}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(body)
+            .map_err(|e| format!("write stdin failed: {e}"))?;
+    }
+    let out = child
+        .wait_with_output()
+        .map_err(|e| format!("wait failed: {e}"))?;
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+    }
+    Ok(())
+}
+
+/// Sliding-window counter of authentication failures per claimed agent id.
+pub struct AuthFailureTracker {
+    threshold: usize,
+    window: Duration,
+    seen: BTreeMap<String, VecDeque<Instant>>,
+}
+
+impl AuthFailureTracker {
+    pub fn new(threshold: usize, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            seen: BTreeMap::new(),
+        }
+    }
+
+    /// Record one failure; returns the count when it reaches the threshold, then starts over.
+    pub fn record(&mut self, agent: &str, now: Instant) -> Option<usize> {
+        if self.seen.len() >= 1024 && !self.seen.contains_key(agent) {
+            self.prune(now);
+        }
+        let q = self.seen.entry(agent.to_string()).or_default();
+        while q.front().is_some_and(|t| now.duration_since(*t) > self.window) {
+            q.pop_front();
+        }
+        q.push_back(now);
+        if q.len() < self.threshold {
+            return None;
+        }
+        let count = q.len();
+        self.seen.remove(agent);
+        Some(count)
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let window = self.window;
+        self.seen
+            .retain(|_, q| q.back().is_some_and(|t| now.duration_since(*t) <= window));
+    }
+}
@@ -0,0 +1,220 @@
+//! Invokes held until someone else agrees: an operator running `approve` (`require_approval` targets), or a
+//! second recruit sending the same request (`two_person_window_secs` targets). Both time out.
+
+use std::collections::BTreeMap;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit::now_ms;
+use crate::invoke::InvokeResult;
+
+/// How long an invoke waits for approval unless the daemon is configured otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// An invoke waiting on an operator, as listed by `turret <bunker> pending`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingApproval {
+    pub request_id: String,
+    pub agent: String,
+    pub target: String,
+    pub since_ms: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Wait {
+    Approved,
+    TimedOut,
+}
+
+#[derive(Debug)]
+pub struct Approvals {
+    timeout: Duration,
+    waiting: Mutex<BTreeMap<String, Parked>>,
+    changed: Condvar,
+    /// First halves of two-person requests, by target and params hash.
+    halves: Mutex<BTreeMap<(String, String), Half>>,
+    paired: Condvar,
+}
+
+/// What the recruit that completes a pair got from running the target; errors as their message.
+pub type Outcome = Result<InvokeResult, String>;
+
+#[derive(Debug)]
+struct Half {
+    agent: String,
+    /// A second recruit has matched and is running it; the first now waits for the outcome.
+    matched: bool,
+    outcome: Option<Outcome>,
+}
+
+pub enum Pair<'a> {
+    /// This invoke completes the pair: run it and [`Second::report`] what happened.
+    Run(Second<'a>),
+    /// The second recruit ran it.
+    Ran(Outcome),
+    TimedOut,
+}
+
+/// Held by the invoke that completes a pair. Dropped without a report, it tells the first half it did not run.
+pub struct Second<'a> {
+    approvals: &'a Approvals,
+    key: (String, String),
+    outcome: Option<Outcome>,
+}
+
+#[derive(Debug)]
+struct Parked {
+    pending: PendingApproval,
+    approved: bool,
+}
+
+impl Approvals {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            waiting: Mutex::new(BTreeMap::new()),
+            changed: Condvar::new(),
+            halves: Mutex::new(BTreeMap::new()),
+            paired: Condvar::new(),
+        }
+    }
+
+    /// Park `request_id` until it is approved, the approval timeout passes, or `deadline` does.
+    /// Fails if an invoke with the same id is already parked.
+    pub fn wait(
+        &self,
+        request_id: &str,
+        agent: &str,
+        target: &str,
+        deadline: Option<Instant>,
+    ) -> Result<Wait, String> {
+        let timeout = Instant::now() + self.timeout;
+        let until = deadline.map_or(timeout, |d| d.min(timeout));
+        let mut waiting = self.lock();
+        if waiting.contains_key(request_id) {
+            return Err(format!("request id '{request_id}' is already awaiting approval"));
+        }
+        let pending = PendingApproval {
+            request_id: request_id.to_string(),
+            agent: agent.to_string(),
+            target: target.to_string(),
+            since_ms: now_ms(),
+        };
+        waiting.insert(request_id.to_string(), Parked { pending, approved: false });
+        loop {
+            if waiting.get(request_id).is_some_and(|p| p.approved) {
+                waiting.remove(request_id);
+                return Ok(Wait::Approved);
+            }
+            let Some(left) = until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) else {
+                waiting.remove(request_id);
+                return Ok(Wait::TimedOut);
+            };
+            waiting = self.changed.wait_timeout(waiting, left).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    /// Release the parked invoke `request_id`; false if there is none.
+    pub fn approve(&self, request_id: &str) -> bool {
+        let mut waiting = self.lock();
+        let Some(parked) = waiting.get_mut(request_id) else {
+            return false;
+        };
+        parked.approved = true;
+        self.changed.notify_all();
+        true
+    }
+
+    /// Oldest first.
+    pub fn pending(&self) -> Vec<PendingApproval> {
+        let mut out: Vec<PendingApproval> = self.lock().values().map(|p| p.pending.clone()).collect();
+        out.sort_by_key(|p| p.since_ms);
+        out
+    }
+
+    /// Match `agent`'s request for `target` with one from a different recruit carrying the same `params_hash`.
+    /// The first to arrive waits up to `window` (or until `deadline`); the second runs it for both.
+    pub fn pair(
+        &self,
+        target: &str,
+        params_hash: &str,
+        agent: &str,
+        window: Duration,
+        deadline: Option<Instant>,
+    ) -> Result<Pair<'_>, String> {
+        let key = (target.to_string(), params_hash.to_string());
+        let mut halves = self.halves();
+        match halves.get_mut(&key) {
+            Some(h) if h.matched => return Err("an identical request is already running".to_string()),
+            Some(h) if h.agent == agent => {
+                return Err(format!("'{agent}' is already waiting for a second recruit to send this request"))
+            }
+            Some(h) => {
+                h.matched = true;
+                return Ok(Pair::Run(Second {
+                    approvals: self,
+                    key,
+                    outcome: None,
+                }));
+            }
+            None => {}
+        }
+        let half = Half {
+            agent: agent.to_string(),
+            matched: false,
+            outcome: None,
+        };
+        halves.insert(key.clone(), half);
+        let window_end = Instant::now() + window;
+        let until = deadline.map_or(window_end, |d| d.min(window_end));
+        loop {
+            let half = halves.get_mut(&key).expect("only the first half removes its entry");
+            if let Some(outcome) = half.outcome.take() {
+                halves.remove(&key);
+                return Ok(Pair::Ran(outcome));
+            }
+            // Once matched, wait out the run however long it takes; the second's own deadline bounds it.
+            if half.matched {
+                halves = self.paired.wait(halves).unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+            let Some(left) = until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) else {
+                halves.remove(&key);
+                return Ok(Pair::TimedOut);
+            };
+            halves = self.paired.wait_timeout(halves, left).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Parked>> {
+        self.waiting.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn halves(&self) -> MutexGuard<'_, BTreeMap<(String, String), Half>> {
+        self.halves.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Second<'_> {
+    pub fn report(mut self, outcome: Outcome) {
+        self.outcome = Some(outcome);
+    }
+}
+
+impl Drop for Second<'_> {
+    fn drop(&mut self) {
+        let outcome = self.outcome.take().unwrap_or_else(|| Err("the second request did not run".to_string()));
+        if let Some(half) = self.approvals.halves().get_mut(&self.key) {
+            half.outcome = Some(outcome);
+        }
+        self.approvals.paired.notify_all();
+    }
+}
+
+impl Default for Approvals {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMEOUT)
+    }
+}
@@ -0,0 +1,327 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::invoke::{InvokeError, InvokeResult};
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuditError {
+    #[error("audit io {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("audit json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("audit chain broken at {path}:{line}: {message}")]
+    Chain {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+/// `prev` of the first record ever written.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsyncPolicy {
+    /// fsync after every record.
+    #[default]
+    Always,
+    /// Leave flushing to the OS.
+    Never,
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditConfig {
+    pub path: PathBuf,
+    /// Rotate once the active file would grow past this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Rotated files kept as `<path>.1` .. `<path>.<keep>`.
+    pub keep: usize,
+    pub fsync: FsyncPolicy,
+}
+
+impl AuditConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_bytes: None,
+            keep: 5,
+            fsync: FsyncPolicy::default(),
+        }
+    }
+}
+
+/// One line of the audit log: a single fire request and how far it got.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditRecord {
+    pub ts_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub agent: Option<String>,
+    pub target: Option<String>,
+    /// `ok` or `fail`; absent when the request never reached authentication.
+    pub auth: Option<&'static str>,
+    /// `allow` or `deny`; absent when authentication failed.
+    pub decision: Option<&'static str>,
+    /// `ok` or the daemon error code.
+    pub outcome: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+impl AuditRecord {
+    pub fn new(agent: Option<String>, target: Option<String>, outcome: impl Into<String>) -> Self {
+        Self {
+            ts_ms: now_ms(),
+            request_id: None,
+            agent,
+            target,
+            auth: None,
+            decision: None,
+            outcome: outcome.into(),
+            result_bytes: None,
+            exit_code: None,
+            duration_ms: None,
+        }
+    }
+
+    /// Record derived from what `execute_invoke` returned; it checks auth, then permission, then runs.
+    pub fn for_invoke(agent: &str, target: &str, res: &Result<InvokeResult, InvokeError>) -> Self {
+        let outcome = match res {
+            Ok(_) => "ok",
+            Err(e) => e.code(),
+        };
+        let mut rec = Self::new(Some(agent.to_string()), Some(target.to_string()), outcome);
+        match res {
+            Err(InvokeError::Unauthenticated | InvokeError::Replay(_)) => rec.auth = Some("fail"),
+            Err(InvokeError::PeerDenied | InvokeError::Frozen) => rec.decision = Some("deny"),
+            Err(
+                InvokeError::Denied
+                | InvokeError::RateLimited(_)
+                | InvokeError::QuotaExceeded(_)
+                | InvokeError::Busy(_)
+                | InvokeError::ApprovalTimeout
+                | InvokeError::TwoPersonTimeout,
+            ) => {
+                rec.auth = Some("ok");
+                rec.decision = Some("deny");
+            }
+            Err(
+                InvokeError::UnknownTarget
+                | InvokeError::BadRequest(_)
+                | InvokeError::Internal(_)
+                | InvokeError::DeadlineExceeded
+                | InvokeError::Cancelled
+                | InvokeError::SecretExpired(_),
+            ) => {
+                rec.auth = Some("ok");
+                rec.decision = Some("allow");
+            }
+            Ok(out) => {
+                rec.auth = Some("ok");
+                rec.decision = Some("allow");
+                rec.result_bytes = Some(out.stdout.len());
+                rec.exit_code = out.exit_code;
+                rec.duration_ms = Some(out.duration_ms);
+            }
+        }
+        rec
+    }
+}
+
+/// A record as written: `prev` is the sha256 of the previous line, chaining across rotations.
+#[derive(Serialize)]
+struct Chained<'a> {
+    #[serde(flatten)]
+    rec: &'a AuditRecord,
+    prev: &'a str,
+}
+
+/// Append-only JSONL audit sink with size-based rotation.
+pub struct AuditLog {
+    cfg: AuditConfig,
+    file: File,
+    size: u64,
+    /// Hash of the last line written, hex.
+    last: String,
+}
+
+impl AuditLog {
+    pub fn open(cfg: AuditConfig) -> Result<Self, AuditError> {
+        let file = open_append(&cfg.path)?;
+        let size = file.metadata().map_err(|e| io_err(&cfg.path, e))?.len();
+        // Continue the chain from the active file, or the newest rotated one when it is empty.
+        let mut last = None;
+        for path in [cfg.path.clone(), rotated(&cfg.path, 1)] {
+            if let Some(line) = last_line(&path)? {
+                last = Some(line_hash(&line));
+                break;
+            }
+        }
+        Ok(Self {
+            cfg,
+            file,
+            size,
+            last: last.unwrap_or_else(|| GENESIS.to_string()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.cfg.path
+    }
+
+    pub fn record(&mut self, rec: &AuditRecord) -> Result<(), AuditError> {
+        let json = serde_json::to_string(&Chained { rec, prev: &self.last })?;
+        let mut line = crate::redact::redact(&json).into_owned().into_bytes();
+        let hash = line_hash(&line);
+        line.push(b'\n');
+
+        if let Some(max) = self.cfg.max_bytes {
+            if self.size > 0 && self.size + line.len() as u64 > max {
+                self.rotate()?;
+            }
+        }
+
+        let path = self.cfg.path.clone();
+        self.file.write_all(&line).map_err(|e| io_err(&path, e))?;
+        if self.cfg.fsync == FsyncPolicy::Always {
+            self.file.sync_data().map_err(|e| io_err(&path, e))?;
+        }
+        self.size += line.len() as u64;
+        self.last = hash;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), AuditError> {
+        let path = self.cfg.path.clone();
+        self.file.sync_all().map_err(|e| io_err(&path, e))?;
+        if self.cfg.keep == 0 {
+            std::fs::remove_file(&path).map_err(|e| io_err(&path, e))?;
+        } else {
+            for i in (1..self.cfg.keep).rev() {
+                let from = rotated(&path, i);
+                if from.exists() {
+                    let to = rotated(&path, i + 1);
+                    std::fs::rename(&from, &to).map_err(|e| io_err(&from, e))?;
+                }
+            }
+            std::fs::rename(&path, rotated(&path, 1)).map_err(|e| io_err(&path, e))?;
+        }
+        self.file = open_append(&path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Check the `prev` chain through `paths`, oldest first. The first record's `prev` is taken on
+/// trust, since older files may have been rotated away. Returns the number of records checked.
+pub fn verify_chain(paths: &[PathBuf]) -> Result<usize, AuditError> {
+    let mut expected: Option<String> = None;
+    let mut count = 0;
+    for path in paths {
+        let file = File::open(path).map_err(|e| io_err(path, e))?;
+        for (i, line) in BufReader::new(file).split(b'\n').enumerate() {
+            let line = line.map_err(|e| io_err(path, e))?;
+            let broken = |message: String| AuditError::Chain {
+                path: path.clone(),
+                line: i + 1,
+                message,
+            };
+            let v: serde_json::Value = serde_json::from_slice(&line).map_err(|e| broken(e.to_string()))?;
+            let prev = v
+                .get("prev")
+                .and_then(|p| p.as_str())
+                .ok_or_else(|| broken("no prev hash".to_string()))?;
+            if let Some(want) = &expected {
+                if prev != want {
+                    return Err(broken(format!("prev is {prev}, previous line hashes to {want}")));
+                }
+            }
+            expected = Some(line_hash(&line));
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// `path` and its rotated files that exist, oldest first.
+pub fn log_files(path: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = (1..).map(|n| rotated(path, n)).take_while(|p| p.exists()).collect();
+    files.reverse();
+    if path.exists() {
+        files.push(path.to_path_buf());
+    }
+    files
+}
+
+fn line_hash(line: &[u8]) -> String {
+    Sha256::digest(line).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Last non-empty line of `path`, without its newline; `None` if the file is missing or empty.
+fn last_line(path: &Path) -> Result<Option<Vec<u8>>, AuditError> {
+    const CHUNK: u64 = 8 << 10;
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(io_err(path, e)),
+    };
+    let mut pos = file.seek(SeekFrom::End(0)).map_err(|e| io_err(path, e))?;
+    let mut tail = Vec::new();
+    loop {
+        let start = pos.saturating_sub(CHUNK);
+        let mut chunk = vec![0; (pos - start) as usize];
+        file.seek(SeekFrom::Start(start))
+            .and_then(|_| file.read_exact(&mut chunk))
+            .map_err(|e| io_err(path, e))?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+        pos = start;
+        let body = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        if let Some(i) = body.iter().rposition(|&b| b == b'\n') {
+            return Ok(Some(body[i + 1..].to_vec()));
+        }
+        if pos == 0 {
+            return Ok((!body.is_empty()).then(|| body.to_vec()));
+        }
+    }
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(format!(".{n}"));
+    PathBuf::from(s)
+}
+
+fn open_append(path: &Path) -> Result<File, AuditError> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| io_err(path, e))
+}
+
+fn io_err(path: &Path, source: io::Error) -> AuditError {
+    AuditError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
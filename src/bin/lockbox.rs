@@ -1,14 +1,120 @@
+use std::collections::{BTreeMap, HashSet};
 use std::io::Read;
 
+use serde::Deserialize;
+
+// Challenge file: set LOCKBOX_CHALLENGE=<path.toml> (e.g. via target out_env).
+// Any section or field left out keeps the built-in riddle below.
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Challenge {
+    argv: ArgvCheck,
+    env: EnvCheck,
+    stdin: StdinCheck,
+    triplecheck: TripleCheck,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct ArgvCheck {
+    token: String,
+    reply: String,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct EnvCheck {
+    key: String,
+    value: String,
+    reply: String,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct StdinCheck {
+    value: String,
+    reply: String,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct TripleCheck {
+    trigger: String,
+    argv_counts: BTreeMap<String, usize>,
+    env: BTreeMap<String, String>,
+    stdin_words: Vec<String>,
+    reply: String,
+}
+
+impl Default for ArgvCheck {
+    fn default() -> Self {
+        Self {
+            token: "rumplestiltskin".to_string(),
+            reply: "that's my name".to_string(),
+        }
+    }
+}
+
+impl Default for EnvCheck {
+    fn default() -> Self {
+        Self {
+            key: "isyourname".to_string(),
+            value: "tomtittot".to_string(),
+            reply: "I know him but that's not me".to_string(),
+        }
+    }
+}
+
+impl Default for StdinCheck {
+    fn default() -> Self {
+        Self {
+            value: "rampelnik".to_string(),
+            reply: "perhaps in a former life".to_string(),
+        }
+    }
+}
+
+impl Default for TripleCheck {
+    fn default() -> Self {
+        // Aliases (chosen arbitrarily):
+        // - argv must contain at least 2x "pump" and 2x "straw"
+        // - env vars isyourname and whatsyourname must be set to specific values
+        // - stdin must contain both "rampelnik" and "tomtittot" (whitespace-separated)
+        Self {
+            trigger: "triplecheck".to_string(),
+            argv_counts: [("pump", 2), ("straw", 2)]
+                .into_iter()
+                .map(|(k, n)| (k.to_string(), n))
+                .collect(),
+            env: [("isyourname", "tomtittot"), ("whatsyourname", "rumplestiltskin")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            stdin_words: vec!["rampelnik".to_string(), "tomtittot".to_string()],
+            reply: "all three speak true".to_string(),
+        }
+    }
+}
+
 fn main() {
+    let challenge = match load_challenge() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("lockbox: {e}");
+            std::process::exit(2);
+        }
+    };
+
     // Modes:
     // - default: precedence argv -> env -> stdin
     // - triplecheck: require all three channels to match (multiple quantities)
 
-    // Mode switch: if argv contains "triplecheck", enforce the strict mode.
-    if std::env::args().any(|a| a == "triplecheck") {
-        if triplecheck_ok() {
-            println!("all three speak true");
+    // Mode switch: if argv contains the trigger, enforce the strict mode.
+    let tc = &challenge.triplecheck;
+    if std::env::args().skip(1).any(|a| a == tc.trigger) {
+        if triplecheck_ok(tc) {
+            println!("{}", tc.reply);
             std::process::exit(0);
         }
         std::process::exit(1);
@@ -16,68 +122,63 @@ fn main() {
 
     // Precedence: argv -> env -> stdin
 
-    // 1) argv: rumplestiltskin
-    let mut args = std::env::args();
-    let _ = args.next();
-    for a in args {
-        if a == "rumplestiltskin" {
-            println!("that's my name");
-            std::process::exit(0);
-        }
+    // 1) argv
+    if std::env::args().skip(1).any(|a| a == challenge.argv.token) {
+        println!("{}", challenge.argv.reply);
+        std::process::exit(0);
     }
 
-    // 2) env: isyourname=tomtittot
-    if std::env::var("isyourname").ok().as_deref() == Some("tomtittot") {
-        println!("I know him but that's not me");
+    // 2) env
+    if std::env::var(&challenge.env.key).ok().as_deref() == Some(challenge.env.value.as_str()) {
+        println!("{}", challenge.env.reply);
         std::process::exit(0);
     }
 
-    // 3) stdin: rampelnik
-    let mut buf = Vec::new();
-    let _ = std::io::stdin().read_to_end(&mut buf);
-    let s = String::from_utf8_lossy(&buf);
-    let s = s.trim_end_matches(['\n', '\r']);
-    if s == "rampelnik" {
-        println!("perhaps in a former life");
+    // 3) stdin
+    let stdin_s = read_stdin();
+    let s = stdin_s.trim_end_matches(['\n', '\r']);
+    if s == challenge.stdin.value {
+        println!("{}", challenge.stdin.reply);
         std::process::exit(0);
     }
 
     std::process::exit(1);
 }
 
-fn triplecheck_ok() -> bool {
-    // Aliases (chosen arbitrarily):
-    // - argv must contain at least 2x "pump" and 2x "straw"
-    // - env vars isyourname and whatsyourname must be set to specific values
-    // - stdin must contain both "rampelnik" and "tomtittot" (whitespace-separated)
+fn load_challenge() -> Result<Challenge, String> {
+    let Ok(path) = std::env::var("LOCKBOX_CHALLENGE") else {
+        return Ok(Challenge::default());
+    };
+    let txt = std::fs::read_to_string(&path).map_err(|e| format!("read {path}: {e}"))?;
+    toml::from_str(&txt).map_err(|e| format!("bad challenge {path}: {e}"))
+}
 
-    let mut pump = 0usize;
-    let mut straw = 0usize;
+fn read_stdin() -> String {
+    let mut buf = Vec::new();
+    let _ = std::io::stdin().read_to_end(&mut buf);
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+fn triplecheck_ok(tc: &TripleCheck) -> bool {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
     for a in std::env::args().skip(1) {
-        if a == "pump" {
-            pump += 1;
-        } else if a == "straw" {
-            straw += 1;
+        if let Some((k, _)) = tc.argv_counts.get_key_value(&a) {
+            *counts.entry(k.as_str()).or_default() += 1;
         }
     }
-    if pump < 2 || straw < 2 {
-        return false;
-    }
-
-    if std::env::var("isyourname").ok().as_deref() != Some("tomtittot") {
-        return false;
-    }
-    if std::env::var("whatsyourname").ok().as_deref() != Some("rumplestiltskin") {
-        return false;
+    for (token, min) in &tc.argv_counts {
+        if counts.get(token.as_str()).copied().unwrap_or(0) < *min {
+            return false;
+        }
     }
 
-    let mut buf = Vec::new();
-    let _ = std::io::stdin().read_to_end(&mut buf);
-    let stdin_s = String::from_utf8_lossy(&buf);
-    let stdin_parts: std::collections::HashSet<&str> = stdin_s.split_whitespace().collect();
-    if !stdin_parts.contains("rampelnik") || !stdin_parts.contains("tomtittot") {
-        return false;
+    for (k, v) in &tc.env {
+        if std::env::var(k).ok().as_deref() != Some(v.as_str()) {
+            return false;
+        }
     }
 
-    true
+    let stdin_s = read_stdin();
+    let stdin_parts: HashSet<&str> = stdin_s.split_whitespace().collect();
+    tc.stdin_words.iter().all(|w| stdin_parts.contains(w.as_str()))
 }
@@ -1,21 +1,46 @@
 use std::collections::BTreeSet;
 use std::io::{self, Read, Write};
-use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use clap::{Parser, Subcommand};
-use base64::Engine;
-use serde::{Deserialize, Serialize};
+use clap::{Parser, Subcommand, ValueEnum};
+use tracing::{info, warn};
 
-use turret::bunker::Bunker;
+use turret::admin::{admin_call, AdminRequest, OperatorProof};
+use turret::agent_secret;
+use turret::alert::{AlertConfig, AlertEvent};
+use turret::audit::{AuditConfig, AuditLog, FsyncPolicy};
+use turret::bunker::{glob_match, is_pattern, Bunker, PeerAllow, SecretMeta, TargetQuota};
 use turret::bunker::TargetDef;
-use turret::invoke::{execute_invoke, InvokeError, InvokePayload};
+use turret::config::EngageSettings;
+use turret::client::{payload_from_json, AgentClient};
+use turret::daemon::Daemon;
+use turret::detach::{detach, Detached};
+use turret::history::History;
+use turret::invoke::{new_request_id, valid_request_id, DEFAULT_MAX_OUTPUT_BYTES};
+use turret::log::{LogFormat, LogTarget};
+use turret::once::OnceStore;
 use turret::rage;
+use turret::redact::Redactor;
+use turret::ratelimit::RateLimiter;
+use turret::replay::ReplayCache;
+use turret::secrets::{ProviderConfig, SecretSource};
+use turret::show::key_label;
+use turret::simulate::Verdict;
+use turret::sockperm::{self, parse_mode, SocketPerms};
+use turret::systemd::{self, SocketUnit};
+use turret::threshold::{shares_path, Seal, SharesFile};
+use turret::usage::UsageStore;
+use turret::vsock::VsockListener;
 
 #[derive(Parser, Debug)]
 #[command(name = "turret")]
 struct Cli {
     bunker_name: String,
+    /// More operator keys, for bunkers dug with `--threshold`.
+    #[arg(long, global = true, env = "TURRET_CO_OPERATORS", value_delimiter = ',')]
+    co_operator: Vec<PathBuf>,
     #[command(subcommand)]
     cmd: CommandGroup,
 }
@@ -26,8 +51,12 @@ enum CommandGroup {
     Dig {
         #[arg(long)]
         weak: bool,
+        /// Repeatable.
         #[arg(long)]
-        operator: Option<String>,
+        operator: Vec<String>,
+        /// Split the bunker key so that any k of the operators are needed to open it.
+        #[arg(long, value_name = "K", conflicts_with = "weak")]
+        threshold: Option<u8>,
         #[arg(long, default_value = "/run/secrets/homelab_ssh_key")]
         host_ssh_key: PathBuf,
     },
@@ -44,44 +73,203 @@ enum CommandGroup {
         cmd: OutCmd,
     },
 
-    /// Grant target permission to rookie.
+    /// Grant target (or role) permission to rookie, for good unless `--until` or `--ttl` is given.
     Allow {
         #[arg(long)]
         rookie: String,
+        #[arg(long, required_unless_present = "role")]
+        target: Option<String>,
+        #[arg(long, conflicts_with = "target")]
+        role: Option<String>,
+        /// The grant stops working at this unix time (seconds).
+        #[arg(long, value_name = "UNIX_SECS")]
+        until: Option<u64>,
+        /// The grant stops working this long from now: seconds, or a number with s, m, h or d.
+        #[arg(long, value_parser = parse_ttl, conflicts_with = "until")]
+        ttl: Option<Duration>,
+        /// The grant is spent by the first successful invoke it authorizes.
         #[arg(long)]
-        target: String,
+        once: bool,
         #[arg(long)]
         operator: PathBuf,
     },
 
-    /// Revoke target permission from rookie.
+    /// Revoke target (or role) permission from rookie.
     Deny {
         #[arg(long)]
         rookie: String,
-        #[arg(long)]
-        target: String,
+        #[arg(long, required_unless_present = "role")]
+        target: Option<String>,
+        #[arg(long, conflicts_with = "target")]
+        role: Option<String>,
         #[arg(long)]
         operator: PathBuf,
     },
 
     /// Start daemon and hold bunker in memory.
-    Engage {
+    Engage(Box<EngageArgs>),
+
+    /// Invoke daemon with rookie request.
+    Fire {
+        #[arg(long)]
+        rookie: String,
+        #[arg(long)]
+        params: Option<String>,
+        #[arg(long)]
+        params_file: Option<PathBuf>,
+        /// Correlation id for daemon logs, audit and history; generated when omitted.
+        #[arg(long)]
+        request_id: Option<String>,
+        /// Distributed-trace id to log and hand to the target; picked up from the environment inside targets.
+        #[arg(long, env = "TURRET_TRACE_ID")]
+        trace_id: Option<String>,
+        /// Sign the invoke with this OpenSSH ed25519 private key instead of sending `agent_secret`.
+        #[arg(long, env = "TURRET_ROOKIE_KEY")]
+        key: Option<PathBuf>,
+        /// Give up after this many seconds; the daemon refuses or kills the target past the deadline too.
+        #[arg(long)]
+        timeout: Option<u64>,
+        /// Resolve the request and print what would run, secrets masked, without running it.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Show invocation metrics from the running daemon.
+    Stats {
+        /// List the last N invocations instead.
+        #[arg(long)]
+        recent: Option<usize>,
+    },
+
+    /// Ask the running daemon to write a non-secret state dump for bug reports to ./<bunker_name>.dump.json (also on
+    /// SIGUSR1).
+    Dump,
+
+    /// Make the running daemon re-read the bunker file (also on SIGHUP).
+    Reload,
+
+    /// Check that the daemon is up and show its load.
+    Status,
+
+    /// List invokes of `require_approval` targets waiting on an operator.
+    Pending,
+
+    /// Let an invoke parked for approval run.
+    Approve {
+        request_id: String,
         #[arg(long)]
         operator: PathBuf,
-        #[arg(long, default_value = "/run/secrets/homelab_ssh_key")]
-        host_ssh_key: PathBuf,
     },
 
-    /// Invoke daemon with rookie request.
-    Fire {
+    /// Make the running daemon refuse every invoke until `thaw`, keeping its state.
+    Freeze {
+        #[arg(long)]
+        operator: PathBuf,
+    },
+
+    /// Let a frozen daemon take invokes again.
+    Thaw {
+        #[arg(long)]
+        operator: PathBuf,
+    },
+
+    /// Print a systemd unit for this bunker, with paths resolved from the current directory.
+    SystemdUnit {
+        #[arg(value_enum, default_value = "service")]
+        unit: UnitArg,
+        /// Service: the engage operator key.
+        #[arg(long)]
+        operator: Option<PathBuf>,
+        #[arg(long)]
+        host_ssh_key: Option<PathBuf>,
+        /// Service: engage with this config file.
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Service: take the sockets from the `fire-socket` and `admin-socket` units.
+        #[arg(long)]
+        socket_activated: bool,
+    },
+
+    /// Check the hash chain of an audit log and its rotated files.
+    VerifyAudit {
+        log: PathBuf,
+    },
+
+    /// Print operators, recruits, targets, permissions and secrets, with secret values masked.
+    Show {
+        #[arg(long)]
+        operator: PathBuf,
+        /// Print this `[secrets]` value in the clear (repeatable).
+        #[arg(long)]
+        reveal: Vec<String>,
+    },
+
+    /// Validate the bunker and warn about unused secrets, idle recruits and targets that cannot fire.
+    Check {
+        #[arg(long)]
+        operator: PathBuf,
+        /// Exit nonzero on warnings too.
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Walk an invoke through the daemon's checks against the bunker, without a daemon, and show which one refuses it.
+    Simulate {
         #[arg(long)]
         rookie: String,
         #[arg(long)]
+        target: String,
+        /// The fire payload; include `agent_secret` to check it too.
+        #[arg(long)]
         params: Option<String>,
         #[arg(long)]
         params_file: Option<PathBuf>,
+        /// Where the daemon records spent single-use grants [default: ./<bunker_name>.once.json].
+        #[arg(long, env = "TURRET_ONCE_FILE")]
+        once_file: Option<PathBuf>,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+
+    /// Compare this bunker with another bunker file: recruits, permissions, targets, secrets (names only).
+    Diff {
+        other: PathBuf,
+        #[arg(long)]
+        operator: PathBuf,
     },
 
+    /// Print the bunker as plaintext TOML, e.g. for a backup or, with --redact, for review in git.
+    Export {
+        #[arg(long)]
+        operator: PathBuf,
+        /// Mask secret values, recruit secrets and the alert webhook; the result cannot be imported.
+        #[arg(long)]
+        redact: bool,
+    },
+
+    /// Replace the bunker with plaintext TOML from a file (`-` for stdin), keeping the current operators.
+    Import {
+        file: PathBuf,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+
+    /// List who changed the bunker and how, oldest first.
+    History {
+        #[arg(long)]
+        operator: PathBuf,
+    },
+
+    /// Replace plaintext recruit secrets in the bunker with argon2id hashes.
+    HashRecruits {
+        #[arg(long)]
+        operator: PathBuf,
+    },
+
+    /// Pass one raw request from stdin to the daemon and its response to stdout (remote targets).
+    #[command(hide = true)]
+    Relay,
+
     /// Stop daemon.
     Disengage {
         #[arg(long)]
@@ -91,6 +279,204 @@ enum CommandGroup {
     },
 }
 
+/// Flags override `TURRET_*` env vars, which override the config file.
+#[derive(clap::Args, Debug)]
+struct EngageArgs {
+    /// TOML file with any of the settings below as top-level keys, plus `[profiles.<name>]`.
+    #[arg(long, env = "TURRET_CONFIG")]
+    config: Option<PathBuf>,
+    /// Overlay `[profiles.<name>]` from the config file.
+    #[arg(long, env = "TURRET_PROFILE", requires = "config")]
+    profile: Option<String>,
+    /// Fork into the background; returns once the sockets are listening.
+    #[arg(long)]
+    daemon: bool,
+    /// Where a `--daemon` engage writes stdout/stderr [default: ./<bunker_name>.log].
+    #[arg(long, requires = "daemon")]
+    daemon_log: Option<PathBuf>,
+    #[arg(long, env = "TURRET_OPERATOR")]
+    operator: Option<PathBuf>,
+    /// [default: /run/secrets/homelab_ssh_key]
+    #[arg(long, env = "TURRET_HOST_SSH_KEY")]
+    host_ssh_key: Option<PathBuf>,
+    /// Append a JSONL audit record for every fire request.
+    #[arg(long, env = "TURRET_AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+    /// Rotate the audit log once it would exceed this many bytes.
+    #[arg(long, env = "TURRET_AUDIT_MAX_BYTES")]
+    audit_max_bytes: Option<u64>,
+    /// Rotated audit files to keep [default: 5].
+    #[arg(long, env = "TURRET_AUDIT_KEEP")]
+    audit_keep: Option<usize>,
+    /// [default: always]
+    #[arg(long, value_enum, env = "TURRET_AUDIT_FSYNC")]
+    audit_fsync: Option<AuditFsync>,
+    /// Where daemon logs go; journald falls back to syslog, syslog to stderr [default: stderr].
+    #[arg(long, value_enum, env = "TURRET_LOG_TARGET")]
+    log_target: Option<LogTargetArg>,
+    /// Line format for stderr and syslog; journald is always structured [default: text].
+    #[arg(long, value_enum, env = "TURRET_LOG_FORMAT")]
+    log_format: Option<LogFormatArg>,
+    /// Recent invocations kept in memory for `stats --recent` [default: 256].
+    #[arg(long, env = "TURRET_HISTORY_SIZE")]
+    history_size: Option<usize>,
+    /// Also append invocation history to this sqlite file (needs the `sqlite` feature).
+    #[arg(long, env = "TURRET_HISTORY_DB")]
+    history_db: Option<PathBuf>,
+    /// Cumulative per-agent/per-target counters [default: ./<bunker_name>.usage.json].
+    #[arg(long, env = "TURRET_USAGE_FILE")]
+    usage_file: Option<PathBuf>,
+    /// Refuse to engage (or reload) a bunker that no operator has signed.
+    #[arg(long, env = "TURRET_REQUIRE_SIGNED_BUNKER")]
+    require_signed_bunker: bool,
+    /// Remember signed-invoke nonces here so a restart inside the replay window cannot reopen it.
+    #[arg(long, env = "TURRET_REPLAY_FILE")]
+    replay_file: Option<PathBuf>,
+    /// Which `allow --once` grants have been spent [default: ./<bunker_name>.once.json].
+    #[arg(long, env = "TURRET_ONCE_FILE")]
+    once_file: Option<PathBuf>,
+    /// Per-target quota counts from `[limits]`, so a restart does not reset them [default: ./<bunker_name>.quota.json].
+    #[arg(long, env = "TURRET_QUOTA_FILE")]
+    quota_file: Option<PathBuf>,
+    /// Also accept `POST /v1/fire/<target>` over HTTP here (needs the `http` feature).
+    #[arg(long, env = "TURRET_HTTP_LISTEN")]
+    http_listen: Option<std::net::SocketAddr>,
+    /// Serve --http-listen over TLS with --tls-cert/--tls-key; a pinned client certificate then stands in for the
+    /// bearer token.
+    #[arg(long, env = "TURRET_HTTP_TLS")]
+    http_tls: bool,
+    /// Also accept fire requests over AF_VSOCK on this port, from VMs on this host.
+    #[arg(long, env = "TURRET_VSOCK_PORT")]
+    vsock_port: Option<u32>,
+    /// Also accept fire requests over mutual TLS here, from recruits added with `in recruit --cert` (needs the `tls`
+    /// feature).
+    #[arg(long, env = "TURRET_TLS_LISTEN")]
+    tls_listen: Option<std::net::SocketAddr>,
+    /// PEM certificate chain the TLS listener and an --http-tls gateway present.
+    #[arg(long, env = "TURRET_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key for --tls-cert.
+    #[arg(long, env = "TURRET_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+    /// Seconds to let in-flight requests finish after SIGTERM/SIGINT [default: 10].
+    #[arg(long, env = "TURRET_SHUTDOWN_GRACE_SECS")]
+    shutdown_grace_secs: Option<u64>,
+    /// Seconds an invoke of a `require_approval` target waits for `approve` [default: 300].
+    #[arg(long, env = "TURRET_APPROVAL_TIMEOUT_SECS")]
+    approval_timeout_secs: Option<u64>,
+    /// Fire requests handled at once; more wait for a free slot [default: 16].
+    #[arg(long, env = "TURRET_MAX_CONCURRENT")]
+    max_concurrent: Option<usize>,
+    /// Largest fire payload accepted on the unix and vsock sockets [default: 1048576].
+    #[arg(long, env = "TURRET_MAX_REQUEST_BYTES")]
+    max_request_bytes: Option<usize>,
+    /// Stdout and stderr bytes kept per run for targets without their own `max_output_bytes` [default: 16777216].
+    #[arg(long, env = "TURRET_MAX_OUTPUT_BYTES")]
+    max_output_bytes: Option<usize>,
+    /// Delegated cgroup v2 directory; targets with memory or process `limits` each run in a transient cgroup in it.
+    #[arg(long, env = "TURRET_CGROUP_PARENT")]
+    cgroup_parent: Option<PathBuf>,
+    /// Octal mode of the fire socket [default: from the umask].
+    #[arg(long, value_parser = parse_mode, env = "TURRET_SOCKET_MODE")]
+    socket_mode: Option<u32>,
+    /// User (name or uid) to own the fire socket.
+    #[arg(long, env = "TURRET_SOCKET_OWNER")]
+    socket_owner: Option<String>,
+    /// Group (name or gid) of the fire socket, e.g. the agents' group with --socket-mode 0660.
+    #[arg(long, env = "TURRET_SOCKET_GROUP")]
+    socket_group: Option<String>,
+    /// Octal mode of the admin socket [default: 0600].
+    #[arg(long, value_parser = parse_mode, env = "TURRET_ADMIN_SOCKET_MODE")]
+    admin_socket_mode: Option<u32>,
+    #[arg(long, env = "TURRET_ADMIN_SOCKET_OWNER")]
+    admin_socket_owner: Option<String>,
+    #[arg(long, env = "TURRET_ADMIN_SOCKET_GROUP")]
+    admin_socket_group: Option<String>,
+}
+
+impl EngageArgs {
+    /// Layer flags/env over the config file.
+    fn settings(self, co_operators: Vec<PathBuf>) -> Result<EngageSettings, Box<dyn std::error::Error>> {
+        let file = match &self.config {
+            Some(path) => EngageSettings::load(path, self.profile.as_deref())?,
+            None => EngageSettings::default(),
+        };
+        let flags = EngageSettings {
+            operator: self.operator,
+            co_operators: (!co_operators.is_empty()).then_some(co_operators),
+            host_ssh_key: self.host_ssh_key,
+            audit_log: self.audit_log,
+            audit_max_bytes: self.audit_max_bytes,
+            audit_keep: self.audit_keep,
+            audit_fsync: self.audit_fsync.map(|f| match f {
+                AuditFsync::Always => FsyncPolicy::Always,
+                AuditFsync::Never => FsyncPolicy::Never,
+            }),
+            log_target: self.log_target.map(|t| match t {
+                LogTargetArg::Stderr => LogTarget::Stderr,
+                LogTargetArg::Journald => LogTarget::Journald,
+                LogTargetArg::Syslog => LogTarget::Syslog,
+            }),
+            log_format: self.log_format.map(|f| match f {
+                LogFormatArg::Text => LogFormat::Text,
+                LogFormatArg::Json => LogFormat::Json,
+            }),
+            history_size: self.history_size,
+            history_db: self.history_db,
+            usage_file: self.usage_file,
+            replay_file: self.replay_file,
+            once_file: self.once_file,
+            quota_file: self.quota_file,
+            require_signed_bunker: self.require_signed_bunker.then_some(true),
+            http_listen: self.http_listen,
+            http_tls: self.http_tls.then_some(true),
+            vsock_port: self.vsock_port,
+            tls_listen: self.tls_listen,
+            tls_cert: self.tls_cert,
+            tls_key: self.tls_key,
+            shutdown_grace_secs: self.shutdown_grace_secs,
+            approval_timeout_secs: self.approval_timeout_secs,
+            max_concurrent: self.max_concurrent,
+            max_request_bytes: self.max_request_bytes,
+            max_output_bytes: self.max_output_bytes,
+            cgroup_parent: self.cgroup_parent,
+            socket_mode: self.socket_mode,
+            socket_owner: self.socket_owner,
+            socket_group: self.socket_group,
+            admin_socket_mode: self.admin_socket_mode,
+            admin_socket_owner: self.admin_socket_owner,
+            admin_socket_group: self.admin_socket_group,
+        };
+        Ok(flags.or(file))
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum UnitArg {
+    Service,
+    FireSocket,
+    AdminSocket,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum AuditFsync {
+    Always,
+    Never,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogFormatArg {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogTargetArg {
+    Stderr,
+    Journald,
+    Syslog,
+}
+
 #[derive(Subcommand, Debug)]
 enum InCmd {
     Operator {
@@ -98,9 +484,20 @@ enum InCmd {
         #[arg(long)]
         operator: PathBuf,
     },
+    /// Add or replace a recruit, with a shared secret, an ed25519 public key, or a TLS client certificate.
     Recruit {
         ident: String,
-        secret: String,
+        #[arg(required_unless_present_any = ["pubkey", "cert"])]
+        secret: Option<String>,
+        /// OpenSSH ed25519 public key file; the recruit then signs with `fire --key`.
+        #[arg(long, conflicts_with = "secret")]
+        pubkey: Option<PathBuf>,
+        /// PEM client certificate; the recruit then fires over `engage --tls-listen` presenting it.
+        #[arg(long, conflicts_with_all = ["secret", "pubkey"])]
+        cert: Option<PathBuf>,
+        /// Store an argon2id hash of the secret instead of the secret itself.
+        #[arg(long, requires = "secret")]
+        hashed: bool,
         #[arg(long)]
         operator: PathBuf,
     },
@@ -108,12 +505,77 @@ enum InCmd {
         ident: String,
         #[arg(long)]
         from: PathBuf,
+        /// Resolve out_command to an absolute path now and refuse to run it if that file later changes.
+        #[arg(long)]
+        pin: bool,
         #[arg(long)]
         operator: PathBuf,
     },
     Secret {
         ident: String,
         value: String,
+        /// Refuse to render the secret after this unix time (seconds).
+        #[arg(long, value_name = "UNIX_SECS")]
+        expires_at: Option<u64>,
+        /// Refuse to render the secret this many seconds after it was set; kept across re-sets.
+        #[arg(long, value_name = "SECS")]
+        max_age: Option<u64>,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Set the alert hooks from the `[alerts]` table of a TOML file.
+    Alerts {
+        #[arg(long)]
+        from: PathBuf,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Merge `[secret_providers]` and `[secret_sources]` from a TOML file.
+    Sources {
+        #[arg(long)]
+        from: PathBuf,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Set a recruit's invoke rate limit.
+    Limit {
+        ident: String,
+        #[arg(long, required_unless_present = "target")]
+        per_minute: Option<u32>,
+        /// Invokes allowed back to back [default: --per-minute].
+        #[arg(long, requires = "per_minute")]
+        burst: Option<u32>,
+        /// Set a quota on this target instead of (or as well as) the overall rate.
+        #[arg(long, requires = "max_per_hour")]
+        target: Option<String>,
+        #[arg(long, requires = "target")]
+        max_per_hour: Option<u32>,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Define or replace a role: a set of targets `allow --role` grants together.
+    Role {
+        ident: String,
+        #[arg(long = "target", required = true)]
+        targets: Vec<String>,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Only let these local users fire as a recruit (checked with SO_PEERCRED on the fire socket).
+    Peers {
+        ident: String,
+        #[arg(long = "uid")]
+        uids: Vec<u32>,
+        /// Matches the peer's primary group.
+        #[arg(long = "gid")]
+        gids: Vec<u32>,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Set a variable every local target command starts with; an empty value leaves it unset, PATH included.
+    BaseEnv {
+        key: String,
+        value: String,
         #[arg(long)]
         operator: PathBuf,
     },
@@ -141,14 +603,43 @@ enum OutCmd {
         #[arg(long)]
         operator: PathBuf,
     },
-}
-
-#[derive(Serialize, Deserialize)]
-struct FireResponse {
-    ok: bool,
-    result_b64: Option<String>,
-    code: Option<String>,
-    message: Option<String>,
+    Alerts {
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Drop a secret source; its provider stays.
+    Source {
+        ident: String,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Drop a recruit's rate limit.
+    Limit {
+        ident: String,
+        /// Only drop the quota on this target.
+        #[arg(long)]
+        target: Option<String>,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Drop a role and every grant of it.
+    Role {
+        ident: String,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Let any local user fire as a recruit again.
+    Peers {
+        ident: String,
+        #[arg(long)]
+        operator: PathBuf,
+    },
+    /// Drop a base env variable; PATH goes back to the default.
+    BaseEnv {
+        key: String,
+        #[arg(long)]
+        operator: PathBuf,
+    },
 }
 
 fn main() {
@@ -163,14 +654,16 @@ fn real_main() -> Result<(), Box<dyn std::error::Error>> {
     let bunker_path = bunker_path(&cli.bunker_name);
     let sock_path = socket_path(&cli.bunker_name);
     let pid_path = pid_path(&cli.bunker_name);
+    let admin_path = admin_socket_path(&cli.bunker_name);
 
     match cli.cmd {
         CommandGroup::Dig {
             weak,
             operator,
+            threshold,
             host_ssh_key,
         } => {
-            if !weak && operator.is_none() {
+            if !weak && operator.is_empty() {
                 return Err("either --weak, --operator, or both are required".into());
             }
             let mut b = Bunker::new();
@@ -178,67 +671,216 @@ fn real_main() -> Result<(), Box<dyn std::error::Error>> {
             if weak {
                 ops.insert(ssh_public_key_from_private(&host_ssh_key)?);
             }
-            if let Some(op) = operator {
-                ops.insert(read_operator_pubkey(&op)?);
+            for op in &operator {
+                ops.insert(read_operator_pubkey(op)?);
             }
             b.operators = ops;
             b.validate()?;
-            write_bunker_encrypted(&bunker_path, &b)?;
+            let seal = threshold.map(Seal::generate).transpose()?;
+            write_bunker_encrypted(&bunker_path, &b, None, seal.as_ref(), "dig")?;
             eprintln!("turret: wrote bunker {}", bunker_path.display());
             Ok(())
         }
 
         CommandGroup::In { cmd } => match cmd {
             InCmd::Operator { ident, operator } => {
-                let mut b = open_with_identity(&bunker_path, &operator, "operator")?;
-                b.operators.insert(read_operator_pubkey(&ident)?);
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let key = read_operator_pubkey(&ident)?;
+                b.operators.insert(key.clone());
                 b.validate()?;
-                write_bunker_encrypted(&bunker_path, &b)?;
+                let action = format!("in operator {}", key_label(&key));
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
                 eprintln!("turret: operator added");
+                send_alert(
+                    &b,
+                    AlertEvent::OperatorAdded {
+                        bunker: cli.bunker_name.clone(),
+                        key,
+                    },
+                );
                 Ok(())
             }
             InCmd::Recruit {
                 ident,
                 secret,
+                pubkey,
+                cert,
+                hashed,
                 operator,
             } => {
-                let mut b = open_with_identity(&bunker_path, &operator, "operator")?;
-                b.agents.insert(ident, secret);
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("in recruit {ident}");
+                // Switching between a secret, a key and a certificate replaces the old credential.
+                b.agents.remove(&ident);
+                b.agent_keys.remove(&ident);
+                b.agent_certs.remove(&ident);
+                let read = |path: &Path| {
+                    std::fs::read_to_string(path)
+                        .map_err(|e| io::Error::new(e.kind(), format!("read {}: {e}", path.display())))
+                };
+                match (secret, pubkey, cert) {
+                    (_, Some(path), _) => {
+                        b.agent_keys.insert(ident, read(&path)?.trim().to_string());
+                    }
+                    (_, None, Some(path)) => {
+                        let pin = turret::pin::cert_sha256(&read(&path)?)
+                            .ok_or_else(|| format!("{}: no PEM certificate", path.display()))?;
+                        b.agent_certs.insert(ident, pin);
+                    }
+                    (Some(secret), None, None) => {
+                        let stored = if hashed { agent_secret::hash(&secret)? } else { secret };
+                        b.agents.insert(ident, stored);
+                    }
+                    (None, None, None) => return Err("recruit needs a secret, --pubkey or --cert".into()),
+                }
                 b.validate()?;
-                write_bunker_encrypted(&bunker_path, &b)?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
                 eprintln!("turret: recruit added");
                 Ok(())
             }
             InCmd::Target {
                 ident,
                 from,
+                pin,
                 operator,
             } => {
-                let mut b = open_with_identity(&bunker_path, &operator, "operator")?;
-                let def = read_target_from_file(&from, &ident)?;
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("in target {ident}");
+                let mut def = read_target_from_file(&from, &ident)?;
+                if pin {
+                    let path = b.base_env(&def).remove("PATH").unwrap_or_default();
+                    let t = &mut def.transform;
+                    let first = (&mut t.out_command, &mut t.out_command_sha256);
+                    let piped = t.pipe.iter_mut().map(|step| (&mut step.out_command, &mut step.out_command_sha256));
+                    for (command, sha) in std::iter::once(first).chain(piped) {
+                        let resolved =
+                            turret::pin::resolve(command, &path).map_err(|e| format!("pin out_command: {e}"))?;
+                        *sha = Some(turret::pin::sha256_file(&resolved)?);
+                        *command = resolved.to_string_lossy().into_owned();
+                        eprintln!("turret: pinned {command}");
+                    }
+                }
                 b.targets.insert(ident, def);
                 b.validate()?;
-                write_bunker_encrypted(&bunker_path, &b)?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
                 eprintln!("turret: target added");
                 Ok(())
             }
             InCmd::Secret {
                 ident,
                 value,
+                expires_at,
+                max_age,
                 operator,
             } => {
-                let mut b = open_with_identity(&bunker_path, &operator, "operator")?;
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("in secret {ident}");
+                // A new value starts a new max-age period; an absolute expiry belonged to the old value.
+                let old_max_age = b.secret_meta.get(&ident).and_then(|m| m.max_age_secs);
+                let meta = SecretMeta {
+                    set_at_ms: Some(turret::audit::now_ms()),
+                    expires_at_ms: expires_at.map(|secs| secs.saturating_mul(1000)),
+                    max_age_secs: max_age.or(old_max_age),
+                };
+                b.secret_meta.insert(ident.clone(), meta);
                 b.secrets.insert(ident, value);
                 b.validate()?;
-                write_bunker_encrypted(&bunker_path, &b)?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
                 eprintln!("turret: secret added");
                 Ok(())
             }
+            InCmd::Alerts { from, operator } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = "in alerts".to_string();
+                b.alerts = Some(read_alerts_file(&from)?);
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: alerts set");
+                Ok(())
+            }
+            InCmd::Limit {
+                ident,
+                per_minute,
+                burst,
+                target,
+                max_per_hour,
+                operator,
+            } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = match &target {
+                    Some(t) => format!("in limit {ident} {t}"),
+                    None => format!("in limit {ident}"),
+                };
+                // Setting the rate keeps the quotas and vice versa.
+                let limit = b.limits.entry(ident).or_default();
+                if per_minute.is_some() {
+                    limit.per_minute = per_minute;
+                    limit.burst = burst;
+                }
+                if let (Some(target), Some(max_per_hour)) = (target, max_per_hour) {
+                    limit.targets.insert(target, TargetQuota { max_per_hour });
+                }
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: limit set");
+                Ok(())
+            }
+            InCmd::Role {
+                ident,
+                targets,
+                operator,
+            } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("in role {ident}");
+                b.roles.insert(ident, targets.into_iter().collect());
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: role set");
+                Ok(())
+            }
+            InCmd::Peers {
+                ident,
+                uids,
+                gids,
+                operator,
+            } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("in peers {ident}");
+                let allow = PeerAllow {
+                    uids: uids.into_iter().collect(),
+                    gids: gids.into_iter().collect(),
+                };
+                b.peers.insert(ident, allow);
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: peers set");
+                Ok(())
+            }
+            InCmd::BaseEnv { key, value, operator } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("in base-env {key}");
+                b.base_env.insert(key, value);
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: base env set");
+                Ok(())
+            }
+            InCmd::Sources { from, operator } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = "in sources".to_string();
+                let sf = read_sources_file(&from)?;
+                b.secret_providers.extend(sf.secret_providers);
+                b.secret_sources.extend(sf.secret_sources);
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: secret sources added");
+                Ok(())
+            }
         },
 
         CommandGroup::Out { cmd } => match cmd {
             OutCmd::Operator { ident, operator } => {
-                let mut b = open_with_identity(&bunker_path, &operator, "operator")?;
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
                 let key = read_operator_pubkey(&ident)?;
                 if !b.operators.remove(&key) {
                     return Err("operator not present".into());
@@ -247,49 +889,174 @@ fn real_main() -> Result<(), Box<dyn std::error::Error>> {
                     return Err("cannot remove final operator".into());
                 }
                 b.validate()?;
-                write_bunker_encrypted(&bunker_path, &b)?;
+                let action = format!("out operator {}", key_label(&key));
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
                 eprintln!("turret: operator removed");
+                send_alert(
+                    &b,
+                    AlertEvent::OperatorRemoved {
+                        bunker: cli.bunker_name.clone(),
+                        key,
+                    },
+                );
                 Ok(())
             }
             OutCmd::Recruit { ident, operator } => {
-                let mut b = open_with_identity(&bunker_path, &operator, "operator")?;
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("out recruit {ident}");
                 b.agents.remove(&ident);
+                b.agent_keys.remove(&ident);
+                b.agent_certs.remove(&ident);
                 b.permissions.remove(&ident);
+                b.limits.remove(&ident);
+                b.peers.remove(&ident);
+                b.prune_grant_terms();
                 b.validate()?;
-                write_bunker_encrypted(&bunker_path, &b)?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
                 eprintln!("turret: recruit removed");
                 Ok(())
             }
             OutCmd::Target { ident, operator } => {
-                let mut b = open_with_identity(&bunker_path, &operator, "operator")?;
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("out target {ident}");
                 b.targets.remove(&ident);
-                for allowed in b.permissions.values_mut() {
+                for allowed in b.permissions.values_mut().chain(b.roles.values_mut()) {
                     allowed.remove(&ident);
                 }
+                for limit in b.limits.values_mut() {
+                    limit.targets.remove(&ident);
+                }
+                b.limits.retain(|_, l| l.per_minute.is_some() || !l.targets.is_empty());
+                // Patterns that only matched this target would now match nothing.
+                let names: Vec<String> = b.targets.keys().cloned().collect();
+                for allowed in b.permissions.values_mut() {
+                    allowed.retain(|g| !is_pattern(g) || names.iter().any(|t| glob_match(g, t)));
+                }
+                b.prune_grant_terms();
                 b.validate()?;
-                write_bunker_encrypted(&bunker_path, &b)?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
                 eprintln!("turret: target removed");
                 Ok(())
             }
             OutCmd::Secret { ident, operator } => {
-                let mut b = open_with_identity(&bunker_path, &operator, "operator")?;
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("out secret {ident}");
                 b.secrets.remove(&ident);
+                b.secret_meta.remove(&ident);
                 b.validate()?;
-                write_bunker_encrypted(&bunker_path, &b)?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
                 eprintln!("turret: secret removed");
                 Ok(())
             }
+            OutCmd::Alerts { operator } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = "out alerts".to_string();
+                b.alerts = None;
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: alerts removed");
+                Ok(())
+            }
+            OutCmd::Limit { ident, target, operator } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = match &target {
+                    Some(t) => format!("out limit {ident} {t}"),
+                    None => format!("out limit {ident}"),
+                };
+                match target {
+                    Some(t) => {
+                        if let Some(limit) = b.limits.get_mut(&ident) {
+                            limit.targets.remove(&t);
+                        }
+                        b.limits.retain(|_, l| l.per_minute.is_some() || !l.targets.is_empty());
+                    }
+                    None => {
+                        b.limits.remove(&ident);
+                    }
+                }
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: limit removed");
+                Ok(())
+            }
+            OutCmd::Role { ident, operator } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("out role {ident}");
+                b.roles.remove(&ident);
+                for allowed in b.permissions.values_mut() {
+                    allowed.remove(&ident);
+                }
+                b.prune_grant_terms();
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: role removed");
+                Ok(())
+            }
+            OutCmd::Peers { ident, operator } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("out peers {ident}");
+                b.peers.remove(&ident);
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: peers removed");
+                Ok(())
+            }
+            OutCmd::BaseEnv { key, operator } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("out base-env {key}");
+                b.base_env.remove(&key);
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: base env removed");
+                Ok(())
+            }
+            OutCmd::Source { ident, operator } => {
+                let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                let action = format!("out source {ident}");
+                b.secret_sources.remove(&ident);
+                b.validate()?;
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+                eprintln!("turret: secret source removed");
+                Ok(())
+            }
         },
 
         CommandGroup::Allow {
             rookie,
             target,
+            role,
+            until,
+            ttl,
+            once,
             operator,
         } => {
-            let mut b = open_with_identity(&bunker_path, &operator, "operator")?;
-            b.permissions.entry(rookie).or_default().insert(target);
+            let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+            let grant = grant_name(&b, target, role)?;
+            let expires_ms = match (until, ttl) {
+                (Some(secs), _) => Some(secs.saturating_mul(1000)),
+                (None, Some(ttl)) => Some(turret::audit::now_ms() + ttl.as_millis() as u64),
+                (None, None) => None,
+            };
+            let mut action = format!("allow {rookie} {grant}");
+            if let Some(at) = expires_ms {
+                action.push_str(&format!(" until {}", at / 1000));
+            }
+            if once {
+                action.push_str(" once");
+            }
+            b.permissions.entry(rookie.clone()).or_default().insert(grant.clone());
+            // Re-allowing replaces any earlier terms, including with none; a fresh --once is unspent.
+            b.grant_expiry.entry(rookie.clone()).or_default().remove(&grant);
+            b.single_use.entry(rookie.clone()).or_default().remove(&grant);
+            if let Some(at) = expires_ms {
+                b.grant_expiry.entry(rookie.clone()).or_default().insert(grant.clone(), at);
+            }
+            if once {
+                b.single_use.entry(rookie).or_default().insert(grant, turret::audit::now_ms());
+            }
+            b.prune_grant_terms();
             b.validate()?;
-            write_bunker_encrypted(&bunker_path, &b)?;
+            write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
             eprintln!("turret: permission granted");
             Ok(())
         }
@@ -297,30 +1064,470 @@ fn real_main() -> Result<(), Box<dyn std::error::Error>> {
         CommandGroup::Deny {
             rookie,
             target,
+            role,
             operator,
         } => {
-            let mut b = open_with_identity(&bunker_path, &operator, "operator")?;
+            let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+            let grant = grant_name(&b, target, role)?;
+            let action = format!("deny {rookie} {grant}");
             if let Some(allowed) = b.permissions.get_mut(&rookie) {
-                allowed.remove(&target);
+                allowed.remove(&grant);
+            }
+            b.prune_grant_terms();
+            if b.may_fire(&rookie, &grant, turret::audit::now_ms()) {
+                eprintln!("turret: {rookie} still reaches {grant} through a role");
             }
             b.validate()?;
-            write_bunker_encrypted(&bunker_path, &b)?;
+            write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
             eprintln!("turret: permission revoked");
             Ok(())
         }
 
-        CommandGroup::Engage {
+        CommandGroup::Engage(args) => {
+            let daemon_log = args.daemon.then(|| {
+                args.daemon_log
+                    .clone()
+                    .unwrap_or_else(|| log_path(&cli.bunker_name))
+            });
+            let settings = args.settings(cli.co_operator)?;
+            let Some(operator) = settings.operator else {
+                return Err("engage needs --operator (or operator in the config file)".into());
+            };
+            let host_ssh_key = settings
+                .host_ssh_key
+                .unwrap_or_else(|| PathBuf::from("/run/secrets/homelab_ssh_key"));
+            let activated = systemd::listeners()?;
+            let sockets_exist = activated.is_none() && (sock_path.exists() || admin_path.exists());
+            if sockets_exist || pid_path.exists() {
+                return Err("daemon already running (socket/pid exists)".into());
+            }
+            if settings.max_concurrent == Some(0) {
+                return Err("max_concurrent must be at least 1".into());
+            }
+            if settings.max_output_bytes == Some(0) {
+                return Err("max_output_bytes must be at least 1".into());
+            }
+            let wanted = settings.log_target.unwrap_or(LogTarget::Stderr);
+            let actual = turret::log::init(wanted, settings.log_format.unwrap_or_default())?;
+            if actual != wanted {
+                warn!("log target {wanted:?} unavailable, using {actual:?}");
+            }
+            let require_signed = settings.require_signed_bunker.unwrap_or(false);
+            let co_operators = settings.co_operators.unwrap_or_default();
+            let mut bunker = fire_up(&bunker_path, &host_ssh_key, Some(&operator), &co_operators, require_signed)?;
+            turret::secrets::resolve(&mut bunker)?;
+            turret::redact::install(Redactor::for_bunker(&bunker));
+            if !bunker.agents.is_empty() {
+                warn!(
+                    recruits = bunker.agents.len(),
+                    "shared-secret recruits are deprecated; re-recruit them with --pubkey"
+                );
+            }
+            let plaintext = bunker.agents.values().filter(|s| !agent_secret::is_hashed(s)).count();
+            if plaintext > 0 {
+                warn!(recruits = plaintext, "recruit secrets stored in plaintext; run `hash-recruits`");
+            }
+            let audit = match settings.audit_log {
+                Some(path) => {
+                    let mut cfg = AuditConfig::new(path);
+                    cfg.max_bytes = settings.audit_max_bytes;
+                    cfg.keep = settings.audit_keep.unwrap_or(cfg.keep);
+                    cfg.fsync = settings.audit_fsync.unwrap_or_default();
+                    Some(AuditLog::open(cfg)?)
+                }
+                None => None,
+            };
+            let history = open_history(settings.history_size.unwrap_or(256), settings.history_db.as_deref())?;
+            let usage = UsageStore::open(
+                settings
+                    .usage_file
+                    .unwrap_or_else(|| usage_path(&cli.bunker_name)),
+            )?;
+            let replay = match &settings.replay_file {
+                Some(path) => ReplayCache::open(path, turret::audit::now_ms())
+                    .map_err(|e| format!("replay file {}: {e}", path.display()))?,
+                None => ReplayCache::new(),
+            };
+            let once = OnceStore::open(settings.once_file.unwrap_or_else(|| once_path(&cli.bunker_name)))?;
+            let quota_file = settings.quota_file.unwrap_or_else(|| quota_path(&cli.bunker_name));
+            let limiter =
+                RateLimiter::open(&quota_file).map_err(|e| format!("quota file {}: {e}", quota_file.display()))?;
+            let reloader = {
+                let (bunker_path, host_ssh_key, operator) =
+                    (bunker_path.clone(), host_ssh_key.clone(), operator.clone());
+                Box::new(move || {
+                    let mut bunker =
+                        fire_up(&bunker_path, &host_ssh_key, Some(&operator), &co_operators, require_signed)
+                        .map_err(|e| e.to_string())?;
+                    turret::secrets::resolve(&mut bunker).map_err(|e| e.to_string())?;
+                    Ok(bunker)
+                })
+            };
+            // Fork before any daemon threads exist.
+            let readiness = match daemon_log {
+                Some(log) => match detach(&log)? {
+                    Detached::Parent(pid) => {
+                        eprintln!("turret: engaged in the background (pid {pid}, log {})", log.display());
+                        return Ok(());
+                    }
+                    Detached::Child(r) => Some(r),
+                },
+                None => None,
+            };
+            let daemon = Daemon::new(bunker)
+                .with_reloader(reloader)
+                .with_audit(audit)
+                .with_history(history)
+                .with_usage(usage)
+                .with_replay(replay)
+                .with_once(once)
+                .with_limiter(limiter)
+                .with_dump_path(dump_path(&cli.bunker_name))
+                .with_shutdown_grace(Duration::from_secs(settings.shutdown_grace_secs.unwrap_or(10)))
+                .with_approval_timeout(
+                    settings
+                        .approval_timeout_secs
+                        .map_or(turret::approval::DEFAULT_TIMEOUT, Duration::from_secs),
+                )
+                .with_max_concurrent(settings.max_concurrent.unwrap_or(16))
+                .with_max_request_bytes(settings.max_request_bytes.unwrap_or(1 << 20))
+                .with_max_output_bytes(settings.max_output_bytes.unwrap_or(DEFAULT_MAX_OUTPUT_BYTES))
+                .with_cgroup_parent(settings.cgroup_parent);
+            let daemon = Arc::new(daemon);
+            turret::daemon::dump_on_sigusr1(Arc::clone(&daemon))?;
+            turret::daemon::reload_on_sighup(Arc::clone(&daemon))?;
+            if let Some(addr) = settings.http_listen {
+                let tls = (settings.http_tls == Some(true))
+                    .then_some((settings.tls_cert.as_deref(), settings.tls_key.as_deref()));
+                start_http(Arc::clone(&daemon), addr, tls)?;
+            }
+            if let Some(port) = settings.vsock_port {
+                let listener = VsockListener::bind(port).map_err(|e| format!("--vsock-port {port}: {e}"))?;
+                info!(port, "vsock listening");
+                turret::vsock::spawn(Arc::clone(&daemon), listener);
+            }
+            if let Some(addr) = settings.tls_listen {
+                start_tls(Arc::clone(&daemon), addr, settings.tls_cert.as_deref(), settings.tls_key.as_deref())?;
+            }
+
+            // Activated sockets belong to systemd: it set their modes and keeps the files.
+            let keep_sockets = activated.is_some();
+            let (fire, admin) = match activated {
+                Some(a) => (a.fire, a.admin),
+                None => {
+                    let fire_perms = SocketPerms {
+                        mode: settings.socket_mode,
+                        owner: settings.socket_owner.clone(),
+                        group: settings.socket_group.clone(),
+                    };
+                    let admin_perms = SocketPerms {
+                        mode: Some(settings.admin_socket_mode.unwrap_or(0o600)),
+                        owner: settings.admin_socket_owner.clone(),
+                        group: settings.admin_socket_group.clone(),
+                    };
+                    let fire = sockperm::bind(&sock_path, &fire_perms)
+                        .map_err(|e| format!("bind {}: {e}", sock_path.display()))?;
+                    let admin = sockperm::bind(&admin_path, &admin_perms)
+                        .map_err(|e| format!("bind {}: {e}", admin_path.display()))?;
+                    (fire, admin)
+                }
+            };
+            write_pid_file(&pid_path)?;
+            turret::daemon::shutdown_on_signal(Arc::clone(&daemon), sock_path.clone(), keep_sockets)?;
+            info!(socket = %sock_path.display(), admin = %admin_path.display(), activated = keep_sockets, "engaged");
+            if let Some(r) = readiness {
+                r.ready();
+            }
+            systemd::notify("READY=1");
+            let res = turret::daemon::serve(daemon, fire, admin);
+            info!("disengaged");
+            if !keep_sockets {
+                let _ = std::fs::remove_file(&sock_path);
+                let _ = std::fs::remove_file(&admin_path);
+            }
+            let _ = std::fs::remove_file(&pid_path);
+            Ok(res?)
+        }
+
+        CommandGroup::Dump => {
+            let resp = admin_call(&admin_path, &AdminRequest::Dump, None)?;
+            eprintln!("turret: {}", resp.message.unwrap_or_else(|| "dump written".to_string()));
+            Ok(())
+        }
+
+        CommandGroup::Reload => {
+            let resp = admin_call(&admin_path, &AdminRequest::Reload, None)?;
+            eprintln!("turret: {}", resp.message.unwrap_or_else(|| "bunker reloaded".to_string()));
+            Ok(())
+        }
+
+        CommandGroup::Status => {
+            let resp = admin_call(&admin_path, &AdminRequest::Status, Some(Duration::from_secs(5)))?;
+            let s = resp.status.ok_or("daemon sent no status")?;
+            println!("pid\t{}", s.pid);
+            println!("uptime\t{}s", s.uptime_ms / 1000);
+            println!("targets\t{}", s.targets);
+            println!("agents\t{}", s.agents);
+            println!("in_flight\t{}", s.in_flight);
+            println!("replay_cache\t{}", s.replay_cache);
+            println!("awaiting_approval\t{}", s.awaiting_approval);
+            if s.frozen {
+                println!("state\tfrozen");
+            }
+            if s.shutting_down {
+                println!("state\tshutting down");
+            }
+            Ok(())
+        }
+
+        CommandGroup::Pending => {
+            let resp = admin_call(&admin_path, &AdminRequest::Pending, Some(Duration::from_secs(5)))?;
+            let now = turret::audit::now_ms();
+            for p in resp.pending.unwrap_or_default() {
+                let waited = now.saturating_sub(p.since_ms) / 1000;
+                println!("{}\t{}\t{}\t{waited}s", p.request_id, p.agent, p.target);
+            }
+            Ok(())
+        }
+
+        CommandGroup::Approve { request_id, operator } => {
+            let proof = operator_proof(&admin_path, &operator, "approve", &request_id)?;
+            let req = AdminRequest::Approve { request_id, proof };
+            let resp = admin_call(&admin_path, &req, Some(Duration::from_secs(5)))?;
+            eprintln!("turret: {}", resp.message.unwrap_or_else(|| "approved".to_string()));
+            Ok(())
+        }
+
+        CommandGroup::Freeze { operator } => {
+            let proof = operator_proof(&admin_path, &operator, "freeze", "")?;
+            let resp = admin_call(&admin_path, &AdminRequest::Freeze { proof }, Some(Duration::from_secs(5)))?;
+            eprintln!("turret: {}", resp.message.unwrap_or_else(|| "frozen".to_string()));
+            Ok(())
+        }
+
+        CommandGroup::Thaw { operator } => {
+            let proof = operator_proof(&admin_path, &operator, "thaw", "")?;
+            let resp = admin_call(&admin_path, &AdminRequest::Thaw { proof }, Some(Duration::from_secs(5)))?;
+            eprintln!("turret: {}", resp.message.unwrap_or_else(|| "thawed".to_string()));
+            Ok(())
+        }
+
+        CommandGroup::SystemdUnit {
+            unit,
             operator,
             host_ssh_key,
+            config,
+            socket_activated,
         } => {
-            if sock_path.exists() || pid_path.exists() {
-                return Err("daemon already running (socket/pid exists)".into());
+            let workdir = std::env::current_dir()?;
+            let name = &cli.bunker_name;
+            let text = match unit {
+                UnitArg::FireSocket => systemd::socket_unit(name, &workdir, SocketUnit::Fire),
+                UnitArg::AdminSocket => systemd::socket_unit(name, &workdir, SocketUnit::Admin),
+                UnitArg::Service => {
+                    let Some(operator) = operator else {
+                        return Err("the service unit needs --operator".into());
+                    };
+                    let abs = |p: PathBuf| std::path::absolute(p).map(|p| p.to_string_lossy().into_owned());
+                    let mut argv = vec![
+                        std::env::current_exe()?.to_string_lossy().into_owned(),
+                        name.clone(),
+                        "engage".to_string(),
+                        "--operator".to_string(),
+                        abs(operator)?,
+                    ];
+                    if let Some(key) = host_ssh_key {
+                        argv.extend(["--host-ssh-key".to_string(), abs(key)?]);
+                    }
+                    if let Some(config) = config {
+                        argv.extend(["--config".to_string(), abs(config)?]);
+                    }
+                    systemd::service_unit(name, &workdir, &argv, socket_activated)
+                }
+            };
+            print!("{text}");
+            Ok(())
+        }
+
+        CommandGroup::VerifyAudit { log } => {
+            let files = turret::audit::log_files(&log);
+            if files.is_empty() {
+                return Err(format!("{}: no audit log", log.display()).into());
+            }
+            let n = turret::audit::verify_chain(&files)?;
+            eprintln!("turret: audit chain intact ({n} records in {} file(s))", files.len());
+            Ok(())
+        }
+
+        CommandGroup::Show { operator, reveal } => {
+            let (b, _) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+            let reveal: BTreeSet<String> = reveal.into_iter().collect();
+            if let Some(unknown) = reveal.iter().find(|name| !b.secrets.contains_key(*name)) {
+                return Err(format!("--reveal {unknown}: no such secret").into());
+            }
+            print!("{}", turret::show::render(&b, &reveal, turret::audit::now_ms()));
+            Ok(())
+        }
+
+        CommandGroup::Check { operator, strict } => {
+            // Decoding validates.
+            let (b, _) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+            let warnings = b.lint(turret::audit::now_ms());
+            for w in &warnings {
+                println!("warning: {w}");
+            }
+            eprintln!("turret: bunker valid, {} warning(s)", warnings.len());
+            if strict && !warnings.is_empty() {
+                return Err("warnings with --strict".into());
+            }
+            Ok(())
+        }
+
+        CommandGroup::Simulate {
+            rookie,
+            target,
+            params,
+            params_file,
+            once_file,
+            operator,
+        } => {
+            let (b, _) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+            let raw = match (params, params_file) {
+                (None, None) => b"{}".to_vec(),
+                (params, params_file) => read_fire_params(params, params_file)?,
+            };
+            let mut fields: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_slice(&raw).map_err(|e| format!("params: {e}"))?;
+            fields.insert("target".to_string(), target.into());
+            let payload = payload_from_json(&rookie, &serde_json::to_vec(&fields)?)?;
+            let once = OnceStore::open(once_file.unwrap_or_else(|| once_path(&cli.bunker_name)))?;
+            let mut denied = None;
+            for step in turret::simulate::simulate(&b, &once, &payload, turret::audit::now_ms()) {
+                let verdict = match step.verdict {
+                    Verdict::Pass => "pass",
+                    Verdict::Note => "note",
+                    Verdict::Deny(code) => {
+                        denied = Some((step.rule, code));
+                        "DENY"
+                    }
+                };
+                println!("{verdict}\t{}\t{}", step.rule, step.detail);
+            }
+            match denied {
+                Some((rule, code)) => Err(format!("{code}: refused by the {rule} rule").into()),
+                None => {
+                    eprintln!("turret: no rule in the bunker refuses it");
+                    Ok(())
+                }
+            }
+        }
+
+        CommandGroup::Diff { other, operator } => {
+            let (a, _) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+            let (b, _) = open_with_identity(&other, &operator, &cli.co_operator, "operator")?;
+            for line in turret::show::diff(&a, &b) {
+                println!("{line}");
+            }
+            Ok(())
+        }
+
+        CommandGroup::Export { operator, redact } => {
+            let (b, _) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+            let b = if redact { turret::show::redacted(&b) } else { b };
+            std::io::stdout().write_all(&b.encode()?)?;
+            Ok(())
+        }
+
+        CommandGroup::Import { file, operator } => {
+            let pt = if file.as_os_str() == "-" {
+                let mut buf = Vec::new();
+                std::io::stdin().read_to_end(&mut buf)?;
+                buf
+            } else {
+                std::fs::read(&file).map_err(|e| io::Error::new(e.kind(), format!("read {}: {e}", file.display())))?
+            };
+            let mut b = Bunker::decode(&pt)?;
+            if turret::show::is_redacted(&b) {
+                return Err(format!("{} is a redacted export", file.display()).into());
+            }
+            // Without a bunker to replace (restoring a backup), the file's own operators are used.
+            let seal = if bunker_path.exists() {
+                let (current, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+                b.operators = current.operators;
+                b.history = current.history;
+                seal
+            } else {
+                None
+            };
+            b.validate()?;
+            let action = format!("import {}", file.display());
+            write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+            eprintln!("turret: bunker imported");
+            Ok(())
+        }
+
+        CommandGroup::History { operator } => {
+            let (b, _) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+            for e in &b.history {
+                println!("{}\t{}\t{}", e.ts_ms, e.operator.as_deref().unwrap_or("-"), e.action);
+            }
+            Ok(())
+        }
+
+        CommandGroup::HashRecruits { operator } => {
+            let (mut b, seal) = open_with_identity(&bunker_path, &operator, &cli.co_operator, "operator")?;
+            let mut hashed = 0;
+            for stored in b.agents.values_mut() {
+                if !agent_secret::is_hashed(stored) {
+                    *stored = agent_secret::hash(stored)?;
+                    hashed += 1;
+                }
+            }
+            if hashed > 0 {
+                b.validate()?;
+                let action = format!("hash-recruits ({hashed})");
+                write_bunker_encrypted(&bunker_path, &b, Some(&operator), seal.as_ref(), &action)?;
+            }
+            eprintln!("turret: {hashed} recruit secret(s) hashed");
+            Ok(())
+        }
+
+        CommandGroup::Stats { recent } => {
+            let req = match recent {
+                Some(limit) => AdminRequest::Recent { limit },
+                None => AdminRequest::Stats,
+            };
+            let resp = admin_call(&admin_path, &req, None)?;
+            if let Some(entries) = resp.recent {
+                for e in entries {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}\t{}ms\t{}",
+                        e.ts_ms,
+                        e.request_id.as_deref().unwrap_or("-"),
+                        e.agent.as_deref().unwrap_or("-"),
+                        e.target.as_deref().unwrap_or("-"),
+                        e.outcome,
+                        e.duration_ms,
+                        e.result_hash.as_deref().unwrap_or("-"),
+                    );
+                }
+            }
+            if let Some(stats) = resp.stats {
+                for (target, m) in stats {
+                    let mean = m.exec_ms.sum.checked_div(m.exec_ms.count).unwrap_or(0);
+                    let outcomes: Vec<String> =
+                        m.outcomes.iter().map(|(k, v)| format!("{k}={v}")).collect();
+                    println!("{target}\tcalls={}\tmean_exec={mean}ms\t{}", m.exec_ms.count, outcomes.join(" "));
+                }
+            }
+            if let Some(usage) = resp.usage {
+                let kinds = [("agent", &usage.agents), ("target", &usage.targets)];
+                for (kind, counters) in kinds {
+                    for (id, c) in counters {
+                        println!("total {kind} {id}\tcalls={}\tok={}\tsince_ms={}", c.calls, c.ok, usage.since_ms);
+                    }
+                }
             }
-            let bunker = fire_up(&bunker_path, &host_ssh_key, Some(&operator))?;
-            std::fs::write(&pid_path, std::process::id().to_string())?;
-            run_daemon(&sock_path, bunker)?;
-            let _ = std::fs::remove_file(&sock_path);
-            let _ = std::fs::remove_file(&pid_path);
             Ok(())
         }
 
@@ -328,54 +1535,97 @@ fn real_main() -> Result<(), Box<dyn std::error::Error>> {
             rookie,
             params,
             params_file,
+            request_id,
+            trace_id,
+            key,
+            timeout,
+            dry_run,
         } => {
             let raw = read_fire_params(params, params_file)?;
-            let mut v: serde_json::Value =
-                serde_json::from_slice(&raw).map_err(|e| format!("invalid fire payload json: {e}"))?;
-            let obj = v
-                .as_object_mut()
-                .ok_or("invalid fire payload json: expected object")?;
-            obj.insert("agent_id".to_string(), serde_json::Value::String(rookie));
-            let payload: InvokePayload = serde_json::from_value(v)
-                .map_err(|e| format!("invalid fire payload json: {e}"))?;
-
-            let mut stream = UnixStream::connect(&sock_path)
-                .map_err(|e| format!("connect {}: {e}", sock_path.display()))?;
-            let req = serde_json::to_vec(&payload)?;
-            stream.write_all(&req)?;
-            stream.shutdown(std::net::Shutdown::Write)?;
-            let mut resp = Vec::new();
-            stream.read_to_end(&mut resp)?;
-            let parsed: FireResponse = serde_json::from_slice(&resp)
-                .map_err(|e| format!("invalid daemon response: {e}"))?;
-            if parsed.ok {
-                if let Some(b64) = parsed.result_b64 {
-                    let out = base64::engine::general_purpose::STANDARD.decode(b64)?;
-                    std::io::stdout().write_all(&out)?;
-                    return Ok(());
+            let mut payload = payload_from_json(&rookie, &raw)?;
+            payload.dry_run |= dry_run;
+            let request_id = request_id
+                .or(payload.request_id.take())
+                .unwrap_or_else(new_request_id);
+            if !valid_request_id(&request_id) {
+                return Err("request id must be 1-64 chars of [A-Za-z0-9-_.:]".into());
+            }
+            payload.request_id = Some(request_id.clone());
+            if let Some(id) = trace_id.or(payload.trace_id.take()) {
+                if !valid_request_id(&id) {
+                    return Err("trace id must be 1-64 chars of [A-Za-z0-9-_.:]".into());
+                }
+                payload.trace_id = Some(id);
+            }
+            let timeout = timeout.map(Duration::from_secs);
+            if let Some(t) = timeout {
+                payload.deadline_ms = Some(turret::audit::now_ms() + t.as_millis() as u64);
+            }
+            if let Some(path) = key {
+                payload.sign(&read_rookie_key(&path)?)?;
+            }
+            // A little slack so the daemon's deadline_exceeded normally arrives first.
+            let res = AgentClient::new(&sock_path)
+                .with_timeout(timeout.map(|t| t + Duration::from_secs(2)))
+                .fire_result(&payload)
+                .map_err(|e| format!("{e} (request_id={request_id})"))?;
+            std::io::stdout().write_all(&res.stdout)?;
+            std::io::stderr().write_all(&res.stderr)?;
+            if res.truncated {
+                eprintln!("turret: output truncated at max_output_bytes (request_id={request_id})");
+            }
+            // allow_failure targets hand back their nonzero exit; pass it on.
+            match res.exit_code {
+                Some(code) if code != 0 => {
+                    std::io::stdout().flush()?;
+                    std::process::exit(code)
                 }
-                return Ok(());
+                _ => Ok(()),
             }
-            let code = parsed.code.unwrap_or_else(|| "error".to_string());
-            let msg = parsed.message.unwrap_or_else(|| "request failed".to_string());
-            return Err(format!("{code}: {msg}").into());
+        }
+
+        CommandGroup::Relay => {
+            let mut req = Vec::new();
+            std::io::stdin().read_to_end(&mut req)?;
+            let resp = AgentClient::new(&sock_path).fire_raw(&req)?;
+            std::io::stdout().write_all(&resp)?;
+            Ok(())
         }
 
         CommandGroup::Disengage {
             operator,
             host_ssh_key,
         } => {
-            let _ = fire_up(&bunker_path, &host_ssh_key, Some(&operator))?;
+            let _ = fire_up(&bunker_path, &host_ssh_key, Some(&operator), &cli.co_operator, false)?;
             let pid_txt = std::fs::read_to_string(&pid_path)
                 .map_err(|e| io::Error::new(e.kind(), format!("read {}: {e}", pid_path.display())))?;
             let pid: i32 = pid_txt.trim().parse().map_err(|_| "invalid pid file")?;
-            let status = std::process::Command::new("kill")
-                .arg(pid.to_string())
-                .status()?;
-            if !status.success() {
-                return Err("failed to stop daemon".into());
+            // Only signal the pid if the daemon on our admin socket confirms it.
+            let status = match admin_call(&admin_path, &AdminRequest::Status, Some(Duration::from_secs(5))) {
+                Ok(resp) => resp.status.ok_or("daemon sent no status")?,
+                Err(e) if !process_alive(pid) => {
+                    let _ = std::fs::remove_file(&sock_path);
+                    let _ = std::fs::remove_file(&admin_path);
+                    let _ = std::fs::remove_file(&pid_path);
+                    eprintln!("turret: daemon was not running ({e}); removed stale files");
+                    return Ok(());
+                }
+                Err(e) => return Err(format!("pid {pid} is alive but no daemon answers on the admin socket: {e}").into()),
+            };
+            if u32::try_from(pid).ok() != Some(status.pid) {
+                return Err(format!("pid file says {pid} but the daemon reports {}; not signalling", status.pid).into());
+            }
+            if unsafe { libc::kill(pid, libc::SIGTERM) } == -1 {
+                return Err(format!("signal {pid}: {}", io::Error::last_os_error()).into());
+            }
+            // The daemon drains and removes its own files; the pid file goes last.
+            let deadline = Instant::now() + DISENGAGE_WAIT;
+            while pid_path.exists() && process_alive(pid) {
+                if Instant::now() >= deadline {
+                    return Err(format!("daemon {pid} still running after {}s", DISENGAGE_WAIT.as_secs()).into());
+                }
+                std::thread::sleep(Duration::from_millis(100));
             }
-            let _ = std::fs::remove_file(&sock_path);
             let _ = std::fs::remove_file(&pid_path);
             eprintln!("turret: disengaged");
             Ok(())
@@ -383,51 +1633,26 @@ fn real_main() -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
-fn run_daemon(sock_path: &Path, bunker: Bunker) -> Result<(), Box<dyn std::error::Error>> {
-    let listener = UnixListener::bind(sock_path)?;
-    eprintln!("turret: engaged on {}", sock_path.display());
-    loop {
-        let (mut stream, _) = listener.accept()?;
-        let mut req = Vec::new();
-        stream.read_to_end(&mut req)?;
-        let resp = match serde_json::from_slice::<InvokePayload>(&req) {
-            Ok(p) => match execute_invoke(&bunker, p) {
-                Ok(bytes) => FireResponse {
-                    ok: true,
-                    result_b64: Some(base64::engine::general_purpose::STANDARD.encode(bytes)),
-                    code: None,
-                    message: None,
-                },
-                Err(e) => map_invoke_error(e),
-            },
-            Err(e) => FireResponse {
-                ok: false,
-                result_b64: None,
-                code: Some("bad_request".to_string()),
-                message: Some(format!("invalid json: {e}")),
-            },
-        };
-        let payload = serde_json::to_vec(&resp)?;
-        stream.write_all(&payload)?;
+fn read_rookie_key(path: &Path) -> Result<ssh_key::PrivateKey, Box<dyn std::error::Error>> {
+    let key = ssh_key::PrivateKey::read_openssh_file(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    if key.is_encrypted() {
+        return Err(format!("{}: passphrase-protected keys are not supported", path.display()).into());
     }
+    if key.algorithm() != ssh_key::Algorithm::Ed25519 {
+        return Err(format!("{}: not an ed25519 key", path.display()).into());
+    }
+    Ok(key)
 }
 
-fn map_invoke_error(e: InvokeError) -> FireResponse {
-    let (code, msg) = match e {
-        InvokeError::Unauthenticated => ("unauthenticated", "bad agent credentials".to_string()),
-        InvokeError::Denied => ("denied", "denied".to_string()),
-        InvokeError::UnknownTarget => ("unknown_target", "unknown target".to_string()),
-        InvokeError::BadRequest(m) => ("bad_request", m),
-        InvokeError::Internal(m) => ("internal", m),
-    };
-    FireResponse {
-        ok: false,
-        result_b64: None,
-        code: Some(code.to_string()),
-        message: Some(msg),
-    }
+/// `kill(pid, 0)`: EPERM still means the process exists.
+fn process_alive(pid: i32) -> bool {
+    let ret = unsafe { libc::kill(pid, 0) };
+    ret == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
 }
 
+/// Longer than the default shutdown grace.
+const DISENGAGE_WAIT: Duration = Duration::from_secs(15);
+
 fn read_fire_params(
     params: Option<String>,
     params_file: Option<PathBuf>,
@@ -453,61 +1678,244 @@ fn pid_path(name: &str) -> PathBuf {
     PathBuf::from(format!("{name}.pid"))
 }
 
-fn fire_up(path: &Path, host_ssh_key: &Path, operator_ssh_key: Option<&Path>) -> Result<Bunker, Box<dyn std::error::Error>> {
-    eprintln!("turret: opening bunker {}", path.display());
+fn admin_socket_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("{name}.admin.sock"))
+}
+
+fn log_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("{name}.log"))
+}
+
+/// Write-then-rename, so readers never see a partial pid.
+fn write_pid_file(path: &Path) -> io::Result<()> {
+    let tmp = path.with_extension("pid.tmp");
+    std::fs::write(&tmp, std::process::id().to_string())?;
+    std::fs::rename(&tmp, path)
+}
+
+fn dump_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("{name}.dump.json"))
+}
+
+fn usage_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("{name}.usage.json"))
+}
+
+fn once_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("{name}.once.json"))
+}
+
+fn quota_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("{name}.quota.json"))
+}
+
+/// `tls` holds the --tls-cert and --tls-key settings when the gateway is to speak TLS.
+fn start_http(
+    daemon: Arc<Daemon>,
+    addr: std::net::SocketAddr,
+    tls: Option<(Option<&Path>, Option<&Path>)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "http")]
+    {
+        let config = match tls {
+            Some((Some(cert), Some(key))) => Some(turret::tls::server_config(cert, key, false)?),
+            Some(_) => return Err("--http-tls needs --tls-cert and --tls-key".into()),
+            None => None,
+        };
+        let listener = std::net::TcpListener::bind(addr)?;
+        info!(addr = %addr, tls = config.is_some(), "http gateway listening");
+        turret::http::spawn(daemon, listener, config);
+        Ok(())
+    }
+    #[cfg(not(feature = "http"))]
+    {
+        let _ = (daemon, tls);
+        Err(format!("--http-listen {addr}: turret was built without the http feature").into())
+    }
+}
+
+fn start_tls(
+    daemon: Arc<Daemon>,
+    addr: std::net::SocketAddr,
+    cert: Option<&Path>,
+    key: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some(cert), Some(key)) = (cert, key) else {
+        return Err(format!("--tls-listen {addr} needs --tls-cert and --tls-key").into());
+    };
+    #[cfg(feature = "tls")]
+    {
+        let config = turret::tls::server_config(cert, key, true)?;
+        let listener = std::net::TcpListener::bind(addr)?;
+        info!(addr = %addr, "tls listening");
+        turret::tls::spawn(daemon, listener, config);
+        Ok(())
+    }
+    #[cfg(not(feature = "tls"))]
+    {
+        let _ = (daemon, cert, key);
+        Err(format!("--tls-listen {addr}: turret was built without the tls feature").into())
+    }
+}
+
+fn open_history(size: usize, db: Option<&Path>) -> Result<History, Box<dyn std::error::Error>> {
+    let history = History::new(size);
+    let Some(db) = db else {
+        return Ok(history);
+    };
+    #[cfg(feature = "sqlite")]
+    {
+        Ok(history.with_sqlite(db)?)
+    }
+    #[cfg(not(feature = "sqlite"))]
+    {
+        Err(format!("--history-db {}: turret was built without the sqlite feature", db.display()).into())
+    }
+}
+
+/// Decrypt and check the operator signature. A signature by the host key alone proves nothing,
+/// so it counts as unsigned; `require_signed` makes unsigned bunkers an error instead of a warning.
+fn fire_up(
+    path: &Path,
+    host_ssh_key: &Path,
+    operator_ssh_key: Option<&Path>,
+    co_operators: &[PathBuf],
+    require_signed: bool,
+) -> Result<Bunker, Box<dyn std::error::Error>> {
+    info!(path = %path.display(), "opening bunker");
     let enc = std::fs::read(path)
         .map_err(|e| io::Error::new(e.kind(), format!("failed to read bunker {}: {e}", path.display())))?;
     if !rage::looks_like_age_file(&enc) {
         return Err("bunker is not an age file".into());
     }
 
-    eprintln!(
-        "turret: attempting host-key decrypt via rage (identity={})",
-        host_ssh_key.display()
-    );
-    let host_pt = rage::decrypt_with_identity_file(&enc, host_ssh_key);
-    let pt = match host_pt {
-        Ok(p) => p,
-        Err(e) => {
-            eprintln!("turret: host-key decrypt failed: {e}");
-            let Some(op) = operator_ssh_key else {
-                return Err("this bunker requires an operator; could not decrypt with host key".into());
-            };
-            eprintln!(
-                "turret: attempting operator decrypt via rage (identity={})",
-                op.display()
-            );
-            rage::decrypt_with_identity_file(&enc, op)
-                .map_err(|_| "this operator is not permitted to open this bunker")?
+    let identities: Vec<&Path> = operator_ssh_key
+        .into_iter()
+        .chain(co_operators.iter().map(PathBuf::as_path))
+        .collect();
+    let pt = if let Some(seal) = unseal(path, &identities)? {
+        seal.decrypt(path, &enc)?
+    } else {
+        info!(identity = %host_ssh_key.display(), "attempting host-key decrypt via rage");
+        match rage::decrypt_with_identity_file(&enc, host_ssh_key) {
+            Ok(p) => p,
+            Err(e) => {
+                info!("host-key decrypt failed: {e}");
+                let Some(op) = operator_ssh_key else {
+                    return Err("this bunker requires an operator; could not decrypt with host key".into());
+                };
+                info!(identity = %op.display(), "attempting operator decrypt via rage");
+                rage::decrypt_with_identity_file(&enc, op)
+                    .map_err(|_| "this operator is not permitted to open this bunker")?
+            }
+        }
+    };
+    let bunker = Bunker::decode(&pt)?;
+    let host = ssh_key::PrivateKey::read_openssh_file(host_ssh_key).ok();
+    match bunker.verify_signature()? {
+        Some(signer) if host.is_none_or(|h| h.public_key().key_data() != signer.key_data()) => {
+            info!(signer = %signer.fingerprint(Default::default()), "bunker signature verified");
         }
+        signer => {
+            let state = if signer.is_some() { "signed only by the host key" } else { "unsigned" };
+            if require_signed {
+                return Err(format!("bunker is {state}; re-save it with an operator's ed25519 key").into());
+            }
+            warn!("bunker is {state}; any in/out/allow/deny with an operator's ed25519 key signs it");
+        }
+    }
+    Ok(bunker)
+}
+
+/// For a `dig --threshold` bunker, rebuild the data key from the operators' shares.
+/// `None` when the bunker has no shares file.
+fn unseal(path: &Path, identities: &[&Path]) -> Result<Option<Seal>, Box<dyn std::error::Error>> {
+    let Some(shares) = SharesFile::load(&shares_path(path))? else {
+        return Ok(None);
     };
-    Ok(Bunker::decode(&pt)?)
+    info!(threshold = shares.threshold, identities = identities.len(), "reconstructing the bunker key from shares");
+    Ok(Some(Seal::unseal(&shares, identities)?))
 }
 
-fn open_with_identity(path: &Path, identity: &Path, label: &str) -> Result<Bunker, Box<dyn std::error::Error>> {
+fn open_with_identity(
+    path: &Path,
+    identity: &Path,
+    co_operators: &[PathBuf],
+    label: &str,
+) -> Result<(Bunker, Option<Seal>), Box<dyn std::error::Error>> {
     eprintln!("turret: opening bunker {}", path.display());
     let enc = std::fs::read(path)
         .map_err(|e| io::Error::new(e.kind(), format!("failed to read bunker {}: {e}", path.display())))?;
     if !rage::looks_like_age_file(&enc) {
         return Err("bunker is not an age file".into());
     }
-    eprintln!(
-        "turret: attempting {label} decrypt via rage (identity={})",
-        identity.display()
-    );
-    let pt = rage::decrypt_with_identity_file(&enc, identity).map_err(|e| format!("decrypt failed: {e}"))?;
-    Ok(Bunker::decode(&pt)?)
+    let identities: Vec<&Path> = std::iter::once(identity)
+        .chain(co_operators.iter().map(PathBuf::as_path))
+        .collect();
+    let (pt, seal) = match unseal(path, &identities)? {
+        Some(seal) => (seal.decrypt(path, &enc)?, Some(seal)),
+        None => {
+            eprintln!(
+                "turret: attempting {label} decrypt via rage (identity={})",
+                identity.display()
+            );
+            let pt = rage::decrypt_with_identity_file(&enc, identity).map_err(|e| format!("decrypt failed: {e}"))?;
+            (pt, None)
+        }
+    };
+    let b = Bunker::decode(&pt)?;
+    // Refuse to re-sign contents someone else rewrote.
+    b.verify_signature()?;
+    Ok((b, seal))
 }
 
-fn write_bunker_encrypted(path: &Path, bunker: &Bunker) -> Result<(), Box<dyn std::error::Error>> {
+/// Record `action` in the edit history and encrypt to the operators, signed by `signer` when it is an
+/// operator's unencrypted ed25519 key. Anything else (an age identity, no signer) writes the bunker unsigned.
+/// With a `seal`, encrypt to its data key instead and re-split the key across the current operators.
+fn write_bunker_encrypted(
+    path: &Path,
+    bunker: &Bunker,
+    signer: Option<&Path>,
+    seal: Option<&Seal>,
+    action: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut bunker = bunker.clone();
+    bunker.signature = None;
+    let key = signer.map(read_rookie_key);
+    let fingerprint = match &key {
+        Some(Ok(key)) => Some(key.public_key().fingerprint(Default::default()).to_string()),
+        _ => None,
+    };
+    bunker.record_edit(turret::audit::now_ms(), fingerprint, action.to_string());
+    match key {
+        Some(Ok(key)) => bunker.sign(&key)?,
+        Some(Err(e)) => eprintln!("turret: writing the bunker unsigned: {e}"),
+        None => {}
+    }
     let pt = bunker.encode()?;
     let dir = path.parent().unwrap_or_else(|| Path::new("."));
-    let tmp_recips = dir.join(".turret.recipients.tmp");
     let mut recips = String::new();
-    for op in &bunker.operators {
-        recips.push_str(op);
-        recips.push('\n');
+    match seal {
+        Some(seal) => {
+            let shares = seal.split(&bunker.operators, dir).map_err(|e| {
+                format!("{e} (a bunker dug with --threshold {} needs that many operators)", seal.threshold)
+            })?;
+            recips.push_str(&shares.recipient);
+            recips.push('\n');
+            shares.save(&shares_path(path))?;
+        }
+        None => {
+            for op in &bunker.operators {
+                recips.push_str(op);
+                recips.push('\n');
+            }
+            match std::fs::remove_file(shares_path(path)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
     }
+    let tmp_recips = dir.join(".turret.recipients.tmp");
     std::fs::write(&tmp_recips, recips)?;
     let tmp_out = dir.join(".turret.bunker.tmp");
     rage::encrypt_to_recipients_file(&pt, &tmp_recips, &tmp_out).map_err(|e| format!("encrypt: {e}"))?;
@@ -516,6 +1924,50 @@ fn write_bunker_encrypted(path: &Path, bunker: &Bunker) -> Result<(), Box<dyn st
     Ok(())
 }
 
+/// Sign `action` on `subject` with the `operator` key over a fresh daemon nonce; the daemon checks the key is an
+/// operator of its bunker.
+fn operator_proof(
+    admin_path: &Path,
+    operator: &Path,
+    action: &str,
+    subject: &str,
+) -> Result<OperatorProof, Box<dyn std::error::Error>> {
+    let key = read_rookie_key(operator)?;
+    let resp = admin_call(admin_path, &AdminRequest::Challenge, Some(Duration::from_secs(5)))?;
+    let nonce = resp.nonce.ok_or("daemon sent no challenge")?;
+    Ok(OperatorProof::sign(&key, action, subject, &nonce)?)
+}
+
+/// The permission entry `allow`/`deny` act on: `--target` must name a target, `--role` a role.
+fn grant_name(b: &Bunker, target: Option<String>, role: Option<String>) -> Result<String, Box<dyn std::error::Error>> {
+    match (target, role) {
+        (Some(t), _) if b.roles.contains_key(&t) => Err(format!("'{t}' is a role; use --role").into()),
+        (Some(t), _) => Ok(t),
+        (None, Some(r)) if b.roles.contains_key(&r) => Ok(r),
+        (None, Some(r)) => Err(format!("no role '{r}'").into()),
+        (None, None) => Err("--target or --role is required".into()),
+    }
+}
+
+/// `90`, `90s`, `30m`, `2h` or `7d`.
+fn parse_ttl(s: &str) -> Result<Duration, String> {
+    let (digits, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c),
+        _ => (s, 's'),
+    };
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        _ => return Err(format!("'{s}' has an unknown unit; use s, m, h or d")),
+    };
+    match digits.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(Duration::from_secs(n.saturating_mul(scale))),
+        _ => Err(format!("'{s}' is not a duration like 30m or 7d")),
+    }
+}
+
 fn read_operator_pubkey(s: &str) -> Result<String, Box<dyn std::error::Error>> {
     if s.starts_with("ssh-") || s.starts_with("age1") {
         return Ok(s.to_string());
@@ -554,6 +2006,45 @@ fn read_targets_file(path: &Path) -> Result<std::collections::BTreeMap<String, T
     Ok(tf.targets)
 }
 
+#[derive(serde::Deserialize)]
+struct AlertsFile {
+    alerts: AlertConfig,
+}
+
+fn read_alerts_file(path: &Path) -> Result<AlertConfig, Box<dyn std::error::Error>> {
+    let txt = std::fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("read {}: {e}", path.display())))?;
+    let af: AlertsFile = toml::from_str(&txt)?;
+    Ok(af.alerts)
+}
+
+#[derive(serde::Deserialize)]
+struct SourcesFile {
+    #[serde(default)]
+    secret_providers: std::collections::BTreeMap<String, ProviderConfig>,
+    #[serde(default)]
+    secret_sources: std::collections::BTreeMap<String, SecretSource>,
+}
+
+fn read_sources_file(path: &Path) -> Result<SourcesFile, Box<dyn std::error::Error>> {
+    let txt = std::fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("read {}: {e}", path.display())))?;
+    let sf: SourcesFile = toml::from_str(&txt)?;
+    if sf.secret_providers.is_empty() && sf.secret_sources.is_empty() {
+        return Err("sources file has no [secret_providers] or [secret_sources] entries".into());
+    }
+    Ok(sf)
+}
+
+/// The bunker change is already written, so a failed hook is reported but not fatal.
+fn send_alert(b: &Bunker, event: AlertEvent) {
+    if let Some(alerts) = &b.alerts {
+        if let Err(e) = alerts.send(&event) {
+            eprintln!("turret: alert: {e}");
+        }
+    }
+}
+
 fn read_target_from_file(path: &Path, ident: &str) -> Result<TargetDef, Box<dyn std::error::Error>> {
     let targets = read_targets_file(path)?;
     targets
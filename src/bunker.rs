@@ -2,6 +2,10 @@ use std::collections::{BTreeMap, BTreeSet};
 
 use serde::{Deserialize, Serialize};
 
+use crate::alert::AlertConfig;
+use crate::peercred::PeerCred;
+use crate::secrets::{ProviderConfig, SecretRef, SecretSource};
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TargetShape {
     #[serde(default)]
@@ -12,32 +16,734 @@ pub struct TargetShape {
     pub require: BTreeSet<String>,
     #[serde(default)]
     pub argv_placeholders: Option<usize>,
+    /// Regexes the argv item at a position (`"0"`, `"1"`, ...) must match in full.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub argv_pattern: BTreeMap<String, String>,
+    /// The only values the argv item at a position may take.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub argv_choices: BTreeMap<String, BTreeSet<String>>,
+    /// Regexes the param at a position must match in full.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params_pattern: BTreeMap<String, String>,
+    /// When set, the only env keys a payload may send; entries may use `*` wildcards.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub env_allow_keys: BTreeSet<String>,
+    /// Env keys a payload may never send, wildcards too; checked before `env_allow_keys`.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub env_forbid_keys: BTreeSet<String>,
+    /// Regexes the value of an env key must match in full.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env_pattern: BTreeMap<String, String>,
+    /// Longest stdin a payload may send, before transforms.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_stdin_bytes: Option<usize>,
+    /// JSON Schema, as JSON text, that stdin must parse and validate against (`jsonschema` feature).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin_schema: Option<String>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+impl TargetShape {
+    pub fn constrains_argv_values(&self) -> bool {
+        !self.argv_pattern.is_empty() || !self.argv_choices.is_empty()
+    }
+
+    pub fn constrains_env(&self) -> bool {
+        !self.env_allow_keys.is_empty() || !self.env_forbid_keys.is_empty() || !self.env_pattern.is_empty()
+    }
+
+    /// `argv_pattern` by position, anchored to match whole items.
+    pub fn argv_regexes(&self) -> Result<Vec<(usize, regex::Regex)>, String> {
+        self.argv_pattern
+            .iter()
+            .map(|(key, pattern)| Ok((argv_position(key)?, full_match(&format!("argv_pattern {key}"), pattern)?)))
+            .collect()
+    }
+
+    /// `params_pattern` by position, anchored to match whole values.
+    pub fn params_regexes(&self) -> Result<Vec<(usize, regex::Regex)>, String> {
+        self.params_pattern
+            .iter()
+            .map(|(key, pattern)| Ok((argv_position(key)?, full_match(&format!("params_pattern {key}"), pattern)?)))
+            .collect()
+    }
+
+    /// `argv_choices` by position.
+    pub fn argv_choice_sets(&self) -> Result<Vec<(usize, &BTreeSet<String>)>, String> {
+        self.argv_choices.iter().map(|(key, choices)| Ok((argv_position(key)?, choices))).collect()
+    }
+
+    /// `env_pattern` by key, anchored to match whole values.
+    pub fn env_regexes(&self) -> Result<Vec<(&String, regex::Regex)>, String> {
+        self.env_pattern
+            .iter()
+            .map(|(key, pattern)| Ok((key, full_match(&format!("env_pattern {key}"), pattern)?)))
+            .collect()
+    }
+
+    /// Whether a payload may send env key `key`.
+    pub fn env_key_allowed(&self, key: &str) -> bool {
+        let listed = |keys: &BTreeSet<String>| keys.iter().any(|k| k == key || (is_pattern(k) && glob_match(k, key)));
+        !listed(&self.env_forbid_keys) && (self.env_allow_keys.is_empty() || listed(&self.env_allow_keys))
+    }
+}
+
+/// `pattern` anchored at both ends; parsed bare first so errors point into the pattern as written.
+fn full_match(what: &str, pattern: &str) -> Result<regex::Regex, String> {
+    regex::Regex::new(pattern)
+        .and_then(|_| regex::Regex::new(&format!("^(?:{pattern})$")))
+        .map_err(|e| format!("{what}: {e}"))
+}
+
+fn argv_position(key: &str) -> Result<usize, String> {
+    key.parse().map_err(|_| format!("position '{key}' is not a number"))
+}
+
+/// The position of a `{0}`-style token, which takes a param rather than naming a secret.
+pub fn param_index(token: &str) -> Option<usize> {
+    token.bytes().all(|b| b.is_ascii_digit()).then(|| token.parse().ok()).flatten()
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TargetTransform {
+    /// Required unless the target `kind` does not run a command.
+    #[serde(default)]
     pub out_command: String,
+    /// SHA-256 the absolute `out_command` must still have at each run; set by `in target --pin`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_command_sha256: Option<String>,
     #[serde(default)]
     pub out_argv_replace: BTreeMap<String, String>,
     #[serde(default)]
     pub out_env: BTreeMap<String, String>,
     #[serde(default)]
     pub out_stdin_replace: BTreeMap<String, String>,
+    /// Hand a secret to the command on an inherited memfd instead of its argv or env.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_secret_fd: Option<SecretFd>,
+    /// Directory the command starts in, instead of the daemon's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// User name or numeric uid to run the command as; a name also sets its primary group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_as_user: Option<String>,
+    /// Group name or numeric gid to run the command as.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_as_group: Option<String>,
+    /// Overrides the bunker's `[base_env]` for this target, key by key.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub base_env: BTreeMap<String, String>,
+    /// Commands the output is piped through in order; the last one's stdout is the result.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pipe: Vec<PipeStep>,
+}
+
+impl TargetTransform {
+    /// `out_command` and each pipe step's, with the hash it is pinned to.
+    pub fn commands(&self) -> impl Iterator<Item = (&String, Option<&String>)> {
+        let piped = self.pipe.iter().map(|step| (&step.out_command, step.out_command_sha256.as_ref()));
+        std::iter::once((&self.out_command, self.out_command_sha256.as_ref())).chain(piped)
+    }
+
+    /// Settings that only a command spawned by the daemon itself can honour.
+    fn local_only(&self) -> Option<&'static str> {
+        if self.out_command_sha256.is_some() {
+            Some("out_command_sha256")
+        } else if self.out_secret_fd.is_some() {
+            Some("out_secret_fd")
+        } else if self.cwd.is_some() {
+            Some("cwd")
+        } else if self.run_as_user.is_some() {
+            Some("run_as_user")
+        } else if self.run_as_group.is_some() {
+            Some("run_as_group")
+        } else if !self.base_env.is_empty() {
+            Some("base_env")
+        } else if !self.pipe.is_empty() {
+            Some("pipe")
+        } else {
+            None
+        }
+    }
+}
+
+/// `out_secret_fd = { name = "FOO" }`: the command reads secret `FOO` from the fd named in `env`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecretFd {
+    pub name: String,
+    #[serde(default = "SecretFd::default_env")]
+    pub env: String,
+}
+
+impl SecretFd {
+    pub const DEFAULT_ENV: &'static str = "TURRET_SECRET_FD";
+
+    fn default_env() -> String {
+        Self::DEFAULT_ENV.to_string()
+    }
+}
+
+/// `[[targets.<name>.transform.pipe]]`: a command reading the previous one's stdout, with the same base env, cwd,
+/// user, limits and sandbox. Payloads cannot reach it except through `{0}`-style params in `out_argv`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PipeStep {
+    pub out_command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_command_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub out_argv: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub out_env: BTreeMap<String, String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TargetDef {
     pub shape: TargetShape,
+    #[serde(default)]
     pub transform: TargetTransform,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<TargetKind>,
+    /// Return nonzero exits to the rookie with their exit code instead of failing the request.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_failure: bool,
+    /// Park each invoke until an operator runs `turret <bunker> approve <request-id>`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_approval: bool,
+    /// Run only once a second, different recruit sends the same request within this many seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub two_person_window_secs: Option<u64>,
+    /// Bytes of stdout and of stderr kept from a run; the rest is dropped and the result marked truncated.
+    /// Defaults to the daemon's `--max-output-bytes`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<usize>,
+    /// Runs of this target at once; further invokes wait in line (`max_queued`) or fail as `busy`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent: Option<usize>,
+    /// Invokes that may wait for a run, first come first served; default 0, so the rest fail at once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_queued: Option<usize>,
+    /// How long a queued invoke waits before failing as `busy`; default 60.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<TargetLimits>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<TargetSandbox>,
+}
+
+/// `[targets.<name>.limits]`: rlimits set in the child before exec, so a runaway target only takes itself down.
+/// With engage `--cgroup-parent`, memory and processes are also enforced on a transient cgroup.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetLimits {
+    /// `RLIMIT_CPU`: seconds of CPU time before SIGKILL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_secs: Option<u64>,
+    /// `RLIMIT_AS`: bytes of address space; in a cgroup, `memory.max` for the whole tree.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_bytes: Option<u64>,
+    /// `RLIMIT_NOFILE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_files: Option<u64>,
+    /// `RLIMIT_NPROC`, which counts every process of the user it runs as; in a cgroup, `pids.max` for the tree.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processes: Option<u64>,
+}
+
+/// `[targets.<name>.sandbox]`: Landlock paths and a seccomp filter for the command (`sandbox` feature).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetSandbox {
+    /// Trees the command may read and execute from, its own binary and libraries included.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub read: Vec<String>,
+    /// Trees it may also write to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub write: Vec<String>,
+    /// The only syscalls it may make, besides `execve`, `exit` and `exit_group`; others fail with EPERM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub syscalls: Option<Vec<String>>,
+    /// Syscalls that fail with EPERM, the rest allowed. Exclusive with `syscalls`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_syscalls: Vec<String>,
+}
+
+impl TargetSandbox {
+    /// Whether Landlock applies; with no paths the filesystem is left alone.
+    pub fn restricts_paths(&self) -> bool {
+        !self.read.is_empty() || !self.write.is_empty()
+    }
+
+    pub fn filters_syscalls(&self) -> bool {
+        self.syscalls.is_some() || !self.deny_syscalls.is_empty()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(p) = self.read.iter().chain(&self.write).find(|p| !p.starts_with('/')) {
+            return Err(format!("sandbox path '{p}' is not absolute"));
+        }
+        if self.syscalls.is_some() && !self.deny_syscalls.is_empty() {
+            return Err("sandbox sets both syscalls and deny_syscalls".to_string());
+        }
+        if !self.restricts_paths() && !self.filters_syscalls() {
+            return Err("sandbox restricts neither paths nor syscalls".to_string());
+        }
+        #[cfg(feature = "sandbox")]
+        crate::sandbox::compile_filter(self)?;
+        Ok(())
+    }
+}
+
+impl TargetLimits {
+    fn validate(&self) -> Result<(), &'static str> {
+        let set = [self.cpu_secs, self.memory_bytes, self.open_files, self.processes];
+        if set.contains(&Some(0)) {
+            return Err("target limits must be > 0");
+        }
+        Ok(())
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+impl TargetDef {
+    /// How many params the `{0}`, `{1}`, ... placeholders in `out_argv_replace`/`out_stdin_replace` take.
+    pub fn params_taken(&self) -> usize {
+        let mut tokens = BTreeSet::new();
+        let t = &self.transform;
+        let piped = t.pipe.iter().flat_map(|step| &step.out_argv);
+        let kinds = self.kind.iter().flat_map(TargetKind::param_templates);
+        for v in t.out_argv_replace.values().chain(t.out_stdin_replace.values()).chain(piped).chain(kinds) {
+            collect_refs_from_string(v, &mut tokens);
+        }
+        tokens.iter().filter_map(|t| param_index(t)).max().map_or(0, |i| i + 1)
+    }
+}
+
+/// Built-in target behaviour. Without a `kind` the target execs `out_command` on the daemon host.
+/// Values may use `{SECRET}` tokens.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TargetKind {
+    /// Create or update a Kubernetes Secret with `kubectl apply`.
+    K8sSecret {
+        namespace: String,
+        name: String,
+        /// Secret key -> value template.
+        data: BTreeMap<String, String>,
+        #[serde(default)]
+        kubeconfig: Option<String>,
+        #[serde(default)]
+        context: Option<String>,
+    },
+    /// Write one file per entry into `dir`, the layout docker/compose secrets expect.
+    SecretFiles {
+        dir: String,
+        /// File name -> value template.
+        files: BTreeMap<String, String>,
+        #[serde(default = "default_secret_file_mode")]
+        mode: u32,
+    },
+    /// Run `out_command` inside a running container via `<runtime> exec`.
+    Container {
+        container: String,
+        #[serde(default)]
+        runtime: ContainerRuntime,
+        #[serde(default)]
+        user: Option<String>,
+        #[serde(default)]
+        workdir: Option<String>,
+    },
+    /// Forward the shaped payload to a target on another turret over ssh, as rookie `rookie`.
+    /// The remote bunker applies its own shape and transform.
+    Remote {
+        /// ssh destination, `[user@]host`.
+        ssh: String,
+        #[serde(default)]
+        port: Option<u16>,
+        #[serde(default)]
+        identity: Option<String>,
+        /// Remote bunker name and the directory its socket lives in.
+        bunker: String,
+        dir: String,
+        #[serde(default = "default_remote_turret")]
+        turret: String,
+        rookie: String,
+        /// Template for the remote rookie's secret, e.g. `{HUB_SECRET}`.
+        secret: String,
+        target: String,
+    },
+    /// Run a WASI (preview1) module in-process; the payload's argv/env/stdin arrive as JSON on stdin.
+    /// Needs the `wasm` feature.
+    Wasm {
+        /// Absolute path to the `.wasm` file.
+        module: String,
+        #[serde(default = "default_wasm_fuel")]
+        fuel: u64,
+        #[serde(default = "default_wasm_max_memory")]
+        max_memory: usize,
+        /// Module environment; values are templates.
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+    },
+    /// Return the contents of a file, read in-process.
+    FileRead {
+        /// Absolute path; may use `{SECRET}` and `{0}`-style param tokens.
+        path: String,
+        /// Larger files fail the invoke rather than come back cut short.
+        #[serde(default = "default_file_max_bytes")]
+        max_bytes: u64,
+    },
+    /// Write the payload's stdin to a file in-process, replacing it atomically unless `append`.
+    FileWrite {
+        /// Absolute path; may use `{SECRET}` and `{0}`-style param tokens.
+        path: String,
+        #[serde(default = "default_file_max_bytes")]
+        max_bytes: u64,
+        /// Permission bits of the file written; an appended-to file keeps its own.
+        #[serde(default = "default_file_mode")]
+        mode: u32,
+        #[serde(default)]
+        append: bool,
+    },
+}
+
+fn default_file_max_bytes() -> u64 {
+    1 << 20
+}
+
+fn default_file_mode() -> u32 {
+    0o600
+}
+
+fn default_wasm_fuel() -> u64 {
+    1_000_000_000
+}
+
+fn default_wasm_max_memory() -> usize {
+    64 << 20
+}
+
+fn default_remote_turret() -> String {
+    "turret".to_string()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerRuntime {
+    #[default]
+    Docker,
+    Podman,
+}
+
+impl ContainerRuntime {
+    pub fn program(self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+        }
+    }
+}
+
+fn default_secret_file_mode() -> u32 {
+    0o400
+}
+
+impl TargetKind {
+    /// Whether `transform.out_command` is used.
+    pub fn runs_command(&self) -> bool {
+        match self {
+            TargetKind::K8sSecret { .. }
+            | TargetKind::SecretFiles { .. }
+            | TargetKind::Remote { .. }
+            | TargetKind::Wasm { .. }
+            | TargetKind::FileRead { .. }
+            | TargetKind::FileWrite { .. } => false,
+            TargetKind::Container { .. } => true,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        match self {
+            TargetKind::K8sSecret {
+                namespace, name, data, ..
+            } => {
+                if !is_dns_label(namespace) || !is_dns_subdomain(name) {
+                    return Err("k8s_secret namespace/name must be valid kubernetes names");
+                }
+                if data.is_empty() {
+                    return Err("k8s_secret data is empty");
+                }
+                if !data
+                    .keys()
+                    .all(|k| !k.is_empty() && k.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)))
+                {
+                    return Err("k8s_secret data key must be [A-Za-z0-9-_.]");
+                }
+            }
+            TargetKind::SecretFiles { dir, files, mode } => {
+                if !dir.starts_with('/') {
+                    return Err("secret_files dir must be absolute");
+                }
+                if files.is_empty() {
+                    return Err("secret_files files is empty");
+                }
+                if files
+                    .keys()
+                    .any(|f| f.is_empty() || f == "." || f == ".." || f.contains('/'))
+                {
+                    return Err("secret_files file name must be a plain name");
+                }
+                if mode & !0o777 != 0 {
+                    return Err("secret_files mode must be permission bits only");
+                }
+            }
+            TargetKind::Container {
+                container,
+                user,
+                workdir,
+                ..
+            } => {
+                // Passed as separate argv entries; a leading '-' would read as a runtime flag.
+                let plain = |v: &str| !v.is_empty() && !v.starts_with('-');
+                if !plain(container)
+                    || !container
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+                {
+                    return Err("container name must be [A-Za-z0-9_.-] and not start with '-'");
+                }
+                if user.as_deref().is_some_and(|u| !plain(u)) {
+                    return Err("container user must not be empty or start with '-'");
+                }
+                if workdir.as_deref().is_some_and(|w| !w.starts_with('/')) {
+                    return Err("container workdir must be absolute");
+                }
+            }
+            TargetKind::Remote {
+                ssh,
+                identity,
+                bunker,
+                dir,
+                turret,
+                rookie,
+                target,
+                ..
+            } => {
+                // These end up on the ssh command line or in the remote shell command.
+                let word = |v: &str| {
+                    !v.is_empty()
+                        && !v.starts_with('-')
+                        && v.chars().all(|c| c.is_ascii_alphanumeric() || "_.-/@:".contains(c))
+                };
+                if !word(ssh) || !word(bunker) || !word(turret) || !word(rookie) || !word(target) {
+                    return Err("remote ssh/bunker/turret/rookie/target must be plain words");
+                }
+                if !dir.starts_with('/') || !word(dir) {
+                    return Err("remote dir must be an absolute plain path");
+                }
+                if identity.as_deref().is_some_and(|i| !i.starts_with('/')) {
+                    return Err("remote identity must be an absolute path");
+                }
+            }
+            TargetKind::Wasm {
+                module,
+                fuel,
+                max_memory,
+                env,
+            } => {
+                if !module.starts_with('/') {
+                    return Err("wasm module must be an absolute path");
+                }
+                if *fuel == 0 || *max_memory == 0 {
+                    return Err("wasm fuel and max_memory must be positive");
+                }
+                if env.keys().any(|k| k.is_empty() || k.contains(['=', '\0'])) {
+                    return Err("wasm env key must be non-empty without '=' or NUL");
+                }
+            }
+            TargetKind::FileRead { path, max_bytes } => {
+                if !path.starts_with('/') {
+                    return Err("file_read path must be absolute");
+                }
+                if *max_bytes == 0 {
+                    return Err("file_read max_bytes must be > 0");
+                }
+            }
+            TargetKind::FileWrite {
+                path, max_bytes, mode, ..
+            } => {
+                if !path.starts_with('/') {
+                    return Err("file_write path must be absolute");
+                }
+                if *max_bytes == 0 {
+                    return Err("file_write max_bytes must be > 0");
+                }
+                if mode & !0o777 != 0 {
+                    return Err("file_write mode must be permission bits only");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn templates(&self) -> Vec<&String> {
+        match self {
+            TargetKind::K8sSecret { data, .. } => data.values().collect(),
+            TargetKind::SecretFiles { files, .. } => files.values().collect(),
+            TargetKind::Container { .. } => Vec::new(),
+            TargetKind::Remote { secret, .. } => vec![secret],
+            TargetKind::Wasm { env, .. } => env.values().collect(),
+            TargetKind::FileRead { .. } | TargetKind::FileWrite { .. } => Vec::new(),
+        }
+    }
+
+    /// Templates that may also take `{0}`-style params.
+    fn param_templates(&self) -> Vec<&String> {
+        match self {
+            TargetKind::FileRead { path, .. } | TargetKind::FileWrite { path, .. } => vec![path],
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn is_dns_label(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= 63
+        && s.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !s.starts_with('-')
+        && !s.ends_with('-')
+}
+
+fn is_dns_subdomain(s: &str) -> bool {
+    s.len() <= 253 && s.split('.').all(is_dns_label)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Bunker {
     pub operators: BTreeSet<String>,
     pub agents: BTreeMap<String, String>,
+    /// Recruits that sign their invokes: agent id to OpenSSH ed25519 public key.
+    pub agent_keys: BTreeMap<String, String>,
+    /// Recruits that present a TLS client certificate instead: agent id to the SHA-256 of its DER.
+    pub agent_certs: BTreeMap<String, String>,
     pub targets: BTreeMap<String, TargetDef>,
+    /// Named target sets; a permission entry may name a role instead of a target.
+    pub roles: BTreeMap<String, BTreeSet<String>>,
     pub permissions: BTreeMap<String, BTreeSet<String>>,
+    /// Agent to permission entry to the unix ms it stops granting; entries without one never expire.
+    pub grant_expiry: BTreeMap<String, BTreeMap<String, u64>>,
+    /// Agent to permission entry to when it was issued, for entries good for one successful invoke.
+    pub single_use: BTreeMap<String, BTreeMap<String, u64>>,
     pub secrets: BTreeMap<String, String>,
+    /// Rotation policy per secret name; secrets without an entry never expire.
+    pub secret_meta: BTreeMap<String, SecretMeta>,
+    /// Secrets fetched from `secret_providers` at engage.
+    pub secret_sources: BTreeMap<String, SecretSource>,
+    pub secret_providers: BTreeMap<String, ProviderConfig>,
+    pub alerts: Option<AlertConfig>,
+    /// Per-recruit invoke rate limits; recruits without an entry are unlimited.
+    pub limits: BTreeMap<String, RateLimit>,
+    /// Local users allowed to fire as a recruit over the unix socket; recruits without an entry are unrestricted.
+    pub peers: BTreeMap<String, PeerAllow>,
+    /// Environment every local target command starts from; see [`Bunker::base_env`].
+    pub base_env: BTreeMap<String, String>,
+    /// Who changed the bunker and how, oldest first; covered by the signature.
+    pub history: Vec<BunkerEdit>,
+    /// An operator's signature over everything else; see [`Bunker::sign`].
+    pub signature: Option<BunkerSignature>,
+}
+
+/// `[[history]]`: one save of the bunker.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BunkerEdit {
+    pub ts_ms: u64,
+    /// SHA256 fingerprint of the saving operator's key, when it was an ssh key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    /// The command, without secret values, e.g. `allow corvus lockbox`.
+    pub action: String,
+}
+
+/// Edits kept in `[[history]]`; older ones are dropped.
+pub const EDIT_HISTORY_KEEP: usize = 1000;
+
+/// `[signature]`: an SSHSIG by `key`, one of the operators, over the bunker encoded without this table.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BunkerSignature {
+    pub key: String,
+    pub sig: String,
+}
+
+/// SSHSIG namespace for bunker signatures, distinct from invoke signatures.
+pub const BUNKER_SIG_NAMESPACE: &str = "turret-bunker@overyonder";
+
+/// `[secret_meta.<name>]`: when a secret was set and when it stops being usable.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecretMeta {
+    /// Written by `in secret`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set_at_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_ms: Option<u64>,
+    /// Counted from `set_at_ms`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_secs: Option<u64>,
+}
+
+impl SecretMeta {
+    /// The earlier of `expires_at_ms` and `set_at_ms + max_age_secs`.
+    pub fn expires_ms(&self) -> Option<u64> {
+        let aged = self
+            .set_at_ms
+            .zip(self.max_age_secs)
+            .map(|(set, age)| set.saturating_add(age.saturating_mul(1000)));
+        match (self.expires_at_ms, aged) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// `[peers.<agent>]`: a connecting process matches by uid or primary gid.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerAllow {
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub uids: BTreeSet<u32>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub gids: BTreeSet<u32>,
+}
+
+impl PeerAllow {
+    pub fn permits(&self, peer: &PeerCred) -> bool {
+        self.uids.contains(&peer.uid) || self.gids.contains(&peer.gid)
+    }
+}
+
+/// `[limits.<agent>]`: a token bucket refilled at `per_minute`, holding up to `burst` invokes, and
+/// `[limits.<agent>.targets.<target>]` quotas. Either may be left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_minute: Option<u32>,
+    /// Defaults to `per_minute`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub targets: BTreeMap<String, TargetQuota>,
+}
+
+impl RateLimit {
+    pub fn burst(&self) -> Option<u32> {
+        self.burst.or(self.per_minute)
+    }
+}
+
+/// Invokes of one target by one recruit, counted over the last hour by the daemon.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetQuota {
+    pub max_per_hour: u32,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -50,17 +756,147 @@ pub enum BunkerError {
     Bad(&'static str),
     #[error("bad bunker: {0}")]
     BadOwned(String),
+    #[error("bunker signature: {0}")]
+    Signature(String),
 }
 
 impl Bunker {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What `def`'s command starts with before the payload's env and `out_env`: `PATH` as
+    /// [`TARGET_PATH`](crate::invoke::TARGET_PATH), then `[base_env]`, then the target's own. Empty values unset.
+    pub fn base_env(&self, def: &TargetDef) -> BTreeMap<String, String> {
+        let mut env = BTreeMap::from([("PATH".to_string(), crate::invoke::TARGET_PATH.to_string())]);
+        env.extend(self.base_env.iter().chain(&def.transform.base_env).map(|(k, v)| (k.clone(), v.clone())));
+        env.retain(|_, v| !v.is_empty());
+        env
+    }
+
+    /// Append to `[[history]]`, keeping the last [`EDIT_HISTORY_KEEP`].
+    pub fn record_edit(&mut self, ts_ms: u64, operator: Option<String>, action: String) {
+        self.history.push(BunkerEdit { ts_ms, operator, action });
+        let excess = self.history.len().saturating_sub(EDIT_HISTORY_KEEP);
+        self.history.drain(..excess);
+    }
+
+    /// The bytes a signature covers: the encoding with `[signature]` left out.
+    fn signed_bytes(&self) -> Result<Vec<u8>, BunkerError> {
         Self {
-            operators: BTreeSet::new(),
-            agents: BTreeMap::new(),
-            targets: BTreeMap::new(),
-            permissions: BTreeMap::new(),
-            secrets: BTreeMap::new(),
+            signature: None,
+            ..self.clone()
+        }
+        .encode()
+    }
+
+    /// Sign the current contents with an operator's ed25519 key, replacing any previous signature.
+    pub fn sign(&mut self, key: &ssh_key::PrivateKey) -> Result<(), BunkerError> {
+        let public = key.public_key();
+        if !self.operators.iter().any(|op| same_key(op, public)) {
+            return Err(BunkerError::Signature("signing key is not an operator".to_string()));
         }
+        let sig = key
+            .sign(BUNKER_SIG_NAMESPACE, ssh_key::HashAlg::Sha512, &self.signed_bytes()?)
+            .and_then(|sig| sig.to_pem(ssh_key::LineEnding::LF))
+            .map_err(|e| BunkerError::Signature(e.to_string()))?;
+        let key = public.to_openssh().map_err(|e| BunkerError::Signature(e.to_string()))?;
+        self.signature = Some(BunkerSignature { key, sig });
+        Ok(())
+    }
+
+    /// The signer's key when the bunker is signed by one of its operators; `None` when unsigned.
+    pub fn verify_signature(&self) -> Result<Option<ssh_key::PublicKey>, BunkerError> {
+        let Some(signature) = &self.signature else {
+            return Ok(None);
+        };
+        let bad = |e: String| BunkerError::Signature(e);
+        let key = ssh_key::PublicKey::from_openssh(&signature.key).map_err(|e| bad(e.to_string()))?;
+        if !self.operators.iter().any(|op| same_key(op, &key)) {
+            return Err(bad("signed by a key that is not an operator".to_string()));
+        }
+        let sig = ssh_key::SshSig::from_pem(&signature.sig).map_err(|e| bad(e.to_string()))?;
+        key.verify(BUNKER_SIG_NAMESPACE, &self.signed_bytes()?, &sig)
+            .map_err(|_| bad("does not match the contents".to_string()))?;
+        Ok(Some(key))
+    }
+
+    /// The first secret `def` renders that has expired by `now_ms`.
+    pub fn expired_secret(&self, def: &TargetDef, now_ms: u64) -> Option<String> {
+        collect_secret_refs(def)
+            .into_iter()
+            .find(|name| self.secret_meta.get(name).and_then(SecretMeta::expires_ms).is_some_and(|at| at <= now_ms))
+    }
+
+    /// Whether `agent` is granted `target` at `now_ms`, directly, by a `*` pattern or through a role.
+    pub fn may_fire(&self, agent: &str, target: &str, now_ms: u64) -> bool {
+        self.grants_for(agent, target, now_ms).next().is_some()
+    }
+
+    /// `agent`'s unexpired permission entries that grant `target`.
+    pub fn grants_for<'a>(&'a self, agent: &str, target: &'a str, now_ms: u64) -> impl Iterator<Item = &'a String> {
+        let agent = agent.to_string();
+        self.permissions
+            .get(&agent)
+            .into_iter()
+            .flatten()
+            .filter(move |g| self.grant_live(&agent, g, now_ms) && self.grant_covers(g, target))
+    }
+
+    /// Whether permission entry `grant` names `target`, matches it as a `*` pattern or is a role holding it.
+    pub fn grant_covers(&self, grant: &str, target: &str) -> bool {
+        grant == target
+            || (is_pattern(grant) && glob_match(grant, target))
+            || self.roles.get(grant).is_some_and(|r| r.contains(target))
+    }
+
+    /// When `agent`'s permission entry `grant` was issued, if it is single-use.
+    pub fn single_use_issued(&self, agent: &str, grant: &str) -> Option<u64> {
+        self.single_use.get(agent)?.get(grant).copied()
+    }
+
+    /// When `agent`'s permission entry `grant` expires, if it does.
+    pub fn grant_expires(&self, agent: &str, grant: &str) -> Option<u64> {
+        self.grant_expiry.get(agent)?.get(grant).copied()
+    }
+
+    /// Drop expiry and single-use entries whose permission entry is gone, after a revoke or removal.
+    pub fn prune_grant_terms(&mut self) {
+        let permissions = &self.permissions;
+        for terms in [&mut self.grant_expiry, &mut self.single_use] {
+            terms.retain(|agent, by_grant| {
+                by_grant.retain(|g, _| permissions.get(agent).is_some_and(|p| p.contains(g)));
+                !by_grant.is_empty()
+            });
+        }
+    }
+
+    fn grant_live(&self, agent: &str, grant: &str, now_ms: u64) -> bool {
+        self.grant_expires(agent, grant).is_none_or(|at| now_ms < at)
+    }
+
+    /// Every target `agent` may fire at `now_ms`, roles and patterns expanded.
+    pub fn allowed_targets(&self, agent: &str, now_ms: u64) -> BTreeSet<&String> {
+        self.targets.keys().filter(|t| self.may_fire(agent, t, now_ms)).collect()
+    }
+
+    /// Every recruit, whatever its credential.
+    pub fn recruits(&self) -> impl Iterator<Item = &String> {
+        self.agents.keys().chain(self.agent_keys.keys()).chain(self.agent_certs.keys())
+    }
+
+    pub fn is_recruit(&self, agent: &str) -> bool {
+        self.agents.contains_key(agent) || self.agent_keys.contains_key(agent) || self.agent_certs.contains_key(agent)
+    }
+
+    /// The recruit whose client certificate hashes to `sha256`, if any.
+    pub fn recruit_for_cert(&self, sha256: &str) -> Option<&String> {
+        self.agent_certs.iter().find(|(_, pin)| *pin == sha256).map(|(agent, _)| agent)
+    }
+
+    /// Whether `peer` could fire as any recruit, so other connections can be dropped unread.
+    pub fn peer_may_connect(&self, peer: &PeerCred) -> bool {
+        self.recruits().any(|agent| self.peers.get(agent).is_none_or(|allow| allow.permits(peer)))
     }
 
     pub fn decode(bytes: &[u8]) -> Result<Self, BunkerError> {
@@ -80,24 +916,227 @@ impl Bunker {
             return Err(BunkerError::Bad("no operators"));
         }
 
+        if let Some(alerts) = &self.alerts {
+            alerts.validate().map_err(BunkerError::Bad)?;
+        }
+
+        for cfg in self.secret_providers.values() {
+            cfg.validate().map_err(BunkerError::Bad)?;
+        }
+        for (name, value) in &self.secrets {
+            if let Some(Err(e)) = SecretRef::parse(value) {
+                return Err(BunkerError::BadOwned(format!("secret '{name}': {e}")));
+            }
+        }
+        for (name, meta) in &self.secret_meta {
+            if !self.secrets.contains_key(name) && !self.secret_sources.contains_key(name) {
+                return Err(BunkerError::BadOwned(format!("secret_meta for unknown secret '{name}'")));
+            }
+            if meta.max_age_secs.is_some() && meta.set_at_ms.is_none() {
+                return Err(BunkerError::BadOwned(format!("secret '{name}' has max_age_secs but no set_at_ms")));
+            }
+        }
+        for (name, source) in &self.secret_sources {
+            if self.secrets.contains_key(name) {
+                return Err(BunkerError::BadOwned(format!("secret '{name}' is both stored and sourced")));
+            }
+            if !self.secret_providers.contains_key(&source.provider) {
+                return Err(BunkerError::BadOwned(format!(
+                    "secret source '{name}' uses unknown provider '{}'",
+                    source.provider
+                )));
+            }
+        }
+
+        for (agent, stored) in &self.agents {
+            if crate::agent_secret::is_hashed(stored) && argon2::PasswordHash::new(stored).is_err() {
+                return Err(BunkerError::BadOwned(format!("recruit '{agent}' has a malformed secret hash")));
+            }
+        }
+
+        for (agent, key) in &self.agent_keys {
+            if self.agents.contains_key(agent) {
+                return Err(BunkerError::BadOwned(format!("recruit '{agent}' has both a secret and a key")));
+            }
+            let parsed = ssh_key::PublicKey::from_openssh(key)
+                .map_err(|e| BunkerError::BadOwned(format!("recruit '{agent}' key: {e}")))?;
+            if parsed.algorithm() != ssh_key::Algorithm::Ed25519 {
+                return Err(BunkerError::BadOwned(format!("recruit '{agent}' key must be ed25519")));
+            }
+        }
+
+        let mut pins = BTreeSet::new();
+        for (agent, pin) in &self.agent_certs {
+            if self.agents.contains_key(agent) || self.agent_keys.contains_key(agent) {
+                return Err(BunkerError::BadOwned(format!("recruit '{agent}' has a certificate and a secret or key")));
+            }
+            if !crate::pin::is_sha256(pin) {
+                return Err(BunkerError::BadOwned(format!("recruit '{agent}' certificate pin is not a sha256 digest")));
+            }
+            if !pins.insert(pin) {
+                return Err(BunkerError::BadOwned(format!("recruit '{agent}' shares its certificate with another")));
+            }
+        }
+
+        for (agent, limit) in &self.limits {
+            if !self.is_recruit(agent) {
+                return Err(BunkerError::BadOwned(format!("limits for unknown agent '{agent}'")));
+            }
+            let quotas = limit.targets.values().map(|q| q.max_per_hour);
+            if [limit.per_minute, limit.burst()].into_iter().flatten().chain(quotas).any(|n| n == 0) {
+                return Err(BunkerError::BadOwned(format!("limits for '{agent}' must be positive")));
+            }
+            if limit.per_minute.is_none() && limit.targets.is_empty() {
+                return Err(BunkerError::BadOwned(format!("limits for '{agent}' set neither per_minute nor a quota")));
+            }
+            if let Some(t) = limit.targets.keys().find(|t| !self.targets.contains_key(*t)) {
+                return Err(BunkerError::BadOwned(format!("limits for '{agent}' name unknown target '{t}'")));
+            }
+        }
+
+        for (agent, allow) in &self.peers {
+            if !self.is_recruit(agent) {
+                return Err(BunkerError::BadOwned(format!("peers for unknown agent '{agent}'")));
+            }
+            if allow.uids.is_empty() && allow.gids.is_empty() {
+                return Err(BunkerError::BadOwned(format!("peers for '{agent}' allow no uid or gid")));
+            }
+        }
+
+        for (role, targets) in &self.roles {
+            if is_pattern(role) {
+                return Err(BunkerError::BadOwned(format!("role name '{role}' contains '*'")));
+            }
+            if self.targets.contains_key(role) {
+                return Err(BunkerError::BadOwned(format!("role '{role}' has the same name as a target")));
+            }
+            if let Some(t) = targets.iter().find(|t| !self.targets.contains_key(*t)) {
+                return Err(BunkerError::BadOwned(format!("role '{role}' references unknown target '{t}'")));
+            }
+        }
+
         for (agent, allowed) in &self.permissions {
-            if !self.agents.contains_key(agent) {
+            if !self.is_recruit(agent) {
                 return Err(BunkerError::Bad("permission references unknown agent"));
             }
             for target in allowed {
-                if !self.targets.contains_key(target) {
-                    return Err(BunkerError::Bad("permission references unknown target"));
+                if is_pattern(target) {
+                    if !self.targets.keys().any(|t| glob_match(target, t)) {
+                        return Err(BunkerError::BadOwned(format!("permission pattern '{target}' matches no target")));
+                    }
+                } else if !self.targets.contains_key(target) && !self.roles.contains_key(target) {
+                    return Err(BunkerError::Bad("permission references unknown target or role"));
+                }
+            }
+        }
+
+        for (table, terms) in [("grant_expiry", &self.grant_expiry), ("single_use", &self.single_use)] {
+            for (agent, by_grant) in terms {
+                let granted = self.permissions.get(agent);
+                if let Some(g) = by_grant.keys().find(|g| !granted.is_some_and(|p| p.contains(*g))) {
+                    return Err(BunkerError::BadOwned(format!(
+                        "{table} for '{agent}' names '{g}', which is not granted"
+                    )));
                 }
             }
         }
 
+        let bad_key = |k: &String| k.is_empty() || k.contains('=');
+        if let Some(k) = self.base_env.keys().find(|k| bad_key(k)) {
+            return Err(BunkerError::BadOwned(format!("base_env key '{k}' is not a valid name")));
+        }
         for (target_name, def) in &self.targets {
+            if let Some(k) = def.transform.base_env.keys().find(|k| bad_key(k)) {
+                return Err(BunkerError::BadOwned(format!("target base_env key '{k}' is not a valid name")));
+            }
             if target_name.is_empty() {
                 return Err(BunkerError::Bad("empty target name"));
             }
-            if def.transform.out_command.trim().is_empty() {
+            if is_pattern(target_name) {
+                return Err(BunkerError::BadOwned(format!("target name '{target_name}' contains '*'")));
+            }
+            if let Some(kind) = &def.kind {
+                kind.validate().map_err(BunkerError::Bad)?;
+            }
+            let runs_command = def.kind.as_ref().is_none_or(TargetKind::runs_command);
+            if runs_command && def.transform.out_command.trim().is_empty() {
                 return Err(BunkerError::Bad("target out_command is empty"));
             }
+            if def.two_person_window_secs == Some(0) {
+                return Err(BunkerError::Bad("two_person_window_secs must be > 0"));
+            }
+            if def.allow_failure && !runs_command {
+                return Err(BunkerError::Bad("allow_failure only applies to targets that run a command"));
+            }
+            if def.max_output_bytes == Some(0) {
+                return Err(BunkerError::Bad("max_output_bytes must be > 0"));
+            }
+            if def.max_output_bytes.is_some() && !runs_command {
+                return Err(BunkerError::Bad("max_output_bytes only applies to targets that run a command"));
+            }
+            if def.max_concurrent == Some(0) {
+                return Err(BunkerError::Bad("max_concurrent must be > 0"));
+            }
+            if def.queue_timeout_secs == Some(0) {
+                return Err(BunkerError::Bad("queue_timeout_secs must be > 0"));
+            }
+            if def.max_concurrent.is_none() && (def.max_queued.is_some() || def.queue_timeout_secs.is_some()) {
+                return Err(BunkerError::Bad("max_queued and queue_timeout_secs need max_concurrent"));
+            }
+            if let Some(limits) = &def.limits {
+                limits.validate().map_err(BunkerError::Bad)?;
+            }
+            if let Some(sandbox) = &def.sandbox {
+                sandbox.validate().map_err(BunkerError::BadOwned)?;
+            }
+            let local_only = def
+                .transform
+                .local_only()
+                .or(def.limits.as_ref().map(|_| "limits"))
+                .or(def.sandbox.as_ref().map(|_| "sandbox"));
+            if let Some(setting) = local_only {
+                if !runs_command || matches!(def.kind, Some(TargetKind::Container { .. })) {
+                    return Err(BunkerError::BadOwned(format!(
+                        "{setting} only applies to targets that run a local command"
+                    )));
+                }
+            }
+            if def.transform.pipe.iter().any(|step| step.out_command.trim().is_empty()) {
+                return Err(BunkerError::Bad("target pipe step out_command is empty"));
+            }
+            for (c, sha) in def.transform.commands() {
+                let Some(sha) = sha else { continue };
+                if !crate::pin::is_sha256(sha) {
+                    return Err(BunkerError::Bad("out_command_sha256 must be 64 lowercase hex digits"));
+                }
+                if !c.starts_with('/') || c.contains('{') {
+                    return Err(BunkerError::Bad("a pinned out_command must be a literal absolute path"));
+                }
+            }
+            if def.transform.cwd.as_deref().is_some_and(|d| !d.starts_with('/')) {
+                return Err(BunkerError::Bad("target cwd must be an absolute path"));
+            }
+            if [&def.transform.run_as_user, &def.transform.run_as_group].into_iter().flatten().any(|n| n.is_empty()) {
+                return Err(BunkerError::Bad("target run_as_user and run_as_group must not be empty"));
+            }
+            if let Some(fd) = &def.transform.out_secret_fd {
+                if fd.env.is_empty() || fd.env.contains('=') {
+                    return Err(BunkerError::BadOwned(format!("out_secret_fd env '{}' is not a valid name", fd.env)));
+                }
+            }
+
+            def.shape.argv_regexes().map_err(BunkerError::BadOwned)?;
+            if def.shape.argv_choice_sets().map_err(BunkerError::BadOwned)?.iter().any(|(_, c)| c.is_empty()) {
+                return Err(BunkerError::Bad("argv_choices entry lists no values"));
+            }
+            def.shape.env_regexes().map_err(BunkerError::BadOwned)?;
+            def.shape.params_regexes().map_err(BunkerError::BadOwned)?;
+            if let Some(schema) = &def.shape.stdin_schema {
+                serde_json::from_str::<serde_json::Value>(schema)
+                    .map_err(|e| BunkerError::BadOwned(format!("stdin_schema: {e}")))?;
+                #[cfg(feature = "jsonschema")]
+                crate::schema::compile(schema).map_err(BunkerError::BadOwned)?;
+            }
 
             for field in def
                 .shape
@@ -106,7 +1145,7 @@ impl Bunker {
                 .chain(def.shape.forbid.iter())
                 .chain(def.shape.require.iter())
             {
-                if !matches!(field.as_str(), "command" | "argv" | "env" | "stdin") {
+                if !matches!(field.as_str(), "command" | "argv" | "env" | "stdin" | "params") {
                     return Err(BunkerError::Bad("target shape has unknown field"));
                 }
             }
@@ -117,8 +1156,14 @@ impl Bunker {
                 }
             }
 
+            for tmpl in template_strings(def) {
+                let filters = template_tokens(tmpl).into_iter().flat_map(|t| t.split('|').skip(1));
+                if let Some(f) = filters.into_iter().find(|f| !crate::invoke::TEMPLATE_FILTERS.contains(f)) {
+                    return Err(BunkerError::BadOwned(format!("target uses unknown template filter '{f}'")));
+                }
+            }
             for s in collect_secret_refs(def) {
-                if !self.secrets.contains_key(&s) {
+                if !self.secrets.contains_key(&s) && !self.secret_sources.contains_key(&s) {
                     return Err(BunkerError::BadOwned(format!("target references unknown secret '{s}'")));
                 }
             }
@@ -128,38 +1173,191 @@ impl Bunker {
     }
 }
 
+impl Bunker {
+    /// Valid but suspicious configuration as of `now_ms`, one message per finding, for `turret check`.
+    pub fn lint(&self, now_ms: u64) -> Vec<String> {
+        let mut out = Vec::new();
+        let used: BTreeSet<String> = self.targets.values().flat_map(collect_secret_refs).collect();
+        for name in self.secrets.keys().chain(self.secret_sources.keys()) {
+            if !used.contains(name) {
+                out.push(format!("secret '{name}' is not used by any target"));
+            }
+        }
+        for agent in self.recruits() {
+            if self.allowed_targets(agent, now_ms).is_empty() {
+                out.push(format!("recruit '{agent}' may fire no target"));
+            }
+        }
+        for (agent, expiring) in &self.grant_expiry {
+            for grant in expiring.keys().filter(|g| !self.grant_live(agent, g, now_ms)) {
+                out.push(format!("grant of '{grant}' to '{agent}' has expired"));
+            }
+        }
+        for (name, def) in &self.targets {
+            if !self.permissions.keys().any(|agent| self.may_fire(agent, name, now_ms)) {
+                out.push(format!("target '{name}' is allowed to no recruit"));
+            }
+            let path = self.base_env(def).remove("PATH").unwrap_or_default();
+            for (command, sha) in def.transform.commands().filter(|_| def.kind.is_none()) {
+                if !command.contains('{') && !is_executable(command, &path) {
+                    out.push(format!("target '{name}' command '{command}' is not an executable file here"));
+                }
+                let file = std::path::Path::new(command);
+                if sha.is_some_and(|sha| crate::pin::sha256_file(file).is_ok_and(|actual| actual != *sha)) {
+                    out.push(format!("target '{name}' command '{command}' has changed since it was pinned"));
+                }
+            }
+            let shape = &def.shape;
+            for field in shape.allow.intersection(&shape.forbid) {
+                out.push(format!("target '{name}' shape both allows and forbids '{field}'"));
+            }
+            for field in &shape.require {
+                if !shape.allow.contains(field) {
+                    out.push(format!("target '{name}' requires '{field}' without allowing it, so nothing conforms"));
+                }
+            }
+            if shape.argv_placeholders.is_some() && !shape.allow.contains("argv") {
+                out.push(format!(
+                    "target '{name}' counts argv placeholders without allowing argv, so nothing conforms"
+                ));
+            }
+            if shape.constrains_argv_values() && !shape.allow.contains("argv") {
+                out.push(format!("target '{name}' constrains argv values without allowing argv"));
+            }
+            if (def.params_taken() > 0 || !shape.params_pattern.is_empty()) && !shape.allow.contains("params") {
+                out.push(format!("target '{name}' takes params without allowing them, so nothing conforms"));
+            }
+            if shape.constrains_env() && !shape.allow.contains("env") {
+                out.push(format!("target '{name}' constrains env without allowing env"));
+            }
+            if (shape.max_stdin_bytes.is_some() || shape.stdin_schema.is_some()) && !shape.allow.contains("stdin") {
+                out.push(format!("target '{name}' constrains stdin without allowing stdin"));
+            }
+            if shape.stdin_schema.is_some() && !cfg!(feature = "jsonschema") {
+                out.push(format!("target '{name}' has a stdin_schema this build cannot check, so nothing conforms"));
+            }
+            if def.sandbox.is_some() && !cfg!(feature = "sandbox") {
+                out.push(format!("target '{name}' has a sandbox this build cannot apply, so it never runs"));
+            }
+        }
+        out
+    }
+}
+
+/// A permission entry with `*` wildcards, granting every target it matches.
+pub fn is_pattern(grant: &str) -> bool {
+    grant.contains('*')
+}
+
+/// `*` matches any run of characters, including none; everything else matches itself.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Paths resolve against the current directory, bare names against the target's `PATH`.
+fn is_executable(command: &str, path: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    let runnable = |p: &std::path::Path| p.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0);
+    if command.contains('/') {
+        return runnable(std::path::Path::new(command));
+    }
+    std::env::split_paths(path).any(|dir| !dir.as_os_str().is_empty() && runnable(&dir.join(command)))
+}
+
 fn collect_secret_refs(def: &TargetDef) -> BTreeSet<String> {
     let mut out = BTreeSet::new();
     collect_refs_from_string(&def.transform.out_command, &mut out);
-    for v in def.transform.out_argv_replace.values() {
-        collect_refs_from_string(v, &mut out);
+    let mut replaced = BTreeSet::new();
+    let piped = def.transform.pipe.iter().flat_map(|step| &step.out_argv);
+    let kinds = def.kind.iter().flat_map(TargetKind::param_templates);
+    let t = &def.transform;
+    for v in t.out_argv_replace.values().chain(t.out_stdin_replace.values()).chain(piped).chain(kinds) {
+        collect_refs_from_string(v, &mut replaced);
     }
-    for (k, v) in &def.transform.out_env {
+    out.extend(replaced.into_iter().filter(|t| param_index(t).is_none()));
+    let piped = def.transform.pipe.iter().flat_map(|step| &step.out_env);
+    for (k, v) in def.transform.out_env.iter().chain(piped) {
         collect_refs_from_string(k, &mut out);
         collect_refs_from_string(v, &mut out);
     }
-    for v in def.transform.out_stdin_replace.values() {
-        collect_refs_from_string(v, &mut out);
+    for step in &def.transform.pipe {
+        collect_refs_from_string(&step.out_command, &mut out);
+    }
+    if let Some(kind) = &def.kind {
+        for v in kind.templates() {
+            collect_refs_from_string(v, &mut out);
+        }
+    }
+    if let Some(fd) = &def.transform.out_secret_fd {
+        out.insert(fd.name.clone());
     }
     out
 }
 
 fn collect_refs_from_string(s: &str, out: &mut BTreeSet<String>) {
+    for token in template_tokens(s) {
+        out.insert(token.split('|').next().unwrap_or_default().to_string());
+    }
+}
+
+/// Every template string of the target, keys of `out_env` included.
+fn template_strings(def: &TargetDef) -> Vec<&String> {
+    let t = &def.transform;
+    let mut out = vec![&t.out_command];
+    out.extend(t.out_argv_replace.values().chain(t.out_stdin_replace.values()));
+    out.extend(t.out_env.iter().flat_map(|(k, v)| [k, v]));
+    for step in &t.pipe {
+        out.push(&step.out_command);
+        out.extend(&step.out_argv);
+        out.extend(step.out_env.iter().flat_map(|(k, v)| [k, v]));
+    }
+    if let Some(kind) = &def.kind {
+        out.extend(kind.templates());
+        out.extend(kind.param_templates());
+    }
+    out
+}
+
+/// The insides of the `{...}` tokens in `s`, filters (`NAME|b64`) included.
+fn template_tokens(s: &str) -> Vec<&str> {
+    let mut out = Vec::new();
     let mut pos = 0usize;
     while let Some(start_rel) = s[pos..].find('{') {
         let start = pos + start_rel;
         let Some(end_rel) = s[start..].find('}') else { break };
         let end = start + end_rel;
         let token = &s[start + 1..end];
-        if !token.is_empty()
-            && token
-                .chars()
-                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
-        {
-            out.insert(token.to_string());
+        if !is_template_token(token) {
+            pos = start + 1;
+            continue;
         }
+        out.push(token);
         pos = end + 1;
     }
+    out
+}
+
+/// `NAME` or `NAME|filter|...`; other text in braces, like a JSON object, is left as written.
+pub(crate) fn is_template_token(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '|')
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -168,12 +1366,40 @@ struct TomlBunker {
     operators: Operators,
     #[serde(default)]
     agents: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    agent_keys: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    agent_certs: BTreeMap<String, String>,
     #[serde(default)]
     targets: BTreeMap<String, TargetDef>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    roles: BTreeMap<String, Vec<String>>,
     #[serde(default)]
     permissions: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    grant_expiry: BTreeMap<String, BTreeMap<String, u64>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    single_use: BTreeMap<String, BTreeMap<String, u64>>,
     #[serde(default)]
     secrets: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    secret_meta: BTreeMap<String, SecretMeta>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    secret_sources: BTreeMap<String, SecretSource>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    secret_providers: BTreeMap<String, ProviderConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alerts: Option<AlertConfig>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    limits: BTreeMap<String, RateLimit>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    peers: BTreeMap<String, PeerAllow>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    base_env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<BunkerEdit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<BunkerSignature>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -197,9 +1423,23 @@ impl From<Bunker> for TomlBunker {
             version: 1,
             operators,
             agents: b.agents,
+            agent_keys: b.agent_keys,
+            agent_certs: b.agent_certs,
             targets: b.targets,
+            roles: b.roles.into_iter().map(|(role, targets)| (role, targets.into_iter().collect())).collect(),
             permissions,
+            grant_expiry: b.grant_expiry,
+            single_use: b.single_use,
             secrets: b.secrets,
+            secret_meta: b.secret_meta,
+            secret_sources: b.secret_sources,
+            secret_providers: b.secret_providers,
+            alerts: b.alerts,
+            limits: b.limits,
+            peers: b.peers,
+            base_env: b.base_env,
+            history: b.history,
+            signature: b.signature,
         }
     }
 }
@@ -222,11 +1462,30 @@ impl TryFrom<TomlBunker> for Bunker {
         let b = Bunker {
             operators,
             agents: t.agents,
+            agent_keys: t.agent_keys,
+            agent_certs: t.agent_certs,
             targets: t.targets,
+            roles: t.roles.into_iter().map(|(role, targets)| (role, targets.into_iter().collect())).collect(),
             permissions,
+            grant_expiry: t.grant_expiry,
+            single_use: t.single_use,
             secrets: t.secrets,
+            secret_meta: t.secret_meta,
+            secret_sources: t.secret_sources,
+            secret_providers: t.secret_providers,
+            alerts: t.alerts,
+            limits: t.limits,
+            peers: t.peers,
+            base_env: t.base_env,
+            history: t.history,
+            signature: t.signature,
         };
         b.validate()?;
         Ok(b)
     }
 }
+
+/// Whether operator recipient `op` is `key`, ignoring comments. `age1` recipients never match.
+pub fn same_key(op: &str, key: &ssh_key::PublicKey) -> bool {
+    ssh_key::PublicKey::from_openssh(op).is_ok_and(|k| k.key_data() == key.key_data())
+}
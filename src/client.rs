@@ -0,0 +1,239 @@
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::Engine;
+
+use crate::invoke::{new_request_id, FireResponse, InvokePayload, InvokeResult};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("connect {path}: {source}")]
+    Connect { path: PathBuf, source: io::Error },
+    #[error("io: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid fire payload json: {0}")]
+    Payload(String),
+    #[error("invalid daemon response: {0}")]
+    Response(String),
+    #[error("{code}: {message}")]
+    Rejected { code: String, message: String },
+}
+
+impl ClientError {
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            ClientError::Rejected { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+}
+
+/// Rookie-side client: one connection per `fire`, matching the daemon's one-shot protocol.
+#[derive(Clone, Debug)]
+pub struct AgentClient {
+    sock_path: PathBuf,
+    timeout: Option<Duration>,
+}
+
+impl AgentClient {
+    pub fn new(sock_path: impl AsRef<Path>) -> Self {
+        Self {
+            sock_path: sock_path.as_ref().to_path_buf(),
+            timeout: None,
+        }
+    }
+
+    /// Check the daemon socket exists without spending a connection on it.
+    pub fn connect(sock_path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let client = Self::new(sock_path);
+        let meta = std::fs::metadata(&client.sock_path).map_err(|source| ClientError::Connect {
+            path: client.sock_path.clone(),
+            source,
+        })?;
+        if !meta.file_type().is_socket() {
+            return Err(ClientError::Connect {
+                path: client.sock_path.clone(),
+                source: io::Error::other("not a unix socket"),
+            });
+        }
+        Ok(client)
+    }
+
+    /// Bound each read and write on the socket. `None` waits forever.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn sock_path(&self) -> &Path {
+        &self.sock_path
+    }
+
+    pub fn fire(&self, payload: &InvokePayload) -> Result<Vec<u8>, ClientError> {
+        self.fire_result(payload).map(|r| r.stdout)
+    }
+
+    /// Like `fire`, but keeps the target's stderr, exit code and duration.
+    pub fn fire_result(&self, payload: &InvokePayload) -> Result<InvokeResult, ClientError> {
+        into_result(self.exchange(payload)?)
+    }
+
+    /// Bind this client to one rookie's shared secret.
+    pub fn as_rookie(self, agent_id: impl Into<String>, agent_secret: impl Into<String>) -> RookieClient {
+        RookieClient {
+            client: self,
+            agent_id: agent_id.into(),
+            credential: Credential::Secret(agent_secret.into()),
+        }
+    }
+
+    /// Bind this client to a recruit that signs its invokes with `key`.
+    pub fn as_signing_rookie(self, agent_id: impl Into<String>, key: ssh_key::PrivateKey) -> RookieClient {
+        RookieClient {
+            client: self,
+            agent_id: agent_id.into(),
+            credential: Credential::Key(Box::new(key)),
+        }
+    }
+
+    fn exchange(&self, payload: &InvokePayload) -> Result<FireResponse, ClientError> {
+        let req = serde_json::to_vec(payload).map_err(|e| ClientError::Payload(e.to_string()))?;
+        let resp = roundtrip(&self.sock_path, self.timeout, &req)?;
+        serde_json::from_slice(&resp).map_err(|e| ClientError::Response(e.to_string()))
+    }
+
+    /// Send an already-encoded request and return the daemon's response bytes untouched.
+    pub fn fire_raw(&self, req: &[u8]) -> Result<Vec<u8>, ClientError> {
+        roundtrip(&self.sock_path, self.timeout, req)
+    }
+
+    /// Build a payload from rookie JSON, forcing `agent_id` to `rookie`, and fire it.
+    pub fn fire_json(&self, rookie: &str, raw: &[u8]) -> Result<Vec<u8>, ClientError> {
+        let payload = payload_from_json(rookie, raw)?;
+        self.fire(&payload)
+    }
+}
+
+/// An `AgentClient` that fills in the rookie's credentials and a fresh request id on every invoke.
+#[derive(Clone)]
+pub struct RookieClient {
+    client: AgentClient,
+    agent_id: String,
+    credential: Credential,
+}
+
+#[derive(Clone)]
+enum Credential {
+    Secret(String),
+    Key(Box<ssh_key::PrivateKey>),
+}
+
+impl std::fmt::Debug for RookieClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RookieClient")
+            .field("client", &self.client)
+            .field("agent_id", &self.agent_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RookieClient {
+    pub fn agent_id(&self) -> &str {
+        &self.agent_id
+    }
+
+    /// Invoke `target` with `params` (`argv`, `env`, `stdin`, `command`, or `null`) and return its stdout.
+    pub fn invoke(&self, target: &str, params: serde_json::Value) -> Result<Vec<u8>, ClientError> {
+        self.invoke_result(target, params).map(|r| r.stdout)
+    }
+
+    pub fn invoke_result(&self, target: &str, params: serde_json::Value) -> Result<InvokeResult, ClientError> {
+        let mut obj = match params {
+            serde_json::Value::Object(m) => m,
+            serde_json::Value::Null => serde_json::Map::new(),
+            _ => return Err(ClientError::Payload("params must be an object".to_string())),
+        };
+        for key in ["agent_id", "agent_secret", "target", "request_id", "ts_ms", "nonce", "signature"] {
+            if obj.contains_key(key) {
+                return Err(ClientError::Payload(format!("params must not set {key}")));
+            }
+        }
+        let request_id = new_request_id();
+        obj.insert("agent_id".into(), self.agent_id.clone().into());
+        obj.insert("target".into(), target.into());
+        obj.insert("request_id".into(), request_id.clone().into());
+        let mut payload: InvokePayload =
+            serde_json::from_value(obj.into()).map_err(|e| ClientError::Payload(e.to_string()))?;
+        match &self.credential {
+            Credential::Secret(secret) => payload.agent_secret = secret.clone(),
+            Credential::Key(key) => payload.sign(key).map_err(ClientError::Payload)?,
+        }
+
+        let resp = self.client.exchange(&payload)?;
+        if resp.request_id.as_deref().is_some_and(|id| id != request_id) {
+            return Err(ClientError::Response(format!(
+                "reply is for request {}, sent {request_id}",
+                resp.request_id.unwrap_or_default()
+            )));
+        }
+        into_result(resp)
+    }
+}
+
+/// One request per connection: write, half-close, read until the daemon closes.
+pub(crate) fn roundtrip(
+    sock_path: &Path,
+    timeout: Option<Duration>,
+    req: &[u8],
+) -> Result<Vec<u8>, ClientError> {
+    let mut stream = UnixStream::connect(sock_path).map_err(|source| ClientError::Connect {
+        path: sock_path.to_path_buf(),
+        source,
+    })?;
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    stream.write_all(req)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut resp = Vec::new();
+    stream.read_to_end(&mut resp)?;
+    Ok(resp)
+}
+
+/// Parse rookie-supplied JSON into a payload; `agent_id` always comes from `rookie`.
+pub fn payload_from_json(rookie: &str, raw: &[u8]) -> Result<InvokePayload, ClientError> {
+    let mut v: serde_json::Value =
+        serde_json::from_slice(raw).map_err(|e| ClientError::Payload(e.to_string()))?;
+    let obj = v
+        .as_object_mut()
+        .ok_or_else(|| ClientError::Payload("expected object".to_string()))?;
+    obj.insert(
+        "agent_id".to_string(),
+        serde_json::Value::String(rookie.to_string()),
+    );
+    serde_json::from_value(v).map_err(|e| ClientError::Payload(e.to_string()))
+}
+
+pub(crate) fn into_result(resp: FireResponse) -> Result<InvokeResult, ClientError> {
+    if !resp.ok {
+        return Err(ClientError::Rejected {
+            code: resp.code.unwrap_or_else(|| "error".to_string()),
+            message: resp.message.unwrap_or_else(|| "request failed".to_string()),
+        });
+    }
+    let decode = |field: &str, b64: Option<String>| match b64 {
+        Some(b64) => base64::engine::general_purpose::STANDARD
+            .decode(b64)
+            .map_err(|e| ClientError::Response(format!("bad {field}: {e}"))),
+        None => Ok(Vec::new()),
+    };
+    Ok(InvokeResult {
+        stdout: decode("result_b64", resp.result_b64)?,
+        stderr: decode("stderr_b64", resp.stderr_b64)?,
+        exit_code: resp.exit_code,
+        duration_ms: resp.duration_ms.unwrap_or(0),
+        truncated: resp.truncated,
+    })
+}
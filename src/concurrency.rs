@@ -0,0 +1,117 @@
+//! Per-target `max_concurrent`: runs beyond it wait in a bounded first-come-first-served line, or fail as `busy`.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How long a queued invoke waits for a run unless the target sets `queue_timeout_secs`.
+pub const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error)]
+#[error("target '{target}' is busy ({running} running, {queued} queued)")]
+pub struct Busy {
+    pub target: String,
+    pub running: usize,
+    pub queued: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct TargetSlots {
+    lanes: Mutex<BTreeMap<String, Lane>>,
+    freed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Lane {
+    running: usize,
+    /// Tickets of the invokes waiting, oldest first.
+    queued: VecDeque<u64>,
+    next_ticket: u64,
+}
+
+impl Lane {
+    fn busy(&self, target: &str) -> Busy {
+        Busy {
+            target: target.to_string(),
+            running: self.running,
+            queued: self.queued.len(),
+        }
+    }
+
+    fn idle(&self) -> bool {
+        self.running == 0 && self.queued.is_empty()
+    }
+}
+
+/// One run of a target, given back when dropped.
+pub struct Running<'a> {
+    slots: &'a TargetSlots,
+    target: String,
+}
+
+impl TargetSlots {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A run of `target` once fewer than `max` are running and every invoke queued before this one has gone.
+    /// Fails right away if `max_queued` are already waiting, or once `until` passes.
+    pub fn acquire(&self, target: &str, max: usize, max_queued: usize, until: Instant) -> Result<Running<'_>, Busy> {
+        let mut lanes = self.lock();
+        let lane = lanes.entry(target.to_string()).or_default();
+        if lane.queued.is_empty() && lane.running < max {
+            lane.running += 1;
+            return Ok(self.running(target));
+        }
+        if lane.queued.len() >= max_queued {
+            return Err(lane.busy(target));
+        }
+        let ticket = lane.next_ticket;
+        lane.next_ticket += 1;
+        lane.queued.push_back(ticket);
+        loop {
+            let lane = lanes.get_mut(target).expect("a lane with waiters is kept");
+            if lane.queued.front() == Some(&ticket) && lane.running < max {
+                lane.queued.pop_front();
+                lane.running += 1;
+                // The next in line may fit as well.
+                self.freed.notify_all();
+                return Ok(self.running(target));
+            }
+            let Some(left) = until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) else {
+                lane.queued.retain(|&t| t != ticket);
+                let busy = lane.busy(target);
+                if lane.idle() {
+                    lanes.remove(target);
+                }
+                self.freed.notify_all();
+                return Err(busy);
+            };
+            lanes = self.freed.wait_timeout(lanes, left).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    fn running(&self, target: &str) -> Running<'_> {
+        Running {
+            slots: self,
+            target: target.to_string(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Lane>> {
+        self.lanes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let mut lanes = self.slots.lock();
+        if let Some(lane) = lanes.get_mut(&self.target) {
+            lane.running -= 1;
+            if lane.idle() {
+                lanes.remove(&self.target);
+            }
+        }
+        self.slots.freed.notify_all();
+    }
+}
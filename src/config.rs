@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::audit::FsyncPolicy;
+use crate::log::{LogFormat, LogTarget};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("config {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("config {path}: {source}")]
+    Toml {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("config {path}: no profile '{profile}'")]
+    NoProfile { path: PathBuf, profile: String },
+}
+
+/// Everything `engage` can be told, each optional so sources can be layered.
+/// Relative paths resolve against the daemon's working directory, like the CLI flags.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EngageSettings {
+    pub operator: Option<PathBuf>,
+    /// More operator keys for a `dig --threshold` bunker.
+    pub co_operators: Option<Vec<PathBuf>>,
+    pub host_ssh_key: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub audit_max_bytes: Option<u64>,
+    pub audit_keep: Option<usize>,
+    pub audit_fsync: Option<FsyncPolicy>,
+    pub log_target: Option<LogTarget>,
+    pub log_format: Option<LogFormat>,
+    pub history_size: Option<usize>,
+    pub history_db: Option<PathBuf>,
+    pub usage_file: Option<PathBuf>,
+    pub replay_file: Option<PathBuf>,
+    pub once_file: Option<PathBuf>,
+    pub quota_file: Option<PathBuf>,
+    pub require_signed_bunker: Option<bool>,
+    pub http_listen: Option<SocketAddr>,
+    pub http_tls: Option<bool>,
+    pub vsock_port: Option<u32>,
+    pub tls_listen: Option<SocketAddr>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub shutdown_grace_secs: Option<u64>,
+    pub approval_timeout_secs: Option<u64>,
+    pub max_concurrent: Option<usize>,
+    pub max_request_bytes: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+    pub cgroup_parent: Option<PathBuf>,
+    pub socket_mode: Option<u32>,
+    pub socket_owner: Option<String>,
+    pub socket_group: Option<String>,
+    pub admin_socket_mode: Option<u32>,
+    pub admin_socket_owner: Option<String>,
+    pub admin_socket_group: Option<String>,
+}
+
+impl EngageSettings {
+    /// Field by field, keep `self` and fill the gaps from `fallback`.
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            operator: self.operator.or(fallback.operator),
+            co_operators: self.co_operators.or(fallback.co_operators),
+            host_ssh_key: self.host_ssh_key.or(fallback.host_ssh_key),
+            audit_log: self.audit_log.or(fallback.audit_log),
+            audit_max_bytes: self.audit_max_bytes.or(fallback.audit_max_bytes),
+            audit_keep: self.audit_keep.or(fallback.audit_keep),
+            audit_fsync: self.audit_fsync.or(fallback.audit_fsync),
+            log_target: self.log_target.or(fallback.log_target),
+            log_format: self.log_format.or(fallback.log_format),
+            history_size: self.history_size.or(fallback.history_size),
+            history_db: self.history_db.or(fallback.history_db),
+            usage_file: self.usage_file.or(fallback.usage_file),
+            replay_file: self.replay_file.or(fallback.replay_file),
+            once_file: self.once_file.or(fallback.once_file),
+            quota_file: self.quota_file.or(fallback.quota_file),
+            require_signed_bunker: self.require_signed_bunker.or(fallback.require_signed_bunker),
+            http_listen: self.http_listen.or(fallback.http_listen),
+            http_tls: self.http_tls.or(fallback.http_tls),
+            vsock_port: self.vsock_port.or(fallback.vsock_port),
+            tls_listen: self.tls_listen.or(fallback.tls_listen),
+            tls_cert: self.tls_cert.or(fallback.tls_cert),
+            tls_key: self.tls_key.or(fallback.tls_key),
+            shutdown_grace_secs: self.shutdown_grace_secs.or(fallback.shutdown_grace_secs),
+            approval_timeout_secs: self.approval_timeout_secs.or(fallback.approval_timeout_secs),
+            max_concurrent: self.max_concurrent.or(fallback.max_concurrent),
+            max_request_bytes: self.max_request_bytes.or(fallback.max_request_bytes),
+            max_output_bytes: self.max_output_bytes.or(fallback.max_output_bytes),
+            cgroup_parent: self.cgroup_parent.or(fallback.cgroup_parent),
+            socket_mode: self.socket_mode.or(fallback.socket_mode),
+            socket_owner: self.socket_owner.or(fallback.socket_owner),
+            socket_group: self.socket_group.or(fallback.socket_group),
+            admin_socket_mode: self.admin_socket_mode.or(fallback.admin_socket_mode),
+            admin_socket_owner: self.admin_socket_owner.or(fallback.admin_socket_owner),
+            admin_socket_group: self.admin_socket_group.or(fallback.admin_socket_group),
+        }
+    }
+
+    /// Read a `turret.toml`: top-level keys are the base, `[profiles.<name>]` tables override them.
+    pub fn load(path: &Path, profile: Option<&str>) -> Result<Self, ConfigError> {
+        let toml_err = |source| ConfigError::Toml {
+            path: path.to_path_buf(),
+            source,
+        };
+        let txt = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let mut table: toml::Table = toml::from_str(&txt).map_err(toml_err)?;
+        let profiles: BTreeMap<String, EngageSettings> = match table.remove("profiles") {
+            Some(v) => v.try_into().map_err(toml_err)?,
+            None => BTreeMap::new(),
+        };
+        let mut settings: EngageSettings = toml::Value::Table(table).try_into().map_err(toml_err)?;
+
+        if let Some(name) = profile {
+            let Some(p) = profiles.get(name) else {
+                return Err(ConfigError::NoProfile {
+                    path: path.to_path_buf(),
+                    profile: name.to_string(),
+                });
+            };
+            settings = p.clone().or(settings);
+        }
+        Ok(settings)
+    }
+}
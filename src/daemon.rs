@@ -0,0 +1,794 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use tracing::{info, info_span, warn};
+
+use crate::admin::{AdminRequest, AdminResponse, Challenges, DaemonStatus, OperatorProof};
+use crate::alert::{AlertEvent, AuthFailureTracker};
+use crate::audit::{now_ms, AuditLog, AuditRecord};
+use crate::bunker::Bunker;
+use crate::concurrency::TargetSlots;
+use crate::dump::{write_dump, BunkerSummary, DumpConfig, DumpError, InFlight, StateDump};
+use crate::history::{result_hash, History, HistoryEntry};
+use crate::invoke::{
+    execute_invoke, new_request_id, valid_request_id, Caller, FireResponse, Hangup, InvokeContext, InvokeError,
+    InvokePayload, InvokeResult, DEFAULT_MAX_OUTPUT_BYTES,
+};
+use crate::metrics::{Metrics, Observation};
+use crate::peercred::PeerCred;
+use crate::redact::Redactor;
+use crate::ratelimit::RateLimiter;
+use crate::approval::Approvals;
+use crate::once::OnceStore;
+use crate::replay::{ReplayCache, ReplayError};
+use crate::usage::UsageStore;
+
+/// Admin requests are small control messages.
+const ADMIN_MAX_REQUEST: usize = 64 << 10;
+
+/// How much of an oversized request is read and thrown away so the client still gets its error.
+const OVERSIZE_DRAIN: u64 = 16 << 20;
+
+/// Produces a freshly decrypted bunker for `reload`.
+pub type Reloader = Box<dyn Fn() -> Result<Bunker, String> + Send + Sync>;
+
+/// State shared by the fire and admin listeners of an engaged bunker.
+pub struct Daemon {
+    /// Swapped whole on reload; requests keep the snapshot they started with.
+    bunker: RwLock<Arc<Bunker>>,
+    reloader: Option<Reloader>,
+    replay: ReplayCache,
+    once: OnceStore,
+    approvals: Approvals,
+    challenges: Challenges,
+    limiter: RateLimiter,
+    slots: TargetSlots,
+    audit: Mutex<Option<AuditLog>>,
+    metrics: Metrics,
+    history: Mutex<History>,
+    auth_failures: Mutex<Option<AuthFailureTracker>>,
+    usage: Mutex<UsageStore>,
+    in_flight: Mutex<BTreeMap<String, Pending>>,
+    engaged_ms: u64,
+    dump_path: PathBuf,
+    shutting_down: AtomicBool,
+    /// Every invoke is refused while set; running ones finish.
+    frozen: AtomicBool,
+    shutdown_grace: Duration,
+    max_concurrent: usize,
+    max_request_bytes: usize,
+    max_output_bytes: usize,
+    cgroup_parent: Option<PathBuf>,
+    connections: Mutex<usize>,
+    connection_done: Condvar,
+}
+
+fn auth_failure_tracker(bunker: &Bunker) -> Option<AuthFailureTracker> {
+    bunker
+        .alerts
+        .as_ref()
+        .map(|a| AuthFailureTracker::new(a.auth_failures, Duration::from_secs(a.auth_window_secs)))
+}
+
+/// A held fire-connection slot; released on drop.
+pub(crate) struct Slot(pub(crate) Arc<Daemon>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut n = self.0.connections.lock().unwrap_or_else(|e| e.into_inner());
+        *n -= 1;
+        self.0.connection_done.notify_one();
+    }
+}
+
+struct Pending {
+    agent: String,
+    target: String,
+    since: Instant,
+}
+
+impl Daemon {
+    pub fn new(bunker: Bunker) -> Self {
+        Self {
+            auth_failures: Mutex::new(auth_failure_tracker(&bunker)),
+            bunker: RwLock::new(Arc::new(bunker)),
+            reloader: None,
+            replay: ReplayCache::new(),
+            once: OnceStore::new(),
+            approvals: Approvals::default(),
+            challenges: Challenges::default(),
+            limiter: RateLimiter::new(),
+            slots: TargetSlots::new(),
+            audit: Mutex::new(None),
+            metrics: Metrics::new(),
+            history: Mutex::new(History::new(256)),
+            usage: Mutex::new(UsageStore::new()),
+            in_flight: Mutex::new(BTreeMap::new()),
+            engaged_ms: now_ms(),
+            dump_path: PathBuf::from("turret-dump.json"),
+            shutting_down: AtomicBool::new(false),
+            frozen: AtomicBool::new(false),
+            shutdown_grace: Duration::from_secs(10),
+            max_concurrent: 16,
+            max_request_bytes: 1 << 20,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+            cgroup_parent: None,
+            connections: Mutex::new(0),
+            connection_done: Condvar::new(),
+        }
+    }
+
+    pub fn with_audit(self, audit: Option<AuditLog>) -> Self {
+        if let Some(a) = &audit {
+            info!(path = %a.path().display(), "audit log enabled");
+        }
+        *self.audit.lock().unwrap_or_else(|e| e.into_inner()) = audit;
+        self
+    }
+
+    pub fn with_history(mut self, history: History) -> Self {
+        self.history = Mutex::new(history);
+        self
+    }
+
+    pub fn with_usage(mut self, usage: UsageStore) -> Self {
+        if let Some(path) = usage.path() {
+            info!(path = %path.display(), "usage counters persisted");
+        }
+        self.usage = Mutex::new(usage);
+        self
+    }
+
+    pub fn with_limiter(mut self, limiter: RateLimiter) -> Self {
+        if let Some(path) = limiter.quota_file() {
+            info!(path = %path.display(), "quota counts persisted");
+        }
+        self.limiter = limiter;
+        self
+    }
+
+    pub fn with_replay(mut self, replay: ReplayCache) -> Self {
+        if let Some(path) = replay.path() {
+            info!(path = %path.display(), entries = replay.len(), "replay nonces persisted");
+        }
+        self.replay = replay;
+        self
+    }
+
+    pub fn with_once(mut self, once: OnceStore) -> Self {
+        if let Some(path) = once.path() {
+            info!(path = %path.display(), "single-use grants persisted");
+        }
+        self.once = once;
+        self
+    }
+
+    pub fn with_approval_timeout(mut self, timeout: Duration) -> Self {
+        self.approvals = Approvals::new(timeout);
+        self
+    }
+
+    pub fn with_dump_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dump_path = path.into();
+        self
+    }
+
+    pub fn with_reloader(mut self, reloader: Reloader) -> Self {
+        self.reloader = Some(reloader);
+        self
+    }
+
+    pub(crate) fn bunker(&self) -> Arc<Bunker> {
+        Arc::clone(&self.bunker.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Re-read the bunker through the reloader and swap it in; in-flight requests finish on the old one.
+    pub fn reload(&self) -> Result<(), String> {
+        let Some(load) = &self.reloader else {
+            return Err("this daemon cannot reload its bunker".to_string());
+        };
+        let bunker = load()?;
+        crate::redact::install(Redactor::for_bunker(&bunker));
+        *self.auth_failures.lock().unwrap_or_else(|e| e.into_inner()) = auth_failure_tracker(&bunker);
+        self.limiter.retain(|agent| bunker.limits.contains_key(agent));
+        info!(
+            targets = bunker.targets.len(),
+            agents = bunker.agents.len(),
+            "bunker reloaded"
+        );
+        *self.bunker.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(bunker);
+        Ok(())
+    }
+
+    /// How long shutdown waits for in-flight requests before giving up on them.
+    pub fn with_shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = grace;
+        self
+    }
+
+    /// Fire connections handled at once; further clients wait in the listen backlog.
+    pub fn with_max_concurrent(mut self, n: usize) -> Self {
+        self.max_concurrent = n.max(1);
+        self
+    }
+
+    /// Largest fire payload read from a socket; longer ones are refused as `bad_request`.
+    pub fn with_max_request_bytes(mut self, n: usize) -> Self {
+        self.max_request_bytes = n;
+        self
+    }
+
+    /// Output kept per stream from targets that set no `max_output_bytes` of their own.
+    pub fn with_max_output_bytes(mut self, n: usize) -> Self {
+        self.max_output_bytes = n;
+        self
+    }
+
+    /// A delegated cgroup v2 directory to hold a transient cgroup per run of targets with memory or process `limits`.
+    pub fn with_cgroup_parent(mut self, dir: Option<PathBuf>) -> Self {
+        self.cgroup_parent = dir;
+        self
+    }
+
+    pub(crate) fn max_request_bytes(&self) -> usize {
+        self.max_request_bytes
+    }
+
+    /// Block until a fire connection may be handled.
+    pub(crate) fn acquire_slot(self: &Arc<Self>) -> Slot {
+        let mut n = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        while *n >= self.max_concurrent {
+            n = self.connection_done.wait(n).unwrap_or_else(|e| e.into_inner());
+        }
+        *n += 1;
+        Slot(Arc::clone(self))
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Wait for in-flight requests to finish, up to the grace period.
+    fn drain(&self) {
+        let deadline = Instant::now() + self.shutdown_grace;
+        loop {
+            // Connections still reading their request are not in `in_flight` yet.
+            let pending = *self.connections.lock().unwrap_or_else(|e| e.into_inner())
+                + self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).len();
+            if pending == 0 {
+                info!("drained");
+                return;
+            }
+            if Instant::now() >= deadline {
+                warn!(pending, "shutdown grace expired with requests still running");
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// `peer` is the connecting process on the unix fire socket; other transports have none.
+    pub fn handle_fire(&self, req: &[u8], accepted: Instant, caller: &Caller) -> FireResponse {
+        let started = Instant::now();
+        let mut request_id = new_request_id();
+        let parsed = if req.len() > self.max_request_bytes {
+            Err(format!("request exceeds {} bytes", self.max_request_bytes))
+        } else {
+            serde_json::from_slice::<InvokePayload>(req).map_err(|e| format!("invalid json: {e}"))
+        };
+        let parsed = parsed.map(|mut p| {
+            match p.request_id.take() {
+                Some(id) if valid_request_id(&id) => request_id = id,
+                Some(_) => warn!(request_id = %request_id, "ignoring malformed client request_id"),
+                None => {}
+            }
+            if p.trace_id.as_deref().is_some_and(|id| !valid_request_id(id)) {
+                warn!(request_id = %request_id, "ignoring malformed client trace_id");
+                p.trace_id = None;
+            }
+            p
+        });
+        // Everything logged for this request, including by the target kinds, carries its ids.
+        let trace_id = parsed.as_ref().ok().and_then(|p| p.trace_id.clone());
+        let _span = info_span!("fire", request_id = %request_id, trace_id = trace_id.as_ref().map(display)).entered();
+        let (mut resp, mut rec, hash) = match parsed {
+            Ok(mut p) => {
+                let (agent, target) = (p.agent_id.clone(), p.target.clone());
+                self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).insert(
+                    request_id.clone(),
+                    Pending {
+                        agent: agent.clone(),
+                        target: target.clone(),
+                        since: accepted,
+                    },
+                );
+                // Remote targets pass it on, so both turrets' audit logs share the id.
+                p.request_id = Some(request_id.clone());
+                let bunker = self.bunker();
+                let cx = InvokeContext {
+                    replay: &self.replay,
+                    once: &self.once,
+                    approvals: &self.approvals,
+                    limiter: &self.limiter,
+                    slots: &self.slots,
+                    frozen: &self.frozen,
+                    max_output_bytes: self.max_output_bytes,
+                    cgroup_parent: self.cgroup_parent.as_deref(),
+                };
+                let res = if self.frozen.load(Ordering::SeqCst) {
+                    Err(InvokeError::Frozen)
+                } else {
+                    execute_invoke(&bunker, &cx, caller, p)
+                };
+                self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).remove(&request_id);
+                let rec = AuditRecord::for_invoke(&agent, &target, &res);
+                if bunker.targets.contains_key(&target) {
+                    self.metrics.observe(
+                        &target,
+                        Observation {
+                            queue: started - accepted,
+                            exec: started.elapsed(),
+                            result_bytes: rec.result_bytes,
+                            outcome: &rec.outcome,
+                        },
+                    );
+                }
+                let hash = res.as_ref().ok().map(|out| result_hash(&out.stdout));
+                (fire_response(res), rec, hash)
+            }
+            Err(e) => (
+                FireResponse {
+                    ok: false,
+                    result_b64: None,
+                    code: Some("bad_request".to_string()),
+                    message: Some(e),
+                    request_id: None,
+                    stderr_b64: None,
+                    exit_code: None,
+                    duration_ms: None,
+                    server_now_ms: None,
+                    truncated: false,
+                },
+                AuditRecord::new(None, None, "bad_request"),
+                None,
+            ),
+        };
+        resp.request_id = Some(request_id.clone());
+        rec.request_id = Some(request_id.clone());
+
+        info!(
+            agent = %rec.agent.as_deref().unwrap_or("-"),
+            target = %rec.target.as_deref().unwrap_or("-"),
+            outcome = %rec.outcome,
+            "fire"
+        );
+        if let Some(a) = self.audit.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            if let Err(e) = a.record(&rec) {
+                warn!("{e}");
+            }
+        }
+        // Only authenticated agents and existing targets are counted, so junk ids cannot grow the state file.
+        if rec.auth == Some("ok") {
+            let bunker = self.bunker();
+            let target = rec.target.as_deref().filter(|t| bunker.targets.contains_key(*t));
+            let res = self.usage.lock().unwrap_or_else(|e| e.into_inner()).record(
+                rec.agent.as_deref(),
+                target,
+                rec.outcome == "ok",
+                rec.ts_ms,
+            );
+            if let Err(e) = res {
+                warn!("{e}");
+            }
+        }
+        let entry = HistoryEntry {
+            ts_ms: rec.ts_ms,
+            request_id: rec.request_id,
+            agent: rec.agent,
+            target: rec.target,
+            outcome: rec.outcome,
+            duration_ms: started.elapsed().as_millis() as u64,
+            result_hash: hash,
+        };
+        if entry.outcome == "unauthenticated" {
+            if let Some(agent) = &entry.agent {
+                self.note_auth_failure(agent, &request_id);
+            }
+        }
+        if let Err(e) = self.history.lock().unwrap_or_else(|e| e.into_inner()).push(entry) {
+            warn!("{e}");
+        }
+        resp
+    }
+
+    fn note_auth_failure(&self, agent: &str, request_id: &str) {
+        let mut tracker = self.auth_failures.lock().unwrap_or_else(|e| e.into_inner());
+        let (Some(alerts), Some(tracker)) = (self.bunker().alerts.clone(), tracker.as_mut()) else {
+            return;
+        };
+        let Some(count) = tracker.record(agent, Instant::now()) else {
+            return;
+        };
+        warn!(agent = %agent, count, "repeated authentication failures");
+        let event = AlertEvent::AuthFailures {
+            agent: agent.to_string(),
+            count,
+            window_secs: alerts.auth_window_secs,
+        };
+        let request_id = request_id.to_string();
+        std::thread::spawn(move || {
+            if let Err(e) = alerts.send(&event) {
+                warn!(request_id = %request_id, "{e}");
+            }
+        });
+    }
+
+    pub fn handle_admin(&self, req: &[u8]) -> AdminResponse {
+        let req: AdminRequest = match serde_json::from_slice(req) {
+            Ok(r) => r,
+            Err(e) => return AdminResponse::error(format!("invalid admin request: {e}")),
+        };
+        match req {
+            AdminRequest::Stats => AdminResponse {
+                ok: true,
+                stats: Some(self.metrics.snapshot()),
+                usage: Some(self.usage.lock().unwrap_or_else(|e| e.into_inner()).snapshot()),
+                ..AdminResponse::default()
+            },
+            AdminRequest::Recent { limit } => AdminResponse {
+                ok: true,
+                recent: Some(self.history.lock().unwrap_or_else(|e| e.into_inner()).recent(limit)),
+                ..AdminResponse::default()
+            },
+            AdminRequest::Dump => match self.dump(&self.dump_path) {
+                Ok(()) => AdminResponse {
+                    ok: true,
+                    message: Some(format!("wrote {}", self.dump_path.display())),
+                    ..AdminResponse::default()
+                },
+                Err(e) => AdminResponse::error(e.to_string()),
+            },
+            AdminRequest::Reload => match self.reload() {
+                Ok(()) => AdminResponse {
+                    ok: true,
+                    message: Some("bunker reloaded".to_string()),
+                    ..AdminResponse::default()
+                },
+                Err(e) => {
+                    warn!("reload: {e}");
+                    AdminResponse::error(e)
+                }
+            },
+            AdminRequest::Status => AdminResponse {
+                ok: true,
+                status: Some(self.status()),
+                ..AdminResponse::default()
+            },
+            AdminRequest::Pending => AdminResponse {
+                ok: true,
+                pending: Some(self.approvals.pending()),
+                ..AdminResponse::default()
+            },
+            AdminRequest::Challenge => match self.challenges.issue() {
+                Ok(nonce) => AdminResponse {
+                    ok: true,
+                    nonce: Some(nonce),
+                    ..AdminResponse::default()
+                },
+                Err(e) => AdminResponse::error(e),
+            },
+            AdminRequest::Approve { request_id, proof } => {
+                let operator = match self.operator(&proof, "approve", &request_id) {
+                    Ok(fp) => fp,
+                    Err(e) => return AdminResponse::error(e),
+                };
+                if self.frozen.load(Ordering::SeqCst) {
+                    return AdminResponse::error("turret is frozen; thaw it before approving");
+                }
+                if !self.approvals.approve(&request_id) {
+                    return AdminResponse::error(format!("no invoke '{request_id}' is awaiting approval"));
+                }
+                info!(request_id = %request_id, operator = %operator, "approved");
+                AdminResponse {
+                    ok: true,
+                    message: Some(format!("approved {request_id}")),
+                    ..AdminResponse::default()
+                }
+            }
+            AdminRequest::Freeze { proof } => {
+                let operator = match self.operator(&proof, "freeze", "") {
+                    Ok(fp) => fp,
+                    Err(e) => return AdminResponse::error(e),
+                };
+                let was = self.frozen.swap(true, Ordering::SeqCst);
+                warn!(operator = %operator, "frozen: refusing every invoke");
+                AdminResponse {
+                    ok: true,
+                    message: Some(if was { "already frozen" } else { "frozen" }.to_string()),
+                    ..AdminResponse::default()
+                }
+            }
+            AdminRequest::Thaw { proof } => {
+                let operator = match self.operator(&proof, "thaw", "") {
+                    Ok(fp) => fp,
+                    Err(e) => return AdminResponse::error(e),
+                };
+                let was = self.frozen.swap(false, Ordering::SeqCst);
+                info!(operator = %operator, "thawed");
+                AdminResponse {
+                    ok: true,
+                    message: Some(if was { "thawed" } else { "was not frozen" }.to_string()),
+                    ..AdminResponse::default()
+                }
+            }
+        }
+    }
+
+    /// The fingerprint of the bunker operator who signed `proof` for `action` on `subject` with an unused nonce.
+    fn operator(&self, proof: &OperatorProof, action: &str, subject: &str) -> Result<String, String> {
+        let checked = if self.challenges.take(&proof.nonce) {
+            proof.verify(&self.bunker().operators, action, subject)
+        } else {
+            Err("unknown, used or expired challenge".to_string())
+        };
+        checked.map_err(|e| {
+            warn!(action, "operator check failed: {e}");
+            format!("{action} refused: {e}")
+        })
+    }
+
+    pub fn status(&self) -> DaemonStatus {
+        let bunker = self.bunker();
+        DaemonStatus {
+            pid: std::process::id(),
+            uptime_ms: now_ms().saturating_sub(self.engaged_ms),
+            targets: bunker.targets.len(),
+            agents: bunker.recruits().count(),
+            in_flight: self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).len(),
+            replay_cache: self.replay.len(),
+            awaiting_approval: self.approvals.pending().len(),
+            shutting_down: self.is_shutting_down(),
+            frozen: self.frozen.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn state_dump(&self) -> StateDump {
+        let now = Instant::now();
+        let in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(id, p)| InFlight {
+                request_id: id.clone(),
+                agent: p.agent.clone(),
+                target: p.target.clone(),
+                age_ms: now.duration_since(p.since).as_millis() as u64,
+            })
+            .collect();
+        let audit_log = self
+            .audit
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|a| a.path().to_path_buf());
+        let (usage_file, usage) = {
+            let u = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+            (u.path().map(Path::to_path_buf), u.snapshot())
+        };
+        let (history_size, recent) = {
+            let h = self.history.lock().unwrap_or_else(|e| e.into_inner());
+            (h.cap(), h.recent(h.cap()))
+        };
+        StateDump {
+            ts_ms: now_ms(),
+            pid: std::process::id(),
+            engaged_ms: self.engaged_ms,
+            config: DumpConfig {
+                audit_log,
+                usage_file,
+                history_size,
+                dump_path: self.dump_path.clone(),
+            },
+            bunker: BunkerSummary::of(&self.bunker()),
+            in_flight,
+            stats: self.metrics.snapshot(),
+            usage,
+            recent,
+        }
+    }
+
+    pub fn dump(&self, path: &Path) -> Result<(), DumpError> {
+        write_dump(path, &self.state_dump())?;
+        info!(path = %path.display(), "state dump written");
+        Ok(())
+    }
+}
+
+/// Serve fire requests on `fire` and operator requests on `admin` until accept fails.
+pub fn serve(daemon: Arc<Daemon>, fire: UnixListener, admin: UnixListener) -> io::Result<()> {
+    let d = Arc::clone(&daemon);
+    std::thread::spawn(move || loop {
+        match admin.accept() {
+            Ok((stream, _)) => {
+                if let Err(e) = reply(stream, ADMIN_MAX_REQUEST, |req| serde_json::to_vec(&d.handle_admin(req))) {
+                    warn!("admin connection: {e}");
+                }
+            }
+            Err(e) => {
+                warn!("admin accept: {e}");
+                return;
+            }
+        }
+    });
+
+    for conn in 1u64.. {
+        let slot = daemon.acquire_slot();
+        let (stream, _) = fire.accept()?;
+        if daemon.is_shutting_down() {
+            break;
+        }
+        let accepted = Instant::now();
+        std::thread::spawn(move || {
+            let _span = info_span!("conn", conn).entered();
+            let d = &slot.0;
+            let peer = match PeerCred::of(&stream) {
+                Ok(peer) => peer,
+                Err(e) => {
+                    warn!("fire connection: SO_PEERCRED: {e}");
+                    return;
+                }
+            };
+            // Dropped before reading anything when `[peers]` rules this user out for every recruit.
+            if !d.bunker().peer_may_connect(&peer) {
+                warn!(uid = peer.uid, gid = peer.gid, pid = peer.pid, "fire connection refused by [peers]");
+                return;
+            }
+            let caller = Caller {
+                peer: Some(peer),
+                cert: None,
+                hangup: Some(Hangup::on_close(stream.as_raw_fd())),
+            };
+            if let Err(e) = reply(stream, d.max_request_bytes, |req| {
+                serde_json::to_vec(&d.handle_fire(req, accepted, &caller))
+            }) {
+                warn!("fire connection: {e}");
+            }
+        });
+    }
+    daemon.drain();
+    Ok(())
+}
+
+/// On SIGTERM/SIGINT stop accepting fire requests and let `serve` drain and return.
+/// `fire_path` is the fire socket; connecting to it wakes the blocked accept. It is removed
+/// unless `keep_socket` (systemd owns activated sockets).
+pub fn shutdown_on_signal(daemon: Arc<Daemon>, fire_path: PathBuf, keep_socket: bool) -> io::Result<()> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    let mut signals = signal_hook::iterator::Signals::new([SIGTERM, SIGINT])?;
+    std::thread::spawn(move || {
+        for sig in signals.forever() {
+            if daemon.shutting_down.swap(true, Ordering::SeqCst) {
+                warn!(signal = sig, "already shutting down");
+                continue;
+            }
+            info!(signal = sig, grace_ms = daemon.shutdown_grace.as_millis() as u64, "shutting down");
+            crate::systemd::notify("STOPPING=1");
+            if let Err(e) = UnixStream::connect(&fire_path) {
+                warn!("wake fire listener: {e}");
+            }
+            // New clients now fail to connect instead of queueing behind the drain.
+            if !keep_socket {
+                let _ = std::fs::remove_file(&fire_path);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Reload the bunker on every SIGHUP; a failed reload keeps the current one.
+pub fn reload_on_sighup(daemon: Arc<Daemon>) -> io::Result<()> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            if let Err(e) = daemon.reload() {
+                warn!("reload: {e}");
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Write a state dump to the default dump path on every SIGUSR1.
+pub fn dump_on_sigusr1(daemon: Arc<Daemon>) -> io::Result<()> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGUSR1])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            if let Err(e) = daemon.dump(&daemon.dump_path) {
+                warn!("{e}");
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Read one request of at most `limit` bytes (one more, to tell the handler it was cut off) and write the reply.
+pub(crate) fn reply<S: Read + Write>(
+    mut stream: S,
+    limit: usize,
+    handle: impl FnOnce(&[u8]) -> serde_json::Result<Vec<u8>>,
+) -> io::Result<()> {
+    let mut req = Vec::new();
+    (&mut stream).take(limit as u64 + 1).read_to_end(&mut req)?;
+    if req.len() > limit {
+        // Closing with unread input resets the connection, losing the reply; discard a bounded amount.
+        io::copy(&mut (&mut stream).take(OVERSIZE_DRAIN), &mut io::sink())?;
+    }
+    let resp = handle(&req)?;
+    stream.write_all(&resp)
+}
+
+fn fire_response(res: Result<InvokeResult, InvokeError>) -> FireResponse {
+    let b64 = base64::engine::general_purpose::STANDARD;
+    match res {
+        Ok(out) => FireResponse {
+            ok: true,
+            result_b64: Some(b64.encode(out.stdout)),
+            code: None,
+            message: None,
+            request_id: None,
+            stderr_b64: (!out.stderr.is_empty()).then(|| b64.encode(out.stderr)),
+            exit_code: out.exit_code,
+            duration_ms: Some(out.duration_ms),
+            server_now_ms: None,
+            truncated: out.truncated,
+        },
+        Err(e) => {
+            let code = e.code();
+            let server_now_ms = match &e {
+                InvokeError::Replay(ReplayError::OutsideWindow { now_ms, .. }) => Some(*now_ms),
+                _ => None,
+            };
+            let msg = match e {
+                InvokeError::Unauthenticated => "bad agent credentials".to_string(),
+                InvokeError::Denied => "denied".to_string(),
+                InvokeError::UnknownTarget => "unknown target".to_string(),
+                InvokeError::BadRequest(m) | InvokeError::Internal(m) => m,
+                InvokeError::Replay(e) => e.to_string(),
+                InvokeError::RateLimited(e) => e.to_string(),
+                InvokeError::QuotaExceeded(e) => e.to_string(),
+                InvokeError::Busy(e) => e.to_string(),
+                InvokeError::PeerDenied
+                | InvokeError::DeadlineExceeded
+                | InvokeError::SecretExpired(_)
+                | InvokeError::ApprovalTimeout
+                | InvokeError::TwoPersonTimeout
+                | InvokeError::Frozen
+                | InvokeError::Cancelled => e.to_string(),
+            };
+            FireResponse {
+                ok: false,
+                result_b64: None,
+                code: Some(code.to_string()),
+                message: Some(msg),
+                request_id: None,
+                stderr_b64: None,
+                exit_code: None,
+                duration_ms: None,
+                server_now_ms,
+                truncated: false,
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! `engage --daemon`: fork into the background and report back once the sockets are listening.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+const READY: &[u8] = b"ready";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DetachError {
+    #[error("detach: {0}")]
+    Io(#[from] io::Error),
+    #[error("daemon exited before it was ready; see {}", .0.display())]
+    NotReady(PathBuf),
+}
+
+/// Held by the detached child; `ready` releases the waiting parent.
+#[derive(Debug)]
+pub struct Readiness {
+    pipe: io::PipeWriter,
+}
+
+impl Readiness {
+    pub fn ready(mut self) {
+        let _ = self.pipe.write_all(READY);
+    }
+}
+
+pub enum Detached {
+    /// The child reported ready under this pid.
+    Parent(u32),
+    Child(Readiness),
+}
+
+/// Fork, `setsid`, and point stdin at /dev/null and stdout/stderr at `log`.
+/// Must run before any threads are started. The parent returns only once the child calls
+/// [`Readiness::ready`], or fails if the child exits first.
+pub fn detach(log: &Path) -> Result<Detached, DetachError> {
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(log)
+        .map_err(|e| io::Error::new(e.kind(), format!("open {}: {e}", log.display())))?;
+    let devnull = File::open("/dev/null")?;
+    let (mut rx, tx) = io::pipe()?;
+
+    // SAFETY: the process is single-threaded here, so the child may keep running Rust code.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => {
+            drop(rx);
+            if unsafe { libc::setsid() } == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+            redirect(&devnull, 0)?;
+            redirect(&log_file, 1)?;
+            redirect(&log_file, 2)?;
+            Ok(Detached::Child(Readiness { pipe: tx }))
+        }
+        pid => {
+            drop(tx);
+            let mut buf = Vec::new();
+            rx.read_to_end(&mut buf)?;
+            if buf == READY {
+                return Ok(Detached::Parent(pid as u32));
+            }
+            let mut status = 0;
+            unsafe { libc::waitpid(pid, &mut status, 0) };
+            Err(DetachError::NotReady(log.to_path_buf()))
+        }
+    }
+}
+
+fn redirect(file: &File, fd: i32) -> io::Result<()> {
+    if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
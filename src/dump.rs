@@ -0,0 +1,86 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::atomic::write_atomic;
+use crate::bunker::Bunker;
+use crate::history::HistoryEntry;
+use crate::metrics::TargetMetrics;
+use crate::usage::UsageSnapshot;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DumpError {
+    #[error("state dump {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("state dump json: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Debug snapshot of an engaged daemon. Holds names and counts only; never secret values.
+#[derive(Debug, Serialize)]
+pub struct StateDump {
+    pub ts_ms: u64,
+    pub pid: u32,
+    pub engaged_ms: u64,
+    pub config: DumpConfig,
+    pub bunker: BunkerSummary,
+    pub in_flight: Vec<InFlight>,
+    pub stats: BTreeMap<String, TargetMetrics>,
+    pub usage: UsageSnapshot,
+    pub recent: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DumpConfig {
+    pub audit_log: Option<PathBuf>,
+    pub usage_file: Option<PathBuf>,
+    pub history_size: usize,
+    pub dump_path: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BunkerSummary {
+    pub operators: BTreeSet<String>,
+    pub agents: Vec<String>,
+    pub targets: Vec<String>,
+    pub roles: BTreeMap<String, BTreeSet<String>>,
+    pub permissions: BTreeMap<String, BTreeSet<String>>,
+    pub secrets: Vec<String>,
+    pub alert_exec: bool,
+    pub alert_webhook: bool,
+}
+
+impl BunkerSummary {
+    pub fn of(b: &Bunker) -> Self {
+        Self {
+            operators: b.operators.clone(),
+            agents: b.recruits().cloned().collect(),
+            targets: b.targets.keys().cloned().collect(),
+            roles: b.roles.clone(),
+            permissions: b.permissions.clone(),
+            secrets: b.secrets.keys().cloned().collect(),
+            alert_exec: b.alerts.as_ref().is_some_and(|a| a.exec.is_some()),
+            alert_webhook: b.alerts.as_ref().is_some_and(|a| a.webhook.is_some()),
+        }
+    }
+}
+
+/// A fire request accepted but not yet answered.
+#[derive(Clone, Debug, Serialize)]
+pub struct InFlight {
+    pub request_id: String,
+    pub agent: String,
+    pub target: String,
+    pub age_ms: u64,
+}
+
+pub fn write_dump(path: &Path, dump: &StateDump) -> Result<(), DumpError> {
+    let io_err = |source| DumpError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let body = serde_json::to_vec_pretty(dump)?;
+    write_atomic(path, &body).map_err(io_err)
+}
@@ -0,0 +1,99 @@
+//! Minimal C ABI over `client::AgentClient`. See `include/turret.h`.
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use crate::client::AgentClient;
+
+#[repr(C)]
+pub struct TurretResult {
+    pub ok: bool,
+    pub data: *mut u8,
+    pub len: usize,
+    pub error: *mut c_char,
+}
+
+/// # Safety
+/// `sock_path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn turret_connect(sock_path: *const c_char) -> *mut AgentClient {
+    let Some(path) = str_arg(sock_path) else {
+        return ptr::null_mut();
+    };
+    match AgentClient::connect(path) {
+        Ok(c) => Box::into_raw(Box::new(c)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `client` must come from `turret_connect`; `rookie` and `params_json` must be valid
+/// NUL-terminated strings. The returned result must be released with `turret_free_result`.
+#[no_mangle]
+pub unsafe extern "C" fn turret_invoke(
+    client: *const AgentClient,
+    rookie: *const c_char,
+    params_json: *const c_char,
+) -> *mut TurretResult {
+    let out = match (client.as_ref(), str_arg(rookie), str_arg(params_json)) {
+        (Some(c), Some(r), Some(p)) => c
+            .fire_json(r, p.as_bytes())
+            .map_err(|e| e.to_string()),
+        _ => Err("bad_request: null or non-utf-8 argument".to_string()),
+    };
+    let res = match out {
+        Ok(bytes) => {
+            let mut bytes = bytes.into_boxed_slice();
+            let len = bytes.len();
+            let data = bytes.as_mut_ptr();
+            std::mem::forget(bytes);
+            TurretResult {
+                ok: true,
+                data,
+                len,
+                error: ptr::null_mut(),
+            }
+        }
+        Err(msg) => TurretResult {
+            ok: false,
+            data: ptr::null_mut(),
+            len: 0,
+            error: CString::new(msg.replace('\0', " "))
+                .map(CString::into_raw)
+                .unwrap_or(ptr::null_mut()),
+        },
+    };
+    Box::into_raw(Box::new(res))
+}
+
+/// # Safety
+/// `result` must come from `turret_invoke` and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn turret_free_result(result: *mut TurretResult) {
+    if result.is_null() {
+        return;
+    }
+    let res = Box::from_raw(result);
+    if !res.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(res.data, res.len)));
+    }
+    if !res.error.is_null() {
+        drop(CString::from_raw(res.error));
+    }
+}
+
+/// # Safety
+/// `client` must come from `turret_connect` and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn turret_disconnect(client: *mut AgentClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
@@ -0,0 +1,69 @@
+//! `file_read` and `file_write` targets: the daemon reads or writes the file itself, with nothing spawned.
+
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// A rendered path, refused if a param or secret could have walked it out of where the template points.
+/// The path is not echoed, as it may hold a secret.
+pub(crate) fn checked(path: &str) -> Result<&Path, String> {
+    let p = Path::new(path);
+    if !p.is_absolute() || path.contains('\0') || p.components().any(|c| c == Component::ParentDir) {
+        return Err("non-conforming payload: rendered path is not absolute or has '..'".to_string());
+    }
+    if p.file_name().is_none() {
+        return Err("non-conforming payload: rendered path names no file".to_string());
+    }
+    Ok(p)
+}
+
+/// The whole of a [`checked`] regular file no larger than `max_bytes`. A symlink in the last component is not followed.
+pub(crate) fn read_file(p: &Path, max_bytes: u64) -> Result<Vec<u8>, String> {
+    let err = |e: std::io::Error| format!("read {}: {e}", p.display());
+    let f = OpenOptions::new().read(true).custom_flags(libc::O_NOFOLLOW).open(p).map_err(err)?;
+    if !f.metadata().map_err(err)?.is_file() {
+        return Err(format!("{} is not a regular file", p.display()));
+    }
+    let mut out = Vec::new();
+    // One byte past the limit tells a file that grew meanwhile from one that is exactly at it.
+    f.take(max_bytes + 1).read_to_end(&mut out).map_err(err)?;
+    if out.len() as u64 > max_bytes {
+        return Err(format!("{} is over max_bytes ({max_bytes})", p.display()));
+    }
+    Ok(out)
+}
+
+/// Replace the [`checked`] file atomically with `mode`, or append to it, creating it with `mode`.
+/// Returns how many bytes were written; not the path, which may hold a secret.
+pub(crate) fn write_file(p: &Path, bytes: &[u8], mode: u32, append: bool) -> Result<Vec<u8>, String> {
+    let err = |e: std::io::Error| format!("write {}: {e}", p.display());
+    if append {
+        let mut f = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(mode)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(p)
+            .map_err(err)?;
+        f.write_all(bytes).and_then(|()| f.sync_data()).map_err(err)?;
+    } else {
+        static SEQ: AtomicU32 = AtomicU32::new(0);
+        let name = p.file_name().unwrap_or_default().to_string_lossy();
+        let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+        let tmp = p.with_file_name(format!(".{name}.turret-{}-{seq}", std::process::id()));
+        let write = || -> std::io::Result<()> {
+            let mut f = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp)?;
+            f.write_all(bytes)?;
+            f.sync_data()?;
+            f.set_permissions(std::fs::Permissions::from_mode(mode))?;
+            std::fs::rename(&tmp, p)
+        };
+        if let Err(e) = write() {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(err(e));
+        }
+    }
+    Ok(format!("{}\n", bytes.len()).into_bytes())
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[cfg(feature = "sqlite")]
+    #[error("history db: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub ts_ms: u64,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    pub agent: Option<String>,
+    pub target: Option<String>,
+    pub outcome: String,
+    pub duration_ms: u64,
+    /// First 16 hex chars of sha256(stdout) for successful runs.
+    pub result_hash: Option<String>,
+}
+
+/// Bounded in-memory record of recent invocations, optionally mirrored to sqlite.
+pub struct History {
+    cap: usize,
+    entries: VecDeque<HistoryEntry>,
+    #[cfg(feature = "sqlite")]
+    db: Option<rusqlite::Connection>,
+}
+
+impl History {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            entries: VecDeque::with_capacity(cap.min(1024)),
+            #[cfg(feature = "sqlite")]
+            db: None,
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn with_sqlite(mut self, path: &std::path::Path) -> Result<Self, HistoryError> {
+        let db = rusqlite::Connection::open(path)?;
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS invocations (
+                ts_ms INTEGER NOT NULL,
+                agent TEXT,
+                target TEXT,
+                outcome TEXT NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                result_hash TEXT,
+                request_id TEXT
+            );
+            CREATE INDEX IF NOT EXISTS invocations_ts ON invocations (ts_ms);",
+        )?;
+        // Databases created before request ids existed lack the column.
+        let has_request_id = db
+            .prepare("SELECT 1 FROM pragma_table_info('invocations') WHERE name = 'request_id'")?
+            .exists([])?;
+        if !has_request_id {
+            db.execute_batch("ALTER TABLE invocations ADD COLUMN request_id TEXT")?;
+        }
+        self.db = Some(db);
+        Ok(self)
+    }
+
+    /// Keep the entry in memory even if the sqlite mirror fails; the error is returned for logging.
+    pub fn push(&mut self, entry: HistoryEntry) -> Result<(), HistoryError> {
+        #[cfg(feature = "sqlite")]
+        let res = match &self.db {
+            Some(db) => db
+                .execute(
+                    "INSERT INTO invocations (ts_ms, agent, target, outcome, duration_ms, result_hash, request_id)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    rusqlite::params![
+                        entry.ts_ms as i64,
+                        entry.agent,
+                        entry.target,
+                        entry.outcome,
+                        entry.duration_ms as i64,
+                        entry.result_hash,
+                        entry.request_id,
+                    ],
+                )
+                .map(|_| ())
+                .map_err(HistoryError::from),
+            None => Ok(()),
+        };
+        #[cfg(not(feature = "sqlite"))]
+        let res = Ok(());
+
+        if self.cap > 0 {
+            if self.entries.len() == self.cap {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(entry);
+        }
+        res
+    }
+
+    /// Newest first.
+    pub fn recent(&self, limit: usize) -> Vec<HistoryEntry> {
+        self.entries.iter().rev().take(limit).cloned().collect()
+    }
+
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+pub fn result_hash(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    digest[..8].iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,249 @@
+//! Minimal HTTP/1.1 frontend: `POST /v1/fire/{target}` with `Authorization: Bearer <agent_id>:<agent_secret>`.
+//! Over TLS a client may instead present a certificate pinned in `[agent_certs]` and send no Authorization.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustls::{ServerConfig, ServerConnection};
+use tracing::{info_span, warn};
+
+use crate::daemon::Daemon;
+use crate::tls::Deadlined;
+use crate::invoke::{Caller, FireResponse, Hangup};
+
+const MAX_HEAD: usize = 16 << 10;
+const MAX_BODY: usize = 1 << 20;
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pause after a failed accept, so a full fd table is not spun on.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Serve the gateway on its own thread, over TLS with `tls`; requests go through the same path as the unix socket,
+/// each connection on its own thread holding a fire slot.
+pub fn spawn(daemon: Arc<Daemon>, listener: TcpListener, tls: Option<Arc<ServerConfig>>) {
+    std::thread::spawn(move || loop {
+        let (stream, peer) = match listener.accept() {
+            Ok(_) if daemon.is_shutting_down() => return,
+            Ok(conn) => conn,
+            // Out of fds or a connection reset before accept: the listener is still good.
+            Err(e) => {
+                warn!("http accept: {e}");
+                std::thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        let accepted = Instant::now();
+        let slot = daemon.acquire_slot();
+        let tls = tls.clone();
+        std::thread::spawn(move || {
+            let _span = info_span!("http", %peer).entered();
+            if let Err(e) = handle(&slot.0, stream, tls, accepted) {
+                warn!("http connection: {e}");
+            }
+        });
+    });
+}
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    request_id: Option<String>,
+    body: Vec<u8>,
+}
+
+fn handle(daemon: &Daemon, stream: TcpStream, tls: Option<Arc<ServerConfig>>, accepted: Instant) -> io::Result<()> {
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    // The whole request must arrive by then, however slowly its bytes trickle in.
+    let mut io = Deadlined {
+        stream: &stream,
+        until: accepted + IO_TIMEOUT,
+    };
+    // HTTP clients do not half-close, so end of input means the client is gone.
+    let mut caller = Caller {
+        hangup: Some(Hangup::on_eof(stream.as_raw_fd())),
+        ..Caller::default()
+    };
+    let Some(config) = tls else {
+        let resp = respond(daemon, read_request(&mut io), caller, accepted)?;
+        return (&stream).write_all(&resp);
+    };
+    let mut conn = ServerConnection::new(config).map_err(io::Error::other)?;
+    caller.cert = crate::tls::handshake(&mut conn, &mut io)?;
+    let req = read_request(rustls::Stream::new(&mut conn, &mut io));
+    let resp = respond(daemon, req, caller, accepted)?;
+    crate::tls::finish(&mut conn, &stream, &resp)
+}
+
+/// The whole HTTP response to `req`.
+fn respond(
+    daemon: &Daemon,
+    req: Result<Request, &str>,
+    caller: Caller,
+    accepted: Instant,
+) -> io::Result<Vec<u8>> {
+    let (status, resp) = match req {
+        Ok(req) => route(daemon, req, caller, accepted),
+        Err(msg) => (400, error_response("bad_request", msg)),
+    };
+    let body = serde_json::to_vec(&resp)?;
+    let mut out = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        reason(status),
+        body.len()
+    )
+    .into_bytes();
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+fn route(daemon: &Daemon, req: Request, caller: Caller, accepted: Instant) -> (u16, FireResponse) {
+    let Some(target) = req.path.strip_prefix("/v1/fire/") else {
+        return (404, error_response("not_found", "no such route"));
+    };
+    if req.method != "POST" {
+        return (405, error_response("bad_request", "use POST"));
+    }
+    if target.is_empty() || target.contains(['/', '%', '?']) {
+        return (404, error_response("unknown_target", "bad target name"));
+    }
+    let bearer = req
+        .authorization
+        .as_deref()
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .and_then(|t| t.split_once(':'));
+    // A bearer token names the recruit itself; without one, a pinned client certificate does.
+    let (agent, secret) = match bearer {
+        Some((agent, secret)) => (agent.to_string(), secret.to_string()),
+        None => match caller.cert.as_deref().and_then(|c| daemon.bunker().recruit_for_cert(c).cloned()) {
+            Some(agent) => (agent, String::new()),
+            None => {
+                return (
+                    401,
+                    error_response("unauthenticated", "expected Authorization: Bearer <agent_id>:<agent_secret>"),
+                )
+            }
+        },
+    };
+
+    let mut payload = if req.body.iter().all(u8::is_ascii_whitespace) {
+        serde_json::Map::new()
+    } else {
+        match serde_json::from_slice::<serde_json::Value>(&req.body) {
+            Ok(serde_json::Value::Object(m)) => m,
+            Ok(_) => return (400, error_response("bad_request", "body must be a json object")),
+            Err(e) => return (400, error_response("bad_request", &format!("invalid json: {e}"))),
+        }
+    };
+    payload.insert("agent_id".into(), agent.into());
+    payload.insert("agent_secret".into(), secret.into());
+    payload.insert("target".into(), target.into());
+    if let Some(id) = req.request_id {
+        payload.entry("request_id").or_insert(id.into());
+    }
+    let raw = match serde_json::to_vec(&payload) {
+        Ok(raw) => raw,
+        Err(e) => return (500, error_response("internal", &e.to_string())),
+    };
+
+    let resp = daemon.handle_fire(&raw, accepted, &caller);
+    let status = match resp.code.as_deref() {
+        None => 200,
+        Some("unauthenticated" | "replay") => 401,
+        Some("denied" | "peer_denied") => 403,
+        Some("unknown_target") => 404,
+        Some("bad_request") => 400,
+        Some("rate_limited" | "quota_exceeded") => 429,
+        Some("deadline_exceeded" | "approval_timeout" | "two_person_timeout") => 504,
+        Some("secret_expired" | "frozen" | "busy") => 503,
+        Some(_) => 500,
+    };
+    (status, resp)
+}
+
+fn read_request(stream: impl Read) -> Result<Request, &'static str> {
+    let mut r = BufReader::new(stream.take(MAX_HEAD as u64));
+    let mut line = String::new();
+    r.read_line(&mut line).map_err(|_| "unreadable request line")?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err("malformed request line");
+    };
+    let mut req = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        authorization: None,
+        request_id: None,
+        body: Vec::new(),
+    };
+
+    let mut content_length = 0usize;
+    loop {
+        line.clear();
+        if r.read_line(&mut line).map_err(|_| "unreadable headers")? == 0 {
+            return Err("truncated headers");
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err("malformed header");
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.parse().map_err(|_| "bad content-length")?,
+            "authorization" => req.authorization = Some(value.to_string()),
+            "x-request-id" => req.request_id = Some(value.to_string()),
+            "transfer-encoding" => return Err("chunked bodies are not supported"),
+            _ => {}
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err("body too large");
+    }
+
+    // Part of the body may already sit in the reader's buffer.
+    let unbuffered = content_length.saturating_sub(r.buffer().len());
+    r.get_mut().set_limit(unbuffered as u64);
+    req.body.reserve(content_length);
+    r.take(content_length as u64)
+        .read_to_end(&mut req.body)
+        .map_err(|_| "unreadable body")?;
+    if req.body.len() != content_length {
+        return Err("truncated body");
+    }
+    Ok(req)
+}
+
+fn error_response(code: &str, message: &str) -> FireResponse {
+    FireResponse {
+        ok: false,
+        result_b64: None,
+        code: Some(code.to_string()),
+        message: Some(message.to_string()),
+        request_id: None,
+        stderr_b64: None,
+        exit_code: None,
+        duration_ms: None,
+        server_now_ms: None,
+        truncated: false,
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Internal Server Error",
+    }
+}
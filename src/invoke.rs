@@ -1,14 +1,34 @@
 use std::collections::BTreeMap;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
 
+use base64::Engine;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
 
-use crate::bunker::{Bunker, TargetDef};
+use crate::approval::{Approvals, Pair, Wait};
+use crate::bunker::{is_template_token, param_index, Bunker, TargetDef, TargetKind, TargetLimits, TargetSandbox};
+use crate::concurrency::{Busy, TargetSlots, DEFAULT_QUEUE_TIMEOUT};
+use crate::limits::Cgroup;
+use crate::once::{Claim, OnceStore};
+use crate::peercred::PeerCred;
+use crate::remote;
+use crate::ratelimit::{QuotaExceeded, RateLimited, RateLimiter};
+use crate::redact::Redactor;
+use crate::replay::{ReplayCache, ReplayError};
+use crate::secret_sync;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct InvokePayload {
     pub agent_id: String,
+    /// Empty for recruits that sign instead.
+    #[serde(default)]
     pub agent_secret: String,
     pub target: String,
     #[serde(default)]
@@ -19,6 +39,241 @@ pub struct InvokePayload {
     pub env: Option<BTreeMap<String, String>>,
     #[serde(default)]
     pub stdin: Option<String>,
+    /// Values for the `{0}`, `{1}`, ... placeholders in the target's `out_argv_replace`/`out_stdin_replace`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Vec<String>>,
+    /// Correlation id carried into every log, audit and history record for this request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Caller's distributed-trace id: logged, forwarded by remote targets, and exported as `TURRET_TRACE_ID`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    /// Signed invokes only: when and with what nonce it was signed, and the armored SSHSIG.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ts_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Unix ms after which the agent no longer wants the result; the daemon refuses or kills the work.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+    /// Check and resolve the request but run nothing; the result is a [`DryRun`] as JSON.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+/// What a dry run would have run. Secret values appear as `«secret:NAME»`; stdin is only measured.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DryRun {
+    pub command: String,
+    pub argv: Vec<String>,
+    pub env_keys: Vec<String>,
+    pub stdin_len: usize,
+    /// The transform's `pipe` steps, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pipe: Vec<DryRunStep>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DryRunStep {
+    pub command: String,
+    pub argv: Vec<String>,
+    pub env_keys: Vec<String>,
+}
+
+/// Env var a command target sees the invoke's `trace_id` in.
+pub const TRACE_ID_ENV: &str = "TURRET_TRACE_ID";
+
+/// SSHSIG namespace for invoke signatures, so they cannot be reused for anything else.
+pub const SIG_NAMESPACE: &str = "turret-invoke@overyonder";
+
+/// The fields a signature covers. `request_id` and `trace_id` are only for correlation and are left out.
+#[derive(Serialize)]
+struct Signed<'a> {
+    agent_id: &'a str,
+    target: &'a str,
+    command: &'a Option<String>,
+    argv: &'a Option<Vec<String>>,
+    env: &'a Option<BTreeMap<String, String>>,
+    stdin: &'a Option<String>,
+    /// Like `deadline_ms` and `dry_run`, left out when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params: &'a Option<Vec<String>>,
+    ts_ms: Option<u64>,
+    nonce: &'a Option<String>,
+    /// Left out when unset, so signatures from before it existed still verify.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deadline_ms: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    dry_run: bool,
+}
+
+impl InvokePayload {
+    fn signed_bytes(&self) -> Vec<u8> {
+        let signed = Signed {
+            agent_id: &self.agent_id,
+            target: &self.target,
+            command: &self.command,
+            argv: &self.argv,
+            env: &self.env,
+            stdin: &self.stdin,
+            params: &self.params,
+            ts_ms: self.ts_ms,
+            nonce: &self.nonce,
+            deadline_ms: self.deadline_ms,
+            dry_run: self.dry_run,
+        };
+        serde_json::to_vec(&signed).expect("plain strings and maps serialize")
+    }
+
+    /// Hex SHA-256 over the target and the fields that shape what runs, for matching two-person requests.
+    pub fn params_hash(&self) -> String {
+        let params = (&self.target, &self.command, &self.argv, &self.env, &self.stdin, &self.params);
+        let bytes = serde_json::to_vec(&params).expect("plain strings and maps serialize");
+        Sha256::digest(bytes).iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Stamp with the current time and a fresh nonce, then sign with the recruit's key.
+    pub fn sign(&mut self, key: &ssh_key::PrivateKey) -> Result<(), String> {
+        self.ts_ms = Some(crate::audit::now_ms());
+        self.nonce = Some(random_nonce().map_err(|e| format!("nonce: {e}"))?);
+        self.agent_secret.clear();
+        let sig = key
+            .sign(SIG_NAMESPACE, ssh_key::HashAlg::Sha512, &self.signed_bytes())
+            .map_err(|e| format!("sign: {e}"))?;
+        self.signature = Some(sig.to_pem(ssh_key::LineEnding::LF).map_err(|e| format!("sign: {e}"))?);
+        Ok(())
+    }
+}
+
+pub(crate) fn random_nonce() -> std::io::Result<String> {
+    let mut buf = [0u8; 16];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut buf)?;
+    Ok(buf.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FireResponse {
+    pub ok: bool,
+    pub result_b64: Option<String>,
+    pub code: Option<String>,
+    pub message: Option<String>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// Target stderr, when it wrote any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_b64: Option<String>,
+    /// Process exit status; absent for kinds that do not run a process.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// The daemon's clock, on `replay` errors for a timestamp outside the window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_now_ms: Option<u64>,
+    /// Stdout or stderr was cut off at the target's `max_output_bytes`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+/// What a target produced. A nonzero `exit_code` only gets this far on `allow_failure` targets.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InvokeResult {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    /// Output past the byte cap was read and dropped.
+    pub truncated: bool,
+}
+
+/// Output a target may produce, per stream, unless it or the daemon sets another cap.
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 16 << 20;
+
+/// The daemon's side of an invoke: replay and single-use state, approvals, limits, and the output cap
+/// for targets that set none.
+pub struct InvokeContext<'a> {
+    pub replay: &'a ReplayCache,
+    pub once: &'a OnceStore,
+    pub approvals: &'a Approvals,
+    pub limiter: &'a RateLimiter,
+    pub slots: &'a TargetSlots,
+    /// The daemon's `freeze` switch, checked again once waiting is over.
+    pub frozen: &'a AtomicBool,
+    pub max_output_bytes: usize,
+    /// Where targets with memory or process `limits` get a transient cgroup; none without it.
+    pub cgroup_parent: Option<&'a Path>,
+}
+
+/// What the transport knows about the other end of a fire connection.
+#[derive(Clone, Debug, Default)]
+pub struct Caller {
+    /// Kernel-reported credentials; the unix fire socket only.
+    pub peer: Option<PeerCred>,
+    /// SHA-256 of the client certificate a TLS connection presented.
+    pub cert: Option<String>,
+    /// The connection itself, so a command still running when the caller leaves is killed.
+    pub hangup: Option<Hangup>,
+}
+
+/// A fire connection, polled while a target's command runs. The fd must stay open until the invoke returns.
+#[derive(Clone, Copy, Debug)]
+pub struct Hangup {
+    fd: RawFd,
+    events: libc::c_short,
+}
+
+impl Hangup {
+    /// For transports whose client half-closes after the request: only closing altogether counts.
+    pub fn on_close(fd: RawFd) -> Self {
+        Self { fd, events: 0 }
+    }
+
+    /// For transports whose client keeps both halves open for the response: closing either counts.
+    pub fn on_eof(fd: RawFd) -> Self {
+        Self {
+            fd,
+            events: libc::POLLRDHUP,
+        }
+    }
+
+    fn gone(self) -> bool {
+        let mut pfd = libc::pollfd {
+            fd: self.fd,
+            events: self.events,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut pfd, 1, 0) } > 0;
+        ready && pfd.revents & (self.events | libc::POLLHUP | libc::POLLERR) != 0
+    }
+}
+
+/// What cuts a run short: its deadline passing, or its caller hanging up.
+#[derive(Clone, Copy, Debug, Default)]
+struct Stop {
+    deadline: Option<Instant>,
+    hangup: Option<Hangup>,
+}
+
+impl Stop {
+    fn now(self) -> bool {
+        self.deadline.is_some_and(|d| Instant::now() >= d) || self.hangup.is_some_and(Hangup::gone)
+    }
+}
+
+/// Time-ordered, process-unique id for requests that arrive without one.
+pub fn new_request_id() -> String {
+    static SEQ: AtomicU32 = AtomicU32::new(0);
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    format!("{:012x}{:04x}{:04x}", crate::audit::now_ms(), std::process::id() & 0xffff, seq & 0xffff)
+}
+
+/// Client-supplied ids end up in syslog and journald, so keep them short and plain.
+pub fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -27,59 +282,723 @@ pub enum InvokeError {
     Unauthenticated,
     #[error("denied")]
     Denied,
+    #[error("peer credentials not allowed for this recruit")]
+    PeerDenied,
     #[error("unknown target")]
     UnknownTarget,
     #[error("bad request: {0}")]
     BadRequest(String),
     #[error("internal: {0}")]
     Internal(String),
+    #[error("replay: {0}")]
+    Replay(#[from] ReplayError),
+    #[error("{0}")]
+    RateLimited(#[from] RateLimited),
+    #[error("{0}")]
+    QuotaExceeded(#[from] QuotaExceeded),
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
+    #[error("secret '{0}' has expired; an operator must rotate it")]
+    SecretExpired(String),
+    #[error("no operator approved the request in time")]
+    ApprovalTimeout,
+    #[error("no second recruit sent the same request in time")]
+    TwoPersonTimeout,
+    #[error("turret is frozen; an operator must thaw it")]
+    Frozen,
+    #[error("{0}")]
+    Busy(#[from] Busy),
+    #[error("the caller hung up; the run was killed")]
+    Cancelled,
 }
 
-pub fn execute_invoke(bunker: &Bunker, payload: InvokePayload) -> Result<Vec<u8>, InvokeError> {
-    let authed = bunker
-        .agents
-        .get(&payload.agent_id)
-        .map(|s| s == &payload.agent_secret)
-        .unwrap_or(false);
-    if !authed {
-        return Err(InvokeError::Unauthenticated);
+impl InvokeError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            InvokeError::Unauthenticated => "unauthenticated",
+            InvokeError::Denied => "denied",
+            InvokeError::PeerDenied => "peer_denied",
+            InvokeError::UnknownTarget => "unknown_target",
+            InvokeError::BadRequest(_) => "bad_request",
+            InvokeError::Internal(_) => "internal",
+            InvokeError::Replay(_) => "replay",
+            InvokeError::RateLimited(_) => "rate_limited",
+            InvokeError::QuotaExceeded(_) => "quota_exceeded",
+            InvokeError::DeadlineExceeded => "deadline_exceeded",
+            InvokeError::SecretExpired(_) => "secret_expired",
+            InvokeError::ApprovalTimeout => "approval_timeout",
+            InvokeError::TwoPersonTimeout => "two_person_timeout",
+            InvokeError::Frozen => "frozen",
+            InvokeError::Busy(_) => "busy",
+            InvokeError::Cancelled => "cancelled",
+        }
     }
+}
 
-    let allowed = bunker
-        .permissions
-        .get(&payload.agent_id)
-        .map(|s| s.contains(&payload.target))
-        .unwrap_or(false);
-    if !allowed {
+pub fn execute_invoke(
+    bunker: &Bunker,
+    cx: &InvokeContext,
+    caller: &Caller,
+    payload: InvokePayload,
+) -> Result<InvokeResult, InvokeError> {
+    // Checked before the secret, so a disallowed local user learns nothing about it.
+    // Without peer credentials (HTTP, vsock, TLS) a restricted recruit cannot fire at all.
+    if let Some(allow) = bunker.peers.get(&payload.agent_id) {
+        if !caller.peer.as_ref().is_some_and(|p| allow.permits(p)) {
+            return Err(InvokeError::PeerDenied);
+        }
+    }
+    authenticate(bunker, cx.replay, caller.cert.as_deref(), &payload)?;
+    if let Some(limit) = bunker.limits.get(&payload.agent_id) {
+        cx.limiter.check(&payload.agent_id, limit, Instant::now())?;
+    }
+
+    let grants: Vec<&String> = bunker
+        .grants_for(&payload.agent_id, &payload.target, crate::audit::now_ms())
+        .collect();
+    if grants.is_empty() {
         return Err(InvokeError::Denied);
     }
+    // Nothing runs, so a dry run spends no quota or single-use grant and waits on no one.
+    if payload.dry_run {
+        let def = bunker.targets.get(&payload.target).ok_or(InvokeError::UnknownTarget)?;
+        return dry_run(def, payload, &bunker.secrets);
+    }
+    let quota = bunker.limits.get(&payload.agent_id).and_then(|l| l.targets.get(&payload.target));
+    if let Some(quota) = quota {
+        cx.limiter.check_quota(&payload.agent_id, &payload.target, quota, crate::audit::now_ms())?;
+    }
+    // A standing grant fires as often as it likes; otherwise this invoke holds one single-use grant,
+    // spent if it succeeds and released if it fails.
+    let claim = if grants.iter().any(|g| bunker.single_use_issued(&payload.agent_id, g).is_none()) {
+        None
+    } else {
+        let claimed = grants.iter().find_map(|g| {
+            let issued = bunker.single_use_issued(&payload.agent_id, g)?;
+            cx.once.claim(&payload.agent_id, g, issued)
+        });
+        Some(claimed.ok_or(InvokeError::Denied)?)
+    };
 
     let def = bunker
         .targets
         .get(&payload.target)
         .ok_or(InvokeError::UnknownTarget)?;
+    if let Some(name) = bunker.expired_secret(def, crate::audit::now_ms()) {
+        return Err(InvokeError::SecretExpired(name));
+    }
+
+    let deadline = match payload.deadline_ms {
+        Some(ms) => {
+            let left = ms.checked_sub(crate::audit::now_ms()).filter(|&left| left > 0);
+            Some(Instant::now() + Duration::from_millis(left.ok_or(InvokeError::DeadlineExceeded)?))
+        }
+        None => None,
+    };
+    let expired = || deadline.is_some_and(|d| Instant::now() >= d);
+
+    // Malformed payloads are refused up front rather than put to an operator or a second recruit.
+    if def.require_approval || def.two_person_window_secs.is_some() {
+        check_shape(def, &payload).map_err(InvokeError::BadRequest)?;
+    }
+
+    let mut second = None;
+    if let Some(secs) = def.two_person_window_secs {
+        info!("awaiting a second recruit");
+        let window = Duration::from_secs(secs);
+        match cx.approvals.pair(&payload.target, &payload.params_hash(), &payload.agent_id, window, deadline) {
+            Ok(Pair::Run(s)) => second = Some(s),
+            Ok(Pair::Ran(outcome)) => {
+                let res = outcome.map_err(|e| InvokeError::Internal(format!("run by the second recruit: {e}")))?;
+                if let Some(Err(e)) = claim.map(Claim::spend) {
+                    warn!("{e}");
+                }
+                return Ok(res);
+            }
+            Ok(Pair::TimedOut) if expired() => return Err(InvokeError::DeadlineExceeded),
+            Ok(Pair::TimedOut) => return Err(InvokeError::TwoPersonTimeout),
+            Err(e) => return Err(InvokeError::BadRequest(e)),
+        }
+    }
+
+    if def.require_approval {
+        let request_id = payload.request_id.clone().unwrap_or_else(new_request_id);
+        info!("awaiting approval");
+        match cx.approvals.wait(&request_id, &payload.agent_id, &payload.target, deadline) {
+            Ok(Wait::Approved) => {}
+            Ok(Wait::TimedOut) if expired() => return Err(InvokeError::DeadlineExceeded),
+            Ok(Wait::TimedOut) => return Err(InvokeError::ApprovalTimeout),
+            Err(e) => return Err(InvokeError::BadRequest(e)),
+        }
+    }
+
+    // Held until the run ends; approval and pairing above do not count against it.
+    let _running = match def.max_concurrent {
+        Some(max) => {
+            let timeout = Instant::now() + def.queue_timeout_secs.map_or(DEFAULT_QUEUE_TIMEOUT, Duration::from_secs);
+            let until = deadline.map_or(timeout, |d| d.min(timeout));
+            match cx.slots.acquire(&payload.target, max, def.max_queued.unwrap_or(0), until) {
+                Ok(running) => Some(running),
+                Err(_) if expired() => return Err(InvokeError::DeadlineExceeded),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        None => None,
+    };
+
+    // Frozen while it waited on an operator, a second recruit or a slot.
+    if cx.frozen.load(Ordering::SeqCst) {
+        if let Some(s) = second {
+            s.report(Err(InvokeError::Frozen.to_string()));
+        }
+        return Err(InvokeError::Frozen);
+    }
+
+    let max_output = def.max_output_bytes.unwrap_or(cx.max_output_bytes);
+    let base_env = bunker.base_env(def);
+    let stop = Stop {
+        deadline,
+        hangup: caller.hangup,
+    };
+    let res = run_def(def, payload, &bunker.secrets, &base_env, stop, max_output, cx.cgroup_parent);
+    if let Some(s) = second {
+        s.report(res.as_ref().map(Clone::clone).map_err(ToString::to_string));
+    }
+    let res = res?;
+    if let Some(Err(e)) = claim.map(Claim::spend) {
+        warn!("{e}");
+    }
+    Ok(res)
+}
+
+/// Conform `payload` to `def` and run it until `stop`, keeping up to `max_output` bytes of each stream.
+fn run_def(
+    def: &TargetDef,
+    payload: InvokePayload,
+    secrets: &BTreeMap<String, String>,
+    base_env: &BTreeMap<String, String>,
+    stop: Stop,
+    max_output: usize,
+    cgroup_parent: Option<&Path>,
+) -> Result<InvokeResult, InvokeError> {
+    let expired = || stop.deadline.is_some_and(|d| Instant::now() >= d);
+    // Targets and kinds may echo what they were given; none of it goes back to the rookie.
+    let scrub = Redactor::new(secrets.values());
+    let started = Instant::now();
+    let mut res = if let Some(kind) = def.kind.as_ref().filter(|k| !k.runs_command()) {
+        check_shape(def, &payload).map_err(InvokeError::BadRequest)?;
+        let res = run_kind(kind, payload, secrets);
+        if expired() {
+            return Err(InvokeError::DeadlineExceeded);
+        }
+        let mut res = res.map_err(|e| match e {
+            InvokeError::Internal(m) => InvokeError::Internal(scrub.redact(&m).into_owned()),
+            e => e,
+        })?;
+        res.stderr = scrub.redact_bytes(&res.stderr);
+        res
+    } else {
+        let c = conform_payload(def, payload, secrets, false).map_err(InvokeError::BadRequest)?;
+        check_container_env(def, &c)?;
+        let res = run_conformed(def, c, base_env, stop, max_output, cgroup_parent);
+        // Killed at the deadline, or finished too late for the agent to still be waiting.
+        if expired() {
+            return Err(InvokeError::DeadlineExceeded);
+        }
+        if stop.hangup.is_some_and(Hangup::gone) {
+            return Err(InvokeError::Cancelled);
+        }
+        let mut res = res.map_err(|e| InvokeError::Internal(scrub.redact(&e).into_owned()))?;
+        res.stderr = scrub.redact_bytes(&res.stderr);
+        if res.exit_code != Some(0) && !(def.allow_failure && res.exit_code.is_some()) {
+            return Err(InvokeError::Internal(failure_message(&res.stderr)));
+        }
+        res
+    };
+    res.duration_ms = started.elapsed().as_millis() as u64;
+    Ok(res)
+}
+
+/// Report what `payload` would run instead of running it.
+fn dry_run(
+    def: &TargetDef,
+    payload: InvokePayload,
+    secrets: &BTreeMap<String, String>,
+) -> Result<InvokeResult, InvokeError> {
+    let plan = resolve(def, payload, secrets)?;
+    let mut stdout = serde_json::to_vec_pretty(&plan).map_err(|e| InvokeError::Internal(e.to_string()))?;
+    stdout.push(b'\n');
+    Ok(InvokeResult {
+        stdout,
+        ..InvokeResult::default()
+    })
+}
+
+/// Resolve `payload` as [`run_def`] would, with secrets masked.
+pub(crate) fn resolve(
+    def: &TargetDef,
+    payload: InvokePayload,
+    secrets: &BTreeMap<String, String>,
+) -> Result<DryRun, InvokeError> {
+    if def.kind.as_ref().is_some_and(|k| !k.runs_command()) {
+        check_shape(def, &payload).map_err(InvokeError::BadRequest)?;
+        return Ok(DryRun {
+            command: crate::show::target_label(def),
+            argv: Vec::new(),
+            env_keys: Vec::new(),
+            stdin_len: payload.stdin.map_or(0, |s| s.len()),
+            pipe: Vec::new(),
+        });
+    }
+    // Secrets can change stdin's length, so measure the real thing.
+    let stdin_len = conform_payload(def, payload.clone(), secrets, false).map_err(InvokeError::BadRequest)?.stdin.len();
+    let c = conform_payload(def, payload, secrets, true).map_err(InvokeError::BadRequest)?;
+    check_container_env(def, &c)?;
+    Ok(match &def.kind {
+        Some(TargetKind::Container {
+            container,
+            runtime,
+            user,
+            workdir,
+        }) => DryRun {
+            command: find_on_daemon_path(runtime.program()),
+            argv: container_exec_argv(container, user.as_deref(), workdir.as_deref(), &c),
+            env_keys: runtime_env(c.env).into_keys().collect(),
+            stdin_len,
+            pipe: Vec::new(),
+        },
+        _ => {
+            let mut env = c.env;
+            if let Some((key, _)) = c.secret_fd {
+                env.insert(key, String::new());
+            }
+            let pipe = c.pipe.into_iter().map(|p| DryRunStep {
+                command: p.command,
+                argv: p.argv,
+                env_keys: p.env.into_keys().collect(),
+            });
+            DryRun {
+                command: c.command,
+                argv: c.argv,
+                env_keys: env.into_keys().collect(),
+                stdin_len,
+                pipe: pipe.collect(),
+            }
+        }
+    })
+}
+
+/// Container env keys become `-e KEY` arguments to the runtime, so they must not read as flags or assignments.
+fn check_container_env(def: &TargetDef, c: &Conformed) -> Result<(), InvokeError> {
+    if matches!(def.kind, Some(TargetKind::Container { .. })) {
+        if let Some(k) = c.env.keys().find(|k| k.is_empty() || k.starts_with('-') || k.contains('=')) {
+            return Err(InvokeError::BadRequest(format!("non-conforming payload: bad env key '{k}'")));
+        }
+    }
+    Ok(())
+}
+
+fn run_conformed(
+    def: &TargetDef,
+    c: Conformed,
+    base_env: &BTreeMap<String, String>,
+    stop: Stop,
+    max_output: usize,
+    cgroup_parent: Option<&Path>,
+) -> Result<InvokeResult, String> {
+    match &def.kind {
+        Some(TargetKind::Container {
+            container,
+            runtime,
+            user,
+            workdir,
+        }) => {
+            let argv = container_exec_argv(container, user.as_deref(), workdir.as_deref(), &c);
+            let env = runtime_env(c.env);
+            let program = find_on_daemon_path(runtime.program());
+            run_command(&program, &argv, &env, &c.stdin, &Launch::default(), stop, max_output)
+        }
+        _ => {
+            let t = &def.transform;
+            let commands = std::iter::once(&c.command).chain(c.pipe.iter().map(|p| &p.command));
+            for (command, (_, sha)) in commands.zip(t.commands()) {
+                if let Some(sha) = sha {
+                    crate::pin::verify(command, sha)?;
+                }
+            }
+            let launch = Launch {
+                base_env: Some(base_env),
+                secret_fd: c.secret_fd.as_ref().map(|(env, bytes)| (env.as_str(), bytes.as_slice())),
+                cwd: t.cwd.as_deref(),
+                user: t.run_as_user.as_deref(),
+                group: t.run_as_group.as_deref(),
+                limits: def.limits.as_ref(),
+                cgroup_parent,
+                sandbox: def.sandbox.as_ref(),
+            };
+            if c.pipe.is_empty() {
+                return run_command(&c.command, &c.argv, &c.env, &c.stdin, &launch, stop, max_output);
+            }
+            run_pipeline(&c, &launch, stop, max_output)
+        }
+    }
+}
+
+/// Key recruits must send a valid, fresh, unreplayed signature, certificate recruits must have presented their
+/// pinned client certificate, and the rest their shared secret.
+fn authenticate(
+    bunker: &Bunker,
+    replay: &ReplayCache,
+    cert: Option<&str>,
+    payload: &InvokePayload,
+) -> Result<(), InvokeError> {
+    if let Some(pin) = bunker.agent_certs.get(&payload.agent_id) {
+        // The TLS handshake proved the client holds the certificate's key.
+        return if cert == Some(pin.as_str()) { Ok(()) } else { Err(InvokeError::Unauthenticated) };
+    }
+    if let Some(key) = bunker.agent_keys.get(&payload.agent_id) {
+        let (Some(sig), Some(ts_ms), Some(nonce)) = (&payload.signature, payload.ts_ms, &payload.nonce) else {
+            return Err(InvokeError::Unauthenticated);
+        };
+        let verified = ssh_key::PublicKey::from_openssh(key).ok().is_some_and(|key| {
+            ssh_key::SshSig::from_pem(sig)
+                .and_then(|sig| key.verify(SIG_NAMESPACE, &payload.signed_bytes(), &sig))
+                .is_ok()
+        });
+        if !verified {
+            return Err(InvokeError::Unauthenticated);
+        }
+        replay.check_and_record(&payload.agent_id, nonce, ts_ms, crate::audit::now_ms())?;
+        return Ok(());
+    }
+
+    let authed = bunker
+        .agents
+        .get(&payload.agent_id)
+        .is_some_and(|stored| crate::agent_secret::verify(stored, &payload.agent_secret));
+    if !authed {
+        return Err(InvokeError::Unauthenticated);
+    }
+    Ok(())
+}
+
+/// `exec -i [-u user] [-w dir] -e KEY.. <container> <command> <argv..>`. Env values stay
+/// in the runtime's own environment (`-e KEY` without `=`), so secrets never hit argv.
+fn container_exec_argv(container: &str, user: Option<&str>, workdir: Option<&str>, c: &Conformed) -> Vec<String> {
+    let mut argv = vec!["exec".to_string(), "-i".to_string()];
+    if let Some(u) = user {
+        argv.extend(["-u".to_string(), u.to_string()]);
+    }
+    if let Some(w) = workdir {
+        argv.extend(["-w".to_string(), w.to_string()]);
+    }
+    for k in c.env.keys() {
+        argv.extend(["-e".to_string(), k.clone()]);
+    }
+    argv.push(container.to_string());
+    argv.push(c.command.clone());
+    argv.extend(c.argv.iter().cloned());
+    argv
+}
+
+/// The PATH a target command sees unless `base_env` sets one, and where a bare `out_command` is looked up.
+pub const TARGET_PATH: &str = "/run/current-system/sw/bin:/usr/bin:/bin";
+
+/// Targets get a fixed PATH, but the runtime CLI itself is located the way the operator's shell would.
+pub(crate) fn find_on_daemon_path(program: &str) -> String {
+    std::env::var_os("PATH")
+        .and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(program))
+                .find(|p| p.is_file())
+        })
+        .map_or_else(|| program.to_string(), |p| p.to_string_lossy().into_owned())
+}
+
+/// The runtime CLI needs its config dir and, for rootless podman, the user runtime dir.
+fn runtime_env(mut env: BTreeMap<String, String>) -> BTreeMap<String, String> {
+    for key in ["HOME", "XDG_RUNTIME_DIR", "DOCKER_HOST", "CONTAINER_HOST"] {
+        if let Ok(v) = std::env::var(key) {
+            env.entry(key.to_string()).or_insert(v);
+        }
+    }
+    env
+}
+
+/// Kinds that act on the bunker's secrets themselves rather than running `out_command`.
+fn run_kind(
+    kind: &TargetKind,
+    payload: InvokePayload,
+    secrets: &BTreeMap<String, String>,
+) -> Result<InvokeResult, InvokeError> {
+    let render_all = |m: &BTreeMap<String, String>| {
+        m.iter()
+            .map(|(k, v)| Ok((k.clone(), render_secret_tokens(v, secrets)?)))
+            .collect::<Result<BTreeMap<_, _>, String>>()
+            .map_err(InvokeError::BadRequest)
+    };
+    // Remote targets already carry the far side's stderr and exit status; the rest only produce stdout.
+    let stdout_only = |stdout| InvokeResult {
+        stdout,
+        ..InvokeResult::default()
+    };
+    match kind {
+        TargetKind::K8sSecret {
+            namespace,
+            name,
+            data,
+            kubeconfig,
+            context,
+        } => secret_sync::apply_k8s_secret(
+            namespace,
+            name,
+            &render_all(data)?,
+            kubeconfig.as_deref(),
+            context.as_deref(),
+        )
+        .map(stdout_only),
+        TargetKind::SecretFiles { dir, files, mode } => {
+            secret_sync::write_secret_files(dir, &render_all(files)?, *mode).map(stdout_only)
+        }
+        TargetKind::Remote {
+            ssh,
+            port,
+            identity,
+            bunker,
+            dir,
+            turret,
+            rookie,
+            secret,
+            target,
+        } => {
+            let forwarded = InvokePayload {
+                agent_id: rookie.clone(),
+                agent_secret: render_secret_tokens(secret, secrets).map_err(InvokeError::BadRequest)?,
+                target: target.clone(),
+                ts_ms: None,
+                nonce: None,
+                signature: None,
+                ..payload
+            };
+            let hop = remote::Hop {
+                ssh,
+                port: *port,
+                identity: identity.as_deref(),
+                bunker,
+                dir,
+                turret,
+            };
+            remote::forward(&hop, &forwarded)
+        }
+        TargetKind::Wasm {
+            module,
+            fuel,
+            max_memory,
+            env,
+        } => {
+            let params = serde_json::json!({
+                "argv": payload.argv.unwrap_or_default(),
+                "env": payload.env.unwrap_or_default(),
+                "stdin": payload.stdin.unwrap_or_default(),
+            });
+            let env = render_all(env)?;
+            run_wasm(module, *fuel, *max_memory, &env, params.to_string().into_bytes()).map(stdout_only)
+        }
+        TargetKind::FileRead { path, max_bytes } => {
+            let path = file_path(path, secrets, payload.params)?;
+            crate::files::read_file(&path, *max_bytes).map(stdout_only)
+        }
+        TargetKind::FileWrite {
+            path,
+            max_bytes,
+            mode,
+            append,
+        } => {
+            let path = file_path(path, secrets, payload.params)?;
+            let content = payload.stdin.unwrap_or_default();
+            if content.len() as u64 > *max_bytes {
+                return Err(InvokeError::BadRequest(format!("stdin is over max_bytes ({max_bytes})")));
+            }
+            crate::files::write_file(&path, content.as_bytes(), *mode, *append).map(stdout_only)
+        }
+        TargetKind::Container { .. } => unreachable!("container targets run a command"),
+    }
+    .map_err(InvokeError::Internal)
+}
 
-    let (command, argv, env_map, stdin_bytes) = conform_payload(def, payload, &bunker.secrets)
-        .map_err(InvokeError::BadRequest)?;
+/// A `file_read`/`file_write` path with its tokens rendered; where params lead it astray, the payload is at fault.
+fn file_path(
+    tmpl: &str,
+    secrets: &BTreeMap<String, String>,
+    params: Option<Vec<String>>,
+) -> Result<PathBuf, InvokeError> {
+    let path = render_tokens(tmpl, secrets, Some(&params.unwrap_or_default()), false).map_err(InvokeError::BadRequest)?;
+    crate::files::checked(&path).map(Path::to_path_buf).map_err(InvokeError::BadRequest)
+}
 
-    run_target(&command, &argv, &env_map, &stdin_bytes).map_err(InvokeError::Internal)
+#[cfg(feature = "wasm")]
+fn run_wasm(
+    module: &str,
+    fuel: u64,
+    max_memory: usize,
+    env: &BTreeMap<String, String>,
+    stdin: Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    crate::wasm::run_module(module, fuel, max_memory, env, stdin)
 }
 
+#[cfg(not(feature = "wasm"))]
+fn run_wasm(
+    _module: &str,
+    _fuel: u64,
+    _max_memory: usize,
+    _env: &BTreeMap<String, String>,
+    _stdin: Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    Err("wasm targets need turret built with the `wasm` feature".to_string())
+}
+
+struct Conformed {
+    command: String,
+    argv: Vec<String>,
+    env: BTreeMap<String, String>,
+    stdin: Vec<u8>,
+    /// `out_secret_fd`: the env var that names the fd, and what the fd holds.
+    secret_fd: Option<(String, Vec<u8>)>,
+    pipe: Vec<Piped>,
+}
+
+/// A rendered `pipe` step.
+struct Piped {
+    command: String,
+    argv: Vec<String>,
+    env: BTreeMap<String, String>,
+}
+
+/// With `mask`, secrets render as `«secret:NAME»` placeholders, for dry runs.
 fn conform_payload(
     def: &TargetDef,
     payload: InvokePayload,
     secrets: &BTreeMap<String, String>,
-) -> Result<(String, Vec<String>, BTreeMap<String, String>, Vec<u8>), String> {
-    let has_command = payload.command.is_some();
-    let has_argv = payload.argv.is_some();
-    let has_env = payload.env.is_some();
-    let has_stdin = payload.stdin.is_some();
+    mask: bool,
+) -> Result<Conformed, String> {
+    check_shape(def, &payload)?;
+
+    let command = render_tokens(&def.transform.out_command, secrets, None, mask)?;
+    if command.trim().is_empty() {
+        return Err("non-conforming payload: command resolved empty".to_string());
+    }
 
+    let params = payload.params.unwrap_or_default();
+    let mut argv = payload.argv.unwrap_or_default();
+    if !argv.is_empty() {
+        let pairs = render_replacements(&def.transform.out_argv_replace, secrets, &params, mask)?;
+        for item in &mut argv {
+            *item = replace_once(item, &pairs);
+        }
+    }
+
+    let mut env = payload.env.unwrap_or_default();
+    for (k_tmpl, v_tmpl) in &def.transform.out_env {
+        let k = render_tokens(k_tmpl, secrets, None, mask)?;
+        let v = render_tokens(v_tmpl, secrets, None, mask)?;
+        env.insert(k, v);
+    }
+    let mut pipe = Vec::with_capacity(def.transform.pipe.len());
+    for step in &def.transform.pipe {
+        let mut env = BTreeMap::new();
+        for (k_tmpl, v_tmpl) in &step.out_env {
+            env.insert(render_tokens(k_tmpl, secrets, None, mask)?, render_tokens(v_tmpl, secrets, None, mask)?);
+        }
+        if let Some(id) = &payload.trace_id {
+            env.insert(TRACE_ID_ENV.to_string(), id.clone());
+        }
+        pipe.push(Piped {
+            command: render_tokens(&step.out_command, secrets, None, mask)?,
+            argv: step
+                .out_argv
+                .iter()
+                .map(|a| render_tokens(a, secrets, Some(&params), mask))
+                .collect::<Result<_, _>>()?,
+            env,
+        });
+    }
+    if let Some(id) = payload.trace_id {
+        env.insert(TRACE_ID_ENV.to_string(), id);
+    }
+
+    let pairs = render_replacements(&def.transform.out_stdin_replace, secrets, &params, mask)?;
+    let stdin_s = replace_once(&payload.stdin.unwrap_or_default(), &pairs);
+
+    let secret_fd = match &def.transform.out_secret_fd {
+        Some(fd) => match secrets.get(&fd.name) {
+            Some(_) if mask => Some((fd.env.clone(), Vec::new())),
+            Some(value) => Some((fd.env.clone(), value.clone().into_bytes())),
+            None => return Err(format!("non-conforming payload: unknown secret '{}'", fd.name)),
+        },
+        None => None,
+    };
+
+    Ok(Conformed {
+        command,
+        argv,
+        env,
+        stdin: stdin_s.into_bytes(),
+        secret_fd,
+        pipe,
+    })
+}
+
+fn render_replacements<'a>(
+    replace: &'a BTreeMap<String, String>,
+    secrets: &BTreeMap<String, String>,
+    params: &[String],
+    mask: bool,
+) -> Result<Vec<(&'a str, String)>, String> {
+    replace
+        .iter()
+        .map(|(from, to)| Ok((from.as_str(), render_tokens(to, secrets, Some(params), mask)?)))
+        .collect()
+}
+
+/// Replace each `from` in `s` in one left-to-right pass, the longer `from` winning where two start together.
+/// Inserted text is not searched again, so a secret or param containing a `from` is left as it is.
+fn replace_once(s: &str, pairs: &[(&str, String)]) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    loop {
+        let hit = pairs
+            .iter()
+            .filter(|(from, _)| !from.is_empty())
+            .filter_map(|(from, to)| rest.find(from).map(|i| (i, from.len(), to)))
+            .min_by_key(|&(i, len, _)| (i, std::cmp::Reverse(len)));
+        let Some((i, len, to)) = hit else {
+            out.push_str(rest);
+            return out;
+        };
+        out.push_str(&rest[..i]);
+        out.push_str(to);
+        rest = &rest[i + len..];
+    }
+}
+
+fn check_shape(def: &TargetDef, payload: &InvokePayload) -> Result<(), String> {
+    check_fields(def, payload)?;
+    check_placeholders(def, payload)?;
+    check_argv_values(def, payload)?;
+    check_params(def, payload)?;
+    check_env(def, payload)?;
+    check_stdin(def, payload)
+}
+
+/// The shape's allow, forbid and require lists.
+pub(crate) fn check_fields(def: &TargetDef, payload: &InvokePayload) -> Result<(), String> {
     let present = [
-        ("command", has_command),
-        ("argv", has_argv),
-        ("env", has_env),
-        ("stdin", has_stdin),
+        ("command", payload.command.is_some()),
+        ("argv", payload.argv.is_some()),
+        ("env", payload.env.is_some()),
+        ("stdin", payload.stdin.is_some()),
+        ("params", payload.params.is_some()),
     ];
 
     for (name, is_present) in present {
@@ -93,7 +1012,10 @@ fn conform_payload(
             return Err(format!("non-conforming payload: field '{name}' is required"));
         }
     }
+    Ok(())
+}
 
+pub(crate) fn check_placeholders(def: &TargetDef, payload: &InvokePayload) -> Result<(), String> {
     if let Some(expect) = def.shape.argv_placeholders {
         let argv = payload
             .argv
@@ -106,53 +1028,154 @@ fn conform_payload(
             ));
         }
     }
+    Ok(())
+}
 
-    let command = render_secret_tokens(&def.transform.out_command, secrets)?;
-    if command.trim().is_empty() {
-        return Err("non-conforming payload: command resolved empty".to_string());
+/// `argv_pattern` and `argv_choices`, for the positions argv reaches. Values are not echoed back.
+pub(crate) fn check_argv_values(def: &TargetDef, payload: &InvokePayload) -> Result<(), String> {
+    let argv = payload.argv.as_deref().unwrap_or_default();
+    for (pos, re) in def.shape.argv_regexes()? {
+        if argv.get(pos).is_some_and(|item| !re.is_match(item)) {
+            return Err(format!("non-conforming payload: argv[{pos}] does not match its pattern"));
+        }
+    }
+    for (pos, choices) in def.shape.argv_choice_sets()? {
+        if argv.get(pos).is_some_and(|item| !choices.contains(item)) {
+            return Err(format!("non-conforming payload: argv[{pos}] is not one of its choices"));
+        }
     }
+    Ok(())
+}
 
-    let mut argv = payload.argv.unwrap_or_default();
-    for item in &mut argv {
-        for (from, to_tmpl) in &def.transform.out_argv_replace {
-            let to = render_secret_tokens(to_tmpl, secrets)?;
-            *item = item.replace(from, &to);
+/// As many params as the transform's placeholders take, each matching its `params_pattern`. Values are not echoed.
+pub(crate) fn check_params(def: &TargetDef, payload: &InvokePayload) -> Result<(), String> {
+    let params = payload.params.as_deref().unwrap_or_default();
+    let expect = def.params_taken();
+    if params.len() != expect {
+        return Err(format!("non-conforming payload: {} params sent, the target takes {expect}", params.len()));
+    }
+    for (pos, re) in def.shape.params_regexes()? {
+        if params.get(pos).is_some_and(|p| !re.is_match(p)) {
+            return Err(format!("non-conforming payload: param {pos} does not match its pattern"));
         }
     }
+    Ok(())
+}
 
-    let mut env = payload.env.unwrap_or_default();
-    for (k_tmpl, v_tmpl) in &def.transform.out_env {
-        let k = render_secret_tokens(k_tmpl, secrets)?;
-        let v = render_secret_tokens(v_tmpl, secrets)?;
-        env.insert(k, v);
+/// `env_forbid_keys`, `env_allow_keys` and `env_pattern`. Values are not echoed back.
+pub(crate) fn check_env(def: &TargetDef, payload: &InvokePayload) -> Result<(), String> {
+    let Some(env) = &payload.env else {
+        return Ok(());
+    };
+    if let Some(key) = env.keys().find(|k| !def.shape.env_key_allowed(k)) {
+        return Err(format!("non-conforming payload: env key '{key}' is not allowed"));
+    }
+    for (key, re) in def.shape.env_regexes()? {
+        if env.get(key).is_some_and(|v| !re.is_match(v)) {
+            return Err(format!("non-conforming payload: env '{key}' does not match its pattern"));
+        }
     }
+    Ok(())
+}
 
-    let mut stdin_s = payload.stdin.unwrap_or_default();
-    for (from, to_tmpl) in &def.transform.out_stdin_replace {
-        let to = render_secret_tokens(to_tmpl, secrets)?;
-        stdin_s = stdin_s.replace(from, &to);
+/// `max_stdin_bytes` and `stdin_schema`, on stdin as sent.
+pub(crate) fn check_stdin(def: &TargetDef, payload: &InvokePayload) -> Result<(), String> {
+    let Some(stdin) = &payload.stdin else {
+        return Ok(());
+    };
+    if let Some(max) = def.shape.max_stdin_bytes.filter(|&max| stdin.len() > max) {
+        return Err(format!("non-conforming payload: stdin is {} bytes, over the {max} byte limit", stdin.len()));
+    }
+    match &def.shape.stdin_schema {
+        Some(schema) => check_stdin_schema(schema, stdin),
+        None => Ok(()),
     }
+}
+
+#[cfg(feature = "jsonschema")]
+fn check_stdin_schema(schema: &str, stdin: &str) -> Result<(), String> {
+    crate::schema::check(schema, stdin)
+}
+
+#[cfg(not(feature = "jsonschema"))]
+fn check_stdin_schema(_schema: &str, _stdin: &str) -> Result<(), String> {
+    Err("stdin_schema needs turret built with the `jsonschema` feature".to_string())
+}
+
+#[cfg(feature = "sandbox")]
+fn confine(cmd: &mut Command, sandbox: &TargetSandbox) -> Result<(), String> {
+    crate::sandbox::confine(cmd, sandbox)
+}
 
-    Ok((command, argv, env, stdin_s.into_bytes()))
+#[cfg(not(feature = "sandbox"))]
+fn confine(_cmd: &mut Command, _sandbox: &TargetSandbox) -> Result<(), String> {
+    Err("sandbox needs turret built with the `sandbox` feature".to_string())
 }
 
 fn render_secret_tokens(tmpl: &str, secrets: &BTreeMap<String, String>) -> Result<String, String> {
+    render_tokens(tmpl, secrets, None, false)
+}
+
+/// What may follow a token's name, applied left to right: `{NAME|b64}`, `{NAME|trim|json}`.
+pub(crate) const TEMPLATE_FILTERS: &[&str] = &["b64", "json", "trim", "urlencode"];
+
+fn apply_filter(filter: &str, value: &str) -> Result<String, String> {
+    match filter {
+        "b64" => Ok(base64::engine::general_purpose::STANDARD.encode(value)),
+        // A whole JSON string, quotes included.
+        "json" => serde_json::to_string(value).map_err(|e| e.to_string()),
+        "trim" => Ok(value.trim().to_string()),
+        "urlencode" => Ok(value
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => char::from(b).to_string(),
+                _ => format!("%{b:02X}"),
+            })
+            .collect()),
+        _ => Err(format!("non-conforming payload: unknown template filter '{filter}'")),
+    }
+}
+
+/// Fill `{SECRET}` tokens, and `{0}`, `{1}`, ... from `params` where the template may take them, then run any
+/// filters. With `mask`, secrets render as `«secret:NAME|filters»`. Inserted values are not scanned again.
+fn render_tokens(
+    tmpl: &str,
+    secrets: &BTreeMap<String, String>,
+    params: Option<&[String]>,
+    mask: bool,
+) -> Result<String, String> {
     let mut out = tmpl.to_string();
     let mut pos = 0usize;
-    loop {
-        let Some(start_rel) = out[pos..].find('{') else {
-            break;
-        };
+    while let Some(start_rel) = out[pos..].find('{') {
         let start = pos + start_rel;
         let Some(end_rel) = out[start..].find('}') else {
             return Err("non-conforming payload: malformed template token".to_string());
         };
         let end = start + end_rel;
-        let name = &out[start + 1..end];
-        let Some(value) = secrets.get(name) else {
-            return Err(format!("non-conforming payload: unknown secret '{name}'"));
+        let token = &out[start + 1..end];
+        if !is_template_token(token) {
+            pos = start + 1;
+            continue;
+        }
+        let mut filters = token.split('|');
+        let name = filters.next().unwrap_or_default();
+        let value = match (param_index(name), params) {
+            (Some(i), Some(params)) => params
+                .get(i)
+                .cloned()
+                .ok_or_else(|| format!("non-conforming payload: no param {i}"))?,
+            _ if !secrets.contains_key(name) => return Err(format!("non-conforming payload: unknown secret '{name}'")),
+            // Names its filters rather than running them.
+            _ if mask => {
+                let masked = format!("«secret:{token}»");
+                out.replace_range(start..=end, &masked);
+                pos = start + masked.len();
+                continue;
+            }
+            _ => secrets[name].clone(),
         };
-        out.replace_range(start..=end, value);
+        let value = filters.try_fold(value, |v, f| apply_filter(f, &v))?;
+        out.replace_range(start..=end, &value);
         pos = start + value.len();
     }
     Ok(out)
@@ -176,12 +1199,143 @@ fn count_placeholders(s: &str) -> usize {
     count
 }
 
-fn run_target(
+/// Run a command to completion; a nonzero exit is an error carrying its stderr.
+pub(crate) fn run_target(
     command: &str,
     argv: &[String],
     env: &BTreeMap<String, String>,
     stdin_bytes: &[u8],
 ) -> Result<Vec<u8>, String> {
+    let launch = Launch::default();
+    let res = run_command(command, argv, env, stdin_bytes, &launch, Stop::default(), DEFAULT_MAX_OUTPUT_BYTES)?;
+    if res.exit_code != Some(0) {
+        return Err(failure_message(&res.stderr));
+    }
+    Ok(res.stdout)
+}
+
+/// How a target's command is started, beyond its argv, env and stdin.
+#[derive(Clone, Copy, Default)]
+struct Launch<'a> {
+    /// What the env starts from before `env`; just `PATH=`[`TARGET_PATH`] without one.
+    base_env: Option<&'a BTreeMap<String, String>>,
+    /// An env var name, and the bytes the child can read from the fd number it holds.
+    secret_fd: Option<(&'a str, &'a [u8])>,
+    cwd: Option<&'a str>,
+    user: Option<&'a str>,
+    group: Option<&'a str>,
+    limits: Option<&'a TargetLimits>,
+    cgroup_parent: Option<&'a Path>,
+    sandbox: Option<&'a TargetSandbox>,
+}
+
+/// Run a command with a cleared env plus the base env; `exit_code` is `None` when a signal killed it.
+/// At `stop` the child is killed. Each stream keeps its first `max_output` bytes.
+fn run_command(
+    command: &str,
+    argv: &[String],
+    env: &BTreeMap<String, String>,
+    stdin_bytes: &[u8],
+    launch: &Launch,
+    stop: Stop,
+    max_output: usize,
+) -> Result<InvokeResult, String> {
+    let (child, _cgroup) = prepare(command, argv, env, launch)?.spawn()?;
+    let out = wait_capped(child, stdin_bytes, stop, max_output)?;
+    if out.truncated {
+        warn!(max_output, "target output truncated");
+    }
+    Ok(InvokeResult {
+        stdout: out.stdout,
+        stderr: out.stderr,
+        exit_code: out.status.code(),
+        duration_ms: 0,
+        truncated: out.truncated,
+    })
+}
+
+/// Run `c.command` and its `pipe` steps side by side, each reading the previous one's stdout, like a shell pipeline
+/// under `set -o pipefail`: the exit code is the last nonzero one. Stderr is every step's in turn, capped as a whole.
+fn run_pipeline(c: &Conformed, launch: &Launch, stop: Stop, max_output: usize) -> Result<InvokeResult, String> {
+    // Only the first command gets the secret fd.
+    let rest = Launch {
+        secret_fd: None,
+        ..*launch
+    };
+    let steps = std::iter::once((&c.command, &c.argv, &c.env, launch))
+        .chain(c.pipe.iter().map(|p| (&p.command, &p.argv, &p.env, &rest)));
+    let mut children = Vec::with_capacity(c.pipe.len() + 1);
+    let mut cgroups = Vec::new();
+    let mut upstream = None;
+    for (command, argv, env, launch) in steps {
+        let spawned = prepare(command, argv, env, launch).and_then(|mut p| {
+            if let Some(out) = upstream.take() {
+                p.cmd.stdin(Stdio::from(out));
+            }
+            p.spawn()
+        });
+        let (mut child, cgroup) = match spawned {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                reap_all(&mut children);
+                return Err(e);
+            }
+        };
+        upstream = child.stdout.take();
+        children.push(child);
+        cgroups.extend(cgroup);
+    }
+    let stdout = drain_capped(upstream, max_output, stop.deadline);
+    let stderrs: Vec<_> =
+        children.iter_mut().map(|child| drain_capped(child.stderr.take(), max_output, stop.deadline)).collect();
+    let fed = feed_stdin(children[0].stdin.take(), &c.stdin, stop.deadline);
+    let statuses = feed_and_wait(&mut children, fed, stop)?;
+    let (stdout, mut truncated) = stdout.join().unwrap_or_default();
+    let mut stderr = Vec::new();
+    for handle in stderrs {
+        let (bytes, cut) = handle.join().unwrap_or_default();
+        stderr.extend_from_slice(&bytes);
+        truncated |= cut;
+    }
+    truncated |= stderr.len() > max_output;
+    stderr.truncate(max_output);
+    if truncated {
+        warn!(max_output, "target output truncated");
+    }
+    let failed = statuses.iter().rev().find(|s| !s.success());
+    Ok(InvokeResult {
+        stdout,
+        stderr,
+        exit_code: failed.or(statuses.last()).and_then(ExitStatus::code),
+        duration_ms: 0,
+        truncated,
+    })
+}
+
+/// A command ready to spawn, and what must stay alive until it has been reaped.
+struct Prepared {
+    cmd: Command,
+    cgroup: Option<Cgroup>,
+    memfd: Option<OwnedFd>,
+}
+
+impl Prepared {
+    /// Spawn, then close the daemon's copies of whatever the child inherited. The cgroup is removed when dropped.
+    fn spawn(self) -> Result<(Child, Option<Cgroup>), String> {
+        let Prepared { mut cmd, cgroup, memfd } = self;
+        let child = cmd.spawn().map_err(|e| format!("spawn failed: {e}"));
+        drop((cmd, memfd));
+        Ok((child?, cgroup))
+    }
+}
+
+/// Set up a command with a cleared env plus the base env, and piped stdio.
+fn prepare(
+    command: &str,
+    argv: &[String],
+    env: &BTreeMap<String, String>,
+    launch: &Launch,
+) -> Result<Prepared, String> {
     if command.is_empty() {
         return Err("empty command".to_string());
     }
@@ -189,32 +1343,243 @@ fn run_target(
     let mut cmd = Command::new(command);
     cmd.args(argv);
     cmd.env_clear();
-    cmd.env("PATH", "/run/current-system/sw/bin:/usr/bin:/bin");
+    match launch.base_env {
+        Some(base) => cmd.envs(base),
+        None => cmd.env("PATH", TARGET_PATH),
+    };
     for (k, v) in env {
         cmd.env(k, v);
     }
     cmd.stdin(Stdio::piped());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
+    // Its own process group, so a deadline kills whatever it started too.
+    cmd.process_group(0);
+    if let Some(dir) = launch.cwd {
+        cmd.current_dir(dir);
+    }
+    let (uid, user_gid) = match launch.user {
+        Some(u) => {
+            let (uid, gid) = crate::sockperm::lookup_user(u).map_err(|e| format!("run_as_user: {e}"))?;
+            (Some(uid), gid)
+        }
+        None => (None, None),
+    };
+    let gid = match launch.group {
+        Some(g) => Some(crate::sockperm::resolve_group(g).map_err(|e| format!("run_as_group: {e}"))?),
+        None => user_gid,
+    };
+    // Set in the child before exec, group first; as root, setting the uid also drops supplementary groups.
+    if let Some(gid) = gid {
+        cmd.gid(gid);
+    }
+    if let Some(uid) = uid {
+        cmd.uid(uid);
+    }
+    let cgroup = match (launch.limits, launch.cgroup_parent) {
+        (Some(limits), Some(parent)) => Cgroup::create(parent, limits).map_err(|e| format!("target cgroup: {e}"))?,
+        _ => None,
+    };
+    if let Some(cg) = &cgroup {
+        cg.enter(&mut cmd);
+    }
+    if let Some(limits) = launch.limits {
+        crate::limits::apply_rlimits(&mut cmd, limits);
+    }
+    let memfd = match launch.secret_fd {
+        Some((key, bytes)) => {
+            let fd = crate::memfd::sealed(key, bytes).map_err(|e| format!("secret fd failed: {e}"))?;
+            cmd.env(key, fd.as_raw_fd().to_string());
+            crate::memfd::pass_to(&mut cmd, &fd);
+            Some(fd)
+        }
+        None => None,
+    };
 
-    let mut child = cmd.spawn().map_err(|e| format!("spawn failed: {e}"))?;
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin
-            .write_all(stdin_bytes)
-            .map_err(|e| format!("write stdin failed: {e}"))?;
+    // Last, so the child sets everything else up before it is confined.
+    if let Some(sandbox) = launch.sandbox {
+        confine(&mut cmd, sandbox)?;
     }
-    let out = child
-        .wait_with_output()
-        .map_err(|e| format!("wait failed: {e}"))?;
+    Ok(Prepared { cmd, cgroup, memfd })
+}
+
+struct Captured {
+    status: ExitStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    truncated: bool,
+}
 
-    if !out.status.success() {
-        let stderr = String::from_utf8_lossy(&out.stderr);
-        let stderr = stderr.trim();
-        if stderr.is_empty() {
-            return Err("command failed".to_string());
+/// `wait_with_output` after writing `stdin_bytes`, but keep only `max_output` bytes per stream and kill the child at
+/// `stop`. Both streams are drained before stdin is written and the rest is read and dropped, so a chatty child never
+/// blocks on a full pipe. Nothing here waits much past the deadline, whoever holds the pipes.
+fn wait_capped(mut child: Child, stdin_bytes: &[u8], stop: Stop, max_output: usize) -> Result<Captured, String> {
+    let stdout = drain_capped(child.stdout.take(), max_output, stop.deadline);
+    let stderr = drain_capped(child.stderr.take(), max_output, stop.deadline);
+    let fed = feed_stdin(child.stdin.take(), stdin_bytes, stop.deadline);
+    let status = feed_and_wait(std::slice::from_mut(&mut child), fed, stop)?.remove(0);
+    let (stdout, out_cut) = stdout.join().unwrap_or_default();
+    let (stderr, err_cut) = stderr.join().unwrap_or_default();
+    Ok(Captured {
+        status,
+        stdout,
+        stderr,
+        truncated: out_cut || err_cut,
+    })
+}
+
+/// How long the drains keep reading past the deadline, for what the killed children had already written.
+const DRAIN_GRACE: Duration = Duration::from_millis(200);
+
+/// Write `bytes` to `stdin` and close it, on its own thread, so the child can answer while it reads.
+/// Gives up with `TimedOut` once `deadline` passes.
+fn feed_stdin(
+    stdin: Option<ChildStdin>,
+    bytes: &[u8],
+    deadline: Option<Instant>,
+) -> std::thread::JoinHandle<std::io::Result<()>> {
+    let bytes = bytes.to_vec();
+    std::thread::spawn(move || {
+        let Some(mut stdin) = stdin else {
+            return Ok(());
+        };
+        let fd = stdin.as_raw_fd();
+        // Only the daemon's end: the child's stays blocking.
+        if deadline.is_some() && unsafe { libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            if !ready_by(fd, libc::POLLOUT, deadline) {
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            match stdin.write(rest) {
+                Ok(n) => rest = &rest[n..],
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted) => {}
+                Err(e) => return Err(e),
+            }
         }
-        return Err(stderr.to_string());
+        Ok(())
+    })
+}
+
+/// Wait for the stdin write, then for every child; if the write failed, kill and reap them all instead.
+fn feed_and_wait(
+    children: &mut [Child],
+    fed: std::thread::JoinHandle<std::io::Result<()>>,
+    stop: Stop,
+) -> Result<Vec<ExitStatus>, String> {
+    let fed = fed.join().unwrap_or_else(|_| Err(std::io::Error::other("writer panicked")));
+    if let Err(e) = fed {
+        reap_all(children);
+        return Err(format!("write stdin failed: {e}"));
     }
+    wait_all(children, stop).map_err(|e| format!("wait failed: {e}"))
+}
 
-    Ok(out.stdout)
+/// Read `pipe` to the end on its own thread, keeping its first `max` bytes and whether any were dropped.
+/// Stops [`DRAIN_GRACE`] after `deadline`, even if something the child started still holds the pipe open.
+fn drain_capped(
+    pipe: Option<impl Read + AsRawFd + Send + 'static>,
+    max: usize,
+    deadline: Option<Instant>,
+) -> std::thread::JoinHandle<(Vec<u8>, bool)> {
+    let until = deadline.map(|d| d + DRAIN_GRACE);
+    std::thread::spawn(move || {
+        let (mut buf, mut truncated) = (Vec::new(), false);
+        let Some(mut pipe) = pipe else {
+            return (buf, truncated);
+        };
+        let mut chunk = [0u8; 8192];
+        loop {
+            if !ready_by(pipe.as_raw_fd(), libc::POLLIN, until) {
+                truncated = true;
+                break;
+            }
+            match pipe.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let keep = n.min(max - buf.len());
+                    buf.extend_from_slice(&chunk[..keep]);
+                    truncated |= keep < n;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        (buf, truncated)
+    })
+}
+
+/// Block until `fd` is ready for `events`, or has hung up or failed; false once `deadline` passes first.
+fn ready_by(fd: std::os::fd::RawFd, events: libc::c_short, deadline: Option<Instant>) -> bool {
+    loop {
+        let timeout = match deadline {
+            None => -1,
+            Some(d) => match d.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+                Some(left) => left.as_millis().clamp(1, i32::MAX as u128) as i32,
+                None => return false,
+            },
+        };
+        let mut pfd = libc::pollfd { fd, events, revents: 0 };
+        match unsafe { libc::poll(&mut pfd, 1, timeout) } {
+            0 => {}
+            -1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {}
+            // Ready, or an error the read or write will report.
+            _ => return true,
+        }
+    }
+}
+
+/// Wait for every child, killing those still running, with their process groups, at `stop`.
+fn wait_all(children: &mut [Child], stop: Stop) -> std::io::Result<Vec<ExitStatus>> {
+    if stop.deadline.is_none() && stop.hangup.is_none() {
+        return children.iter_mut().map(Child::wait).collect();
+    }
+    let mut statuses = vec![None; children.len()];
+    loop {
+        for (child, status) in children.iter_mut().zip(&mut statuses) {
+            if status.is_none() {
+                *status = child.try_wait()?;
+            }
+        }
+        if statuses.iter().all(Option::is_some) {
+            return Ok(statuses.into_iter().flatten().collect());
+        }
+        if stop.now() {
+            for (child, status) in children.iter_mut().zip(&mut statuses) {
+                if status.is_none() {
+                    kill_group(child)?;
+                    *status = Some(child.wait()?);
+                }
+            }
+            return Ok(statuses.into_iter().flatten().collect());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Kill and reap children that will not be waited for.
+fn reap_all(children: &mut [Child]) {
+    for child in children {
+        let _ = kill_group(child);
+        let _ = child.wait();
+    }
+}
+
+/// SIGKILL an unreaped child's process group, which [`prepare`] made it the leader of, and the child itself in case it
+/// has left the group.
+fn kill_group(child: &mut Child) -> std::io::Result<()> {
+    // SAFETY: plain kill(2); the child is not reaped yet, so its pid still names its group.
+    unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+    child.kill()
+}
+
+fn failure_message(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let stderr = stderr.trim();
+    if stderr.is_empty() {
+        return "command failed".to_string();
+    }
+    stderr.to_string()
 }
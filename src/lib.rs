@@ -1,3 +1,48 @@
+pub mod admin;
+pub mod agent_secret;
+pub mod alert;
+pub mod approval;
+mod atomic;
+pub mod audit;
 pub mod bunker;
+pub mod client;
+pub mod concurrency;
+pub mod config;
+pub mod daemon;
+pub mod detach;
+pub mod dump;
+pub mod ffi;
+mod files;
+pub mod history;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod invoke;
+mod limits;
+pub mod log;
+mod memfd;
+pub mod metrics;
+pub mod once;
+pub mod peercred;
+pub mod pin;
 pub mod rage;
+pub mod ratelimit;
+pub mod redact;
+mod remote;
+pub mod replay;
+#[cfg(feature = "sandbox")]
+mod sandbox;
+#[cfg(feature = "jsonschema")]
+mod schema;
+mod secret_sync;
+pub mod secrets;
+pub mod show;
+pub mod simulate;
+pub mod sockperm;
+pub mod systemd;
+pub mod threshold;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod usage;
+pub mod vsock;
+#[cfg(feature = "wasm")]
+mod wasm;
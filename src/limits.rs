@@ -0,0 +1,127 @@
+//! Target `limits`: rlimits set in the child before exec, and optionally a transient cgroup v2 per run under the
+//! daemon's `--cgroup-parent`, which also holds whatever the target forks.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use crate::bunker::TargetLimits;
+
+/// Set soft and hard limits alike in `cmd`'s child, so the target cannot raise them again.
+pub fn apply_rlimits(cmd: &mut Command, limits: &TargetLimits) {
+    let set: Vec<_> = [
+        (libc::RLIMIT_CPU, limits.cpu_secs),
+        (libc::RLIMIT_AS, limits.memory_bytes),
+        (libc::RLIMIT_NOFILE, limits.open_files),
+        (libc::RLIMIT_NPROC, limits.processes),
+    ]
+    .into_iter()
+    .filter_map(|(resource, v)| Some((resource, v?)))
+    .collect();
+    if set.is_empty() {
+        return;
+    }
+    // SAFETY: setrlimit is async-signal-safe, and `set` was built before the fork.
+    unsafe {
+        cmd.pre_exec(move || {
+            for &(resource, v) in &set {
+                let lim = libc::rlimit {
+                    rlim_cur: v,
+                    rlim_max: v,
+                };
+                if libc::setrlimit(resource, &lim) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// A cgroup made for one run and removed, with anything left in it killed, when dropped.
+pub struct Cgroup {
+    dir: PathBuf,
+    procs: File,
+}
+
+impl Cgroup {
+    /// `None` when `limits` has nothing a cgroup enforces.
+    pub fn create(parent: &Path, limits: &TargetLimits) -> io::Result<Option<Self>> {
+        if limits.memory_bytes.is_none() && limits.processes.is_none() {
+            return Ok(None);
+        }
+        static SEQ: AtomicU32 = AtomicU32::new(0);
+        let name = format!("turret-{}-{}", std::process::id(), SEQ.fetch_add(1, Ordering::Relaxed));
+        let dir = parent.join(name);
+        std::fs::create_dir(&dir)?;
+        let cg = Self {
+            procs: match OpenOptions::new().write(true).open(dir.join("cgroup.procs")) {
+                Ok(f) => f,
+                Err(e) => {
+                    let _ = std::fs::remove_dir(&dir);
+                    return Err(e);
+                }
+            },
+            dir,
+        };
+        if let Some(n) = limits.memory_bytes {
+            cg.write("memory.max", n)?;
+            cg.write("memory.swap.max", 0).or_else(ignore_missing)?;
+        }
+        if let Some(n) = limits.processes {
+            cg.write("pids.max", n)?;
+        }
+        Ok(Some(cg))
+    }
+
+    /// Move `cmd`'s child into the cgroup before exec. The fd was opened by the daemon, so this works after the
+    /// child has switched to `run_as_user`.
+    pub fn enter(&self, cmd: &mut Command) {
+        let fd = self.procs.as_raw_fd();
+        // SAFETY: write is async-signal-safe; the fd stays open until the child has been reaped.
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::write(fd, b"0".as_ptr().cast(), 1) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    fn write(&self, file: &str, n: u64) -> io::Result<()> {
+        let path = self.dir.join(file);
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|mut f| f.write_all(n.to_string().as_bytes()))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+    }
+}
+
+/// Without swap accounting there is no `memory.swap.max`; `memory.max` still holds.
+fn ignore_missing(e: io::Error) -> io::Result<()> {
+    match e.kind() {
+        io::ErrorKind::NotFound => Ok(()),
+        _ => Err(e),
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        let _ = std::fs::write(self.dir.join("cgroup.kill"), "1");
+        // Killed processes leave the cgroup asynchronously.
+        for _ in 0..50 {
+            match std::fs::remove_dir(&self.dir) {
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => std::thread::sleep(Duration::from_millis(10)),
+                _ => return,
+            }
+        }
+        tracing::warn!(cgroup = %self.dir.display(), "could not remove target cgroup");
+    }
+}
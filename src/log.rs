@@ -0,0 +1,286 @@
+use std::fmt::Write as _;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+use serde::Deserialize;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::redact::{redact, RedactingWriter};
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET: &str = "/dev/log";
+const IDENTIFIER: &str = "turret";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogTarget {
+    Stderr,
+    /// Native journal protocol; falls back to syslog, then stderr.
+    Journald,
+    /// RFC 3164 datagrams on /dev/log; falls back to stderr.
+    Syslog,
+}
+
+/// Line format for stderr and syslog; journald entries are always structured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per event, with the fields of its enclosing spans.
+    Json,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogError {
+    #[error("logging already initialised")]
+    AlreadySet,
+}
+
+/// Install the global subscriber. Returns the target actually in use after fallbacks.
+pub fn init(target: LogTarget, format: LogFormat) -> Result<LogTarget, LogError> {
+    let (target, sink) = match target {
+        LogTarget::Journald => match connect(JOURNALD_SOCKET) {
+            Some(s) => (LogTarget::Journald, Some(DatagramLayer::new(s, Wire::Journald))),
+            None => match connect(SYSLOG_SOCKET) {
+                Some(s) => (LogTarget::Syslog, Some(DatagramLayer::new(s, Wire::Syslog(format)))),
+                None => (LogTarget::Stderr, None),
+            },
+        },
+        LogTarget::Syslog => match connect(SYSLOG_SOCKET) {
+            Some(s) => (LogTarget::Syslog, Some(DatagramLayer::new(s, Wire::Syslog(format)))),
+            None => (LogTarget::Stderr, None),
+        },
+        LogTarget::Stderr => (LogTarget::Stderr, None),
+    };
+
+    let res = match (sink, format) {
+        (Some(layer), _) => tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer)),
+        (None, LogFormat::Json) => tracing::subscriber::set_global_default(tracing_subscriber::registry().with(JsonLayer)),
+        (None, LogFormat::Text) => tracing::subscriber::set_global_default(
+            tracing_subscriber::fmt()
+                .with_writer(|| RedactingWriter(std::io::stderr()))
+                .with_target(false)
+                .finish(),
+        ),
+    };
+    res.map_err(|_| LogError::AlreadySet)?;
+    Ok(target)
+}
+
+fn connect(path: &str) -> Option<UnixDatagram> {
+    if !Path::new(path).exists() {
+        return None;
+    }
+    let sock = UnixDatagram::unbound().ok()?;
+    sock.connect(path).ok()?;
+    Some(sock)
+}
+
+#[derive(Clone, Copy)]
+enum Wire {
+    Journald,
+    Syslog(LogFormat),
+}
+
+struct DatagramLayer {
+    sock: UnixDatagram,
+    wire: Wire,
+}
+
+impl DatagramLayer {
+    fn new(sock: UnixDatagram, wire: Wire) -> Self {
+        Self { sock, wire }
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for DatagramLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        store_span_fields(attrs, id, &ctx);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        update_span_fields(id, values, &ctx);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let fields = event_fields(event, &ctx);
+        let level = *event.metadata().level();
+        let buf = match self.wire {
+            Wire::Journald => journald_entry(level, &fields),
+            Wire::Syslog(LogFormat::Text) => syslog_line(level, &fields).into_bytes(),
+            Wire::Syslog(LogFormat::Json) => syslog_json(level, &fields).into_bytes(),
+        };
+        let _ = self.sock.send(&buf);
+    }
+}
+
+/// `--log-format json` on stderr.
+struct JsonLayer;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for JsonLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        store_span_fields(attrs, id, &ctx);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        update_span_fields(id, values, &ctx);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let fields = event_fields(event, &ctx);
+        let mut line = json_object(*event.metadata().level(), &fields);
+        line.push('\n');
+        let _ = std::io::Write::write_all(&mut std::io::stderr(), line.as_bytes());
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    /// Numbers and bools stay typed for JSON output.
+    extra: Vec<(String, serde_json::Value)>,
+}
+
+/// A span's own fields, kept in its extensions so events inside it can carry them.
+struct SpanFields(Vec<(String, serde_json::Value)>);
+
+fn store_span_fields<S: Subscriber + for<'a> LookupSpan<'a>>(attrs: &Attributes<'_>, id: &Id, ctx: &Context<'_, S>) {
+    let mut f = Fields::default();
+    attrs.record(&mut f);
+    if let Some(span) = ctx.span(id) {
+        span.extensions_mut().replace(SpanFields(f.extra));
+    }
+}
+
+fn update_span_fields<S: Subscriber + for<'a> LookupSpan<'a>>(id: &Id, values: &Record<'_>, ctx: &Context<'_, S>) {
+    let mut f = Fields::default();
+    values.record(&mut f);
+    if let Some(span) = ctx.span(id) {
+        if let Some(stored) = span.extensions_mut().get_mut::<SpanFields>() {
+            stored.0.extend(f.extra);
+        }
+    }
+}
+
+/// The event's fields after those of its spans, outermost first.
+fn event_fields<S: Subscriber + for<'a> LookupSpan<'a>>(event: &Event<'_>, ctx: &Context<'_, S>) -> Fields {
+    let mut fields = Fields::default();
+    if let Some(scope) = ctx.event_scope(event) {
+        for span in scope.from_root() {
+            if let Some(f) = span.extensions().get::<SpanFields>() {
+                fields.extra.extend(f.0.iter().cloned());
+            }
+        }
+    }
+    event.record(&mut fields);
+    fields
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let value = redact(value).into_owned();
+        if field.name() == "message" {
+            self.message = value;
+        } else {
+            self.extra.push((field.name().to_string(), value.into()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let value = redact(&format!("{value:?}")).into_owned();
+        if field.name() == "message" {
+            self.message = value;
+        } else {
+            self.extra.push((field.name().to_string(), value.into()));
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.extra.push((field.name().to_string(), value.into()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.extra.push((field.name().to_string(), value.into()));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.extra.push((field.name().to_string(), value.into()));
+    }
+}
+
+/// A field value as plain text, for journald and text syslog.
+fn text(v: &serde_json::Value) -> std::borrow::Cow<'_, str> {
+    match v {
+        serde_json::Value::String(s) => s.as_str().into(),
+        other => other.to_string().into(),
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+fn journald_entry(level: Level, f: &Fields) -> Vec<u8> {
+    let mut out = Vec::new();
+    journald_field(&mut out, "MESSAGE", &f.message);
+    journald_field(&mut out, "PRIORITY", &severity(level).to_string());
+    journald_field(&mut out, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    for (k, v) in &f.extra {
+        let key: String = k
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        journald_field(&mut out, &format!("TURRET_{key}"), &text(v));
+    }
+    out
+}
+
+// Journal native protocol: KEY=value\n, or KEY\n<u64 le len>value\n when value has newlines.
+fn journald_field(out: &mut Vec<u8>, key: &str, value: &str) {
+    out.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        out.push(b'\n');
+        out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        out.push(b'=');
+    }
+    out.extend_from_slice(value.as_bytes());
+    out.push(b'\n');
+}
+
+fn json_object(level: Level, f: &Fields) -> String {
+    let mut obj = serde_json::Map::new();
+    obj.insert("ts_ms".into(), crate::audit::now_ms().into());
+    obj.insert("level".into(), level.as_str().into());
+    obj.insert("message".into(), f.message.clone().into());
+    for (k, v) in &f.extra {
+        obj.insert(k.clone(), v.clone());
+    }
+    serde_json::Value::Object(obj).to_string()
+}
+
+fn syslog_json(level: Level, f: &Fields) -> String {
+    let pri = 3 * 8 + severity(level) as u32;
+    format!("<{pri}>{IDENTIFIER}[{}]: {}", std::process::id(), json_object(level, f))
+}
+
+fn syslog_line(level: Level, f: &Fields) -> String {
+    // facility 3 = daemon
+    let pri = 3 * 8 + severity(level) as u32;
+    let mut line = format!("<{pri}>{IDENTIFIER}[{}]: {}", std::process::id(), f.message);
+    for (k, v) in &f.extra {
+        let _ = write!(line, " {k}={}", text(v));
+    }
+    line.replace('\n', " ")
+}
@@ -0,0 +1,42 @@
+//! Sealed memfds for `out_secret_fd`: a secret the target reads from an inherited fd, so it shows up neither in
+//! `/proc/<pid>/cmdline` nor `environ`, and never touches disk.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// An fd holding `bytes`, read from the start, that nobody can write, grow or shrink. Close-on-exec until
+/// [`pass_to`] lets one child inherit it.
+pub fn sealed(name: &str, bytes: &[u8]) -> io::Result<OwnedFd> {
+    let name = CString::new(format!("turret-{name}")).map_err(io::Error::other)?;
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: memfd_create just returned this fd and nothing else owns it.
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(bytes)?;
+    let seals = libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file.into())
+}
+
+/// Keep `fd` open across `cmd`'s exec, at the same number. Other children spawned meanwhile do not get it.
+pub fn pass_to(cmd: &mut Command, fd: &OwnedFd) {
+    let raw: RawFd = fd.as_raw_fd();
+    // SAFETY: fcntl is async-signal-safe, and only touches the forked child's copy of the fd table.
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::fcntl(raw, libc::F_SETFD, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
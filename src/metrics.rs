@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+const MS_BOUNDS: &[u64] = &[1, 5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000];
+const BYTE_BOUNDS: &[u64] = &[64, 256, 1 << 10, 4 << 10, 16 << 10, 64 << 10, 256 << 10, 1 << 20, 4 << 20];
+
+/// Fixed-bucket histogram: `counts[i]` holds observations `<= bounds[i]`, the last slot the overflow.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub bounds: Vec<u64>,
+    pub counts: Vec<u64>,
+    pub count: u64,
+    pub sum: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &[u64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len() + 1],
+            count: 0,
+            sum: 0,
+        }
+    }
+
+    pub fn observe(&mut self, v: u64) {
+        let i = self.bounds.partition_point(|b| *b < v);
+        self.counts[i] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(v);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetMetrics {
+    pub queue_ms: Histogram,
+    pub exec_ms: Histogram,
+    pub result_bytes: Histogram,
+    /// Count per outcome: `ok` or the daemon error code.
+    pub outcomes: BTreeMap<String, u64>,
+}
+
+impl Default for TargetMetrics {
+    fn default() -> Self {
+        Self {
+            queue_ms: Histogram::new(MS_BOUNDS),
+            exec_ms: Histogram::new(MS_BOUNDS),
+            result_bytes: Histogram::new(BYTE_BOUNDS),
+            outcomes: BTreeMap::new(),
+        }
+    }
+}
+
+/// One finished fire request.
+#[derive(Clone, Debug)]
+pub struct Observation<'a> {
+    pub queue: Duration,
+    pub exec: Duration,
+    pub result_bytes: Option<usize>,
+    pub outcome: &'a str,
+}
+
+/// Cheap-to-clone handle shared by whoever records and whoever reports.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    targets: Arc<Mutex<BTreeMap<String, TargetMetrics>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&self, target: &str, obs: Observation<'_>) {
+        let mut targets = self.targets.lock().unwrap_or_else(|e| e.into_inner());
+        let m = targets.entry(target.to_string()).or_default();
+        m.queue_ms.observe(obs.queue.as_millis() as u64);
+        m.exec_ms.observe(obs.exec.as_millis() as u64);
+        if let Some(n) = obs.result_bytes {
+            m.result_bytes.observe(n as u64);
+        }
+        *m.outcomes.entry(obs.outcome.to_string()).or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, TargetMetrics> {
+        self.targets.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn target(&self, target: &str) -> Option<TargetMetrics> {
+        self.targets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(target)
+            .cloned()
+    }
+}
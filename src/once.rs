@@ -0,0 +1,127 @@
+//! Single-use grants (`allow --once`): which have been spent, kept in a state file so a restart does not
+//! hand them out again. A grant is identified by when it was issued, so re-granting it starts afresh.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::atomic::write_atomic;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OnceError {
+    #[error("single-use state {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("single-use state {path}: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// Agent to permission entry to the issue time of the spent grant.
+type Spent = BTreeMap<String, BTreeMap<String, u64>>;
+
+#[derive(Debug, Default)]
+struct State {
+    spent: Spent,
+    /// Held by invokes in flight, so two cannot both spend the same grant.
+    claimed: BTreeSet<(String, String, u64)>,
+}
+
+#[derive(Debug, Default)]
+pub struct OnceStore {
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+/// A grant reserved for one invoke: spent by [`Claim::spend`], released if dropped.
+pub struct Claim<'a> {
+    store: &'a OnceStore,
+    key: (String, String, u64),
+}
+
+impl OnceStore {
+    /// In-memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load `path` if it exists; every spent grant rewrites it.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, OnceError> {
+        let path = path.into();
+        let spent = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|source| OnceError::Json {
+                path: path.clone(),
+                source,
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Spent::new(),
+            Err(source) => return Err(OnceError::Io { path, source }),
+        };
+        Ok(Self {
+            path: Some(path),
+            state: Mutex::new(State {
+                spent,
+                claimed: BTreeSet::new(),
+            }),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Reserve `agent`'s grant `grant`, issued at `issued_ms`, unless it is spent or another invoke holds it.
+    pub fn claim(&self, agent: &str, grant: &str, issued_ms: u64) -> Option<Claim<'_>> {
+        let mut state = self.lock();
+        let spent = state.spent.get(agent).and_then(|g| g.get(grant)) == Some(&issued_ms);
+        let key = (agent.to_string(), grant.to_string(), issued_ms);
+        if spent || !state.claimed.insert(key.clone()) {
+            return None;
+        }
+        Some(Claim { store: self, key })
+    }
+
+    /// Whether `agent`'s grant `grant`, issued at `issued_ms`, has been spent.
+    pub fn is_spent(&self, agent: &str, grant: &str, issued_ms: u64) -> bool {
+        self.lock().spent.get(agent).and_then(|g| g.get(grant)) == Some(&issued_ms)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn persist(&self, spent: &Spent) -> Result<(), OnceError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let io_err = |source| OnceError::Io {
+            path: path.clone(),
+            source,
+        };
+        let body = serde_json::to_vec(spent).map_err(|source| OnceError::Json {
+            path: path.clone(),
+            source,
+        })?;
+        write_atomic(path, &body).map_err(io_err)
+    }
+}
+
+impl Claim<'_> {
+    /// The grant is spent in memory even if persisting fails; the error is returned for logging.
+    pub fn spend(self) -> Result<(), OnceError> {
+        let mut state = self.store.lock();
+        let (agent, grant, issued_ms) = self.key.clone();
+        state.spent.entry(agent).or_default().insert(grant, issued_ms);
+        let res = self.store.persist(&state.spent);
+        // Dropping `self` releases the claim, which takes the lock again.
+        drop(state);
+        res
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.store.lock().claimed.remove(&self.key);
+    }
+}
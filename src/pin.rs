@@ -0,0 +1,83 @@
+//! Pinned target binaries: `in target --pin` resolves `out_command` to an absolute path and records its SHA-256,
+//! and every run re-checks the file first, so neither a PATH change nor a swapped binary runs in its place.
+//! Recruits' TLS client certificates are pinned the same way, by the SHA-256 of their DER.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+/// What identifies a file's contents without reading them; the kernel updates ctime on any change.
+type Stamp = (u64, u64, u64, i64, i64, i64, i64);
+
+/// Files already hashed, so a large binary is read once per change rather than once per run.
+static VERIFIED: Mutex<BTreeMap<PathBuf, (Stamp, String)>> = Mutex::new(BTreeMap::new());
+
+/// `command` as an absolute, canonical path: paths against the current directory, bare names along `path`.
+pub fn resolve(command: &str, path: &str) -> io::Result<PathBuf> {
+    let found = if command.contains('/') {
+        Some(PathBuf::from(command))
+    } else {
+        std::env::split_paths(path)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(|dir| dir.join(command))
+            .find(|p| p.is_file())
+    };
+    let found = found.ok_or_else(|| io::Error::other(format!("'{command}' is not on PATH {path}")))?;
+    std::fs::canonicalize(&found).map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", found.display())))
+}
+
+/// Lowercase hex SHA-256 of the file at `path`.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut f = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match f.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Lowercase hex SHA-256 of `bytes`, as [`sha256_file`] reports it.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// [`sha256_hex`] of the first certificate in a PEM file, i.e. of the DER a TLS client presents.
+pub fn cert_sha256(pem: &str) -> Option<String> {
+    let body = pem.split("-----BEGIN CERTIFICATE-----").nth(1)?.split("-----END CERTIFICATE-----").next()?;
+    let der = base64::engine::general_purpose::STANDARD.decode(body.split_whitespace().collect::<String>()).ok()?;
+    Some(sha256_hex(&der))
+}
+
+/// Whether `s` could be a [`sha256_file`] result.
+pub fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Refuse unless the file at `command` still hashes to `expected`. Checked just before spawn; whoever could swap
+/// the file in between could also have written it in the first place.
+pub(crate) fn verify(command: &str, expected: &str) -> Result<(), String> {
+    let path = Path::new(command);
+    let changed = |what: String| format!("pinned out_command {command} {what}");
+    let m = std::fs::metadata(path).map_err(|e| changed(format!("cannot be read: {e}")))?;
+    let stamp = (m.dev(), m.ino(), m.size(), m.mtime(), m.mtime_nsec(), m.ctime(), m.ctime_nsec());
+    let mut verified = VERIFIED.lock().unwrap_or_else(|e| e.into_inner());
+    if verified.get(path).is_some_and(|(s, h)| *s == stamp && h == expected) {
+        return Ok(());
+    }
+    let actual = sha256_file(path).map_err(|e| changed(format!("cannot be read: {e}")))?;
+    if actual != expected {
+        verified.remove(path);
+        return Err(changed(format!("has changed (sha256 {actual})")));
+    }
+    verified.insert(path.to_path_buf(), (stamp, actual));
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! Per-recruit token buckets and per-target hourly quotas for `[limits]`. State lives in the daemon and
+//! survives reloads; quota counts can also be kept in a file so they survive restarts.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tracing::warn;
+
+use crate::atomic::write_atomic;
+use crate::bunker::{RateLimit, TargetQuota};
+
+const HOUR_MS: u64 = 3_600_000;
+
+#[derive(Debug, thiserror::Error)]
+#[error("rate limited; retry in {retry_after_ms}ms")]
+pub struct RateLimited {
+    pub retry_after_ms: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("quota for '{target}' used up ({max_per_hour} per hour); retry in {retry_after_ms}ms")]
+pub struct QuotaExceeded {
+    pub target: String,
+    pub max_per_hour: u32,
+    pub retry_after_ms: u64,
+}
+
+/// Unix ms of each counted invoke in the last hour, by recruit and target.
+type Counted = BTreeMap<String, BTreeMap<String, VecDeque<u64>>>;
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    quotas: Mutex<Counted>,
+    /// Rewritten after each counted invoke.
+    quota_file: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the quota counts in `path` if it exists, and keep it up to date from now on.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let quotas = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Counted::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            quotas: Mutex::new(quotas),
+            quota_file: Some(path),
+            ..Self::default()
+        })
+    }
+
+    pub fn quota_file(&self) -> Option<&Path> {
+        self.quota_file.as_deref()
+    }
+
+    /// Take one token from `principal`'s bucket, starting it full on first use. Without `per_minute`, always passes.
+    pub fn check(&self, principal: &str, limit: &RateLimit, now: Instant) -> Result<(), RateLimited> {
+        let (Some(per_minute), Some(burst)) = (limit.per_minute, limit.burst()) else {
+            return Ok(());
+        };
+        let burst = f64::from(burst);
+        let per_ms = f64::from(per_minute) / 60_000.0;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let b = buckets.entry(principal.to_string()).or_insert(Bucket {
+            tokens: burst,
+            refilled: now,
+        });
+        let elapsed_ms = now.saturating_duration_since(b.refilled).as_secs_f64() * 1000.0;
+        // A reload may have lowered the burst.
+        b.tokens = (b.tokens + elapsed_ms * per_ms).min(burst);
+        b.refilled = now;
+        if b.tokens >= 1.0 {
+            b.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(RateLimited {
+            retry_after_ms: ((1.0 - b.tokens) / per_ms).ceil() as u64,
+        })
+    }
+
+    /// Count one invoke of `target` by `principal`, unless the last hour already holds `quota.max_per_hour`.
+    pub fn check_quota(
+        &self,
+        principal: &str,
+        target: &str,
+        quota: &TargetQuota,
+        now_ms: u64,
+    ) -> Result<(), QuotaExceeded> {
+        let mut quotas = self.quotas.lock().unwrap_or_else(|e| e.into_inner());
+        let since = now_ms.saturating_sub(HOUR_MS);
+        for times in quotas.values_mut().flat_map(|t| t.values_mut()) {
+            while times.front().is_some_and(|&t| t <= since) {
+                times.pop_front();
+            }
+        }
+        let times = quotas
+            .entry(principal.to_string())
+            .or_default()
+            .entry(target.to_string())
+            .or_default();
+        // A reload may have lowered the quota below what is already counted.
+        if times.len() >= quota.max_per_hour as usize {
+            let oldest = times[times.len() - quota.max_per_hour as usize];
+            return Err(QuotaExceeded {
+                target: target.to_string(),
+                max_per_hour: quota.max_per_hour,
+                retry_after_ms: (oldest + HOUR_MS).saturating_sub(now_ms),
+            });
+        }
+        times.push_back(now_ms);
+        quotas.retain(|_, targets| {
+            targets.retain(|_, times| !times.is_empty());
+            !targets.is_empty()
+        });
+        if let Err(e) = self.persist(&quotas) {
+            warn!("quota file: {e}");
+        }
+        Ok(())
+    }
+
+    fn persist(&self, quotas: &Counted) -> io::Result<()> {
+        let Some(path) = &self.quota_file else {
+            return Ok(());
+        };
+        let body = serde_json::to_vec(quotas).map_err(io::Error::other)?;
+        write_atomic(path, &body)
+    }
+
+    /// Forget recruits that no longer have a limit.
+    pub fn retain(&self, limited: impl Fn(&str) -> bool) {
+        self.buckets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|k, _| limited(k));
+        self.quotas
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|k, _| limited(k));
+    }
+}
@@ -0,0 +1,153 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::RwLock;
+
+use crate::bunker::Bunker;
+
+pub const MASK: &str = "[REDACTED]";
+
+/// Values shorter than this are not masked; they would shred unrelated text.
+pub const MIN_SECRET_LEN: usize = 4;
+
+static ACTIVE: RwLock<Option<Redactor>> = RwLock::new(None);
+
+/// Masks known secret values and bearer tokens in text bound for log and audit sinks.
+#[derive(Clone, Debug, Default)]
+pub struct Redactor {
+    /// Longest first, so a secret containing another is masked whole.
+    needles: Vec<String>,
+}
+
+impl Redactor {
+    pub fn new<I, S>(values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut needles = Vec::new();
+        for v in values {
+            let v = v.as_ref();
+            if v.len() < MIN_SECRET_LEN {
+                continue;
+            }
+            needles.push(v.to_string());
+            // Audit lines are JSON, where quotes, backslashes and control chars appear escaped.
+            if let Ok(quoted) = serde_json::to_string(v) {
+                let escaped = &quoted[1..quoted.len() - 1];
+                if escaped != v {
+                    needles.push(escaped.to_string());
+                }
+            }
+        }
+        needles.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        needles.dedup();
+        Self { needles }
+    }
+
+    /// Bunker `[secrets]` values and recruit secrets.
+    pub fn for_bunker(b: &Bunker) -> Self {
+        Self::new(b.secrets.values().chain(b.agents.values()))
+    }
+
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut out = Cow::Borrowed(text);
+        for n in &self.needles {
+            if out.contains(n.as_str()) {
+                out = Cow::Owned(out.replace(n.as_str(), MASK));
+            }
+        }
+        match mask_bearer(&out) {
+            Some(masked) => Cow::Owned(masked),
+            None => out,
+        }
+    }
+
+    /// [`Redactor::redact`] for command output. Output that is not UTF-8 has only the secret values masked.
+    pub fn redact_bytes(&self, bytes: &[u8]) -> Vec<u8> {
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            return self.redact(text).into_owned().into_bytes();
+        }
+        let mut out = bytes.to_vec();
+        for n in &self.needles {
+            out = replace_bytes(&out, n.as_bytes(), MASK.as_bytes());
+        }
+        out
+    }
+}
+
+fn replace_bytes(hay: &[u8], needle: &[u8], with: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(hay.len());
+    let mut i = 0;
+    while i < hay.len() {
+        if hay[i..].starts_with(needle) {
+            out.extend_from_slice(with);
+            i += needle.len();
+        } else {
+            out.push(hay[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Replace the process-wide redactor used by the log and audit sinks.
+pub fn install(r: Redactor) {
+    *ACTIVE.write().unwrap_or_else(|e| e.into_inner()) = Some(r);
+}
+
+/// Redact with the installed redactor; bearer tokens are masked even before one is installed.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    match ACTIVE.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        Some(r) => Cow::Owned(r.redact(text).into_owned()),
+        None => mask_bearer(text).map_or(Cow::Borrowed(text), Cow::Owned),
+    }
+}
+
+/// `Bearer <token>` (any case) becomes `Bearer [REDACTED]`. `None` when there is nothing to mask.
+fn mask_bearer(text: &str) -> Option<String> {
+    const WORD: &str = "bearer ";
+    let lower = text.to_ascii_lowercase();
+    if !lower.contains(WORD) {
+        return None;
+    }
+    let is_token = |c: char| c.is_ascii_alphanumeric() || "-._~+/=:".contains(c);
+    let mut out = String::with_capacity(text.len());
+    let mut rest = 0;
+    let mut changed = false;
+    for (i, _) in lower.match_indices(WORD) {
+        if i < rest {
+            continue;
+        }
+        let start = i + WORD.len();
+        let len = text[start..].find(|c: char| !is_token(c)).unwrap_or(text.len() - start);
+        if len == 0 {
+            continue;
+        }
+        out.push_str(&text[rest..start]);
+        out.push_str(MASK);
+        rest = start + len;
+        changed = true;
+    }
+    if !changed {
+        return None;
+    }
+    out.push_str(&text[rest..]);
+    Some(out)
+}
+
+/// Writer that redacts each buffer before passing it on. tracing's fmt layer writes whole lines.
+pub struct RedactingWriter<W>(pub W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(s) => self.0.write_all(redact(s).as_bytes())?,
+            Err(_) => self.0.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
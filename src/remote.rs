@@ -0,0 +1,45 @@
+//! Remote targets: hand a payload to another turret's `relay` over ssh and unwrap its reply.
+
+use std::collections::BTreeMap;
+
+use crate::client::into_result;
+use crate::invoke::{find_on_daemon_path, run_target, FireResponse, InvokePayload, InvokeResult};
+
+pub(crate) struct Hop<'a> {
+    pub ssh: &'a str,
+    pub port: Option<u16>,
+    pub identity: Option<&'a str>,
+    pub bunker: &'a str,
+    pub dir: &'a str,
+    pub turret: &'a str,
+}
+
+/// The payload (with the remote rookie's secret) goes over ssh stdin, never argv.
+pub(crate) fn forward(hop: &Hop<'_>, payload: &InvokePayload) -> Result<InvokeResult, String> {
+    let req = serde_json::to_vec(payload).map_err(|e| e.to_string())?;
+
+    let mut argv: Vec<String> = ["-T", "-o", "BatchMode=yes"].map(String::from).to_vec();
+    if let Some(p) = hop.port {
+        argv.extend(["-p".to_string(), p.to_string()]);
+    }
+    if let Some(i) = hop.identity {
+        argv.extend(["-i".to_string(), i.to_string(), "-o".to_string(), "IdentitiesOnly=yes".to_string()]);
+    }
+    argv.push(hop.ssh.to_string());
+    argv.push("--".to_string());
+    // Validated as plain words in the bunker, so no quoting is needed for the remote shell.
+    argv.push(format!("cd {} && exec {} {} relay", hop.dir, hop.turret, hop.bunker));
+
+    // ssh wants ~/.ssh/known_hosts and, without an identity, the agent.
+    let mut env = BTreeMap::new();
+    for key in ["HOME", "SSH_AUTH_SOCK"] {
+        if let Ok(v) = std::env::var(key) {
+            env.insert(key.to_string(), v);
+        }
+    }
+    let out = run_target(&find_on_daemon_path("ssh"), &argv, &env, &req)?;
+
+    let resp: FireResponse =
+        serde_json::from_slice(&out).map_err(|e| format!("remote {}: bad response: {e}", hop.ssh))?;
+    into_result(resp).map_err(|e| format!("remote {}: {e}", hop.ssh))
+}
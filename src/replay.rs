@@ -0,0 +1,212 @@
+//! Nonces seen on signed invokes, remembered for as long as their timestamp could still be accepted.
+//! Optionally mirrored to an append-only file so a restart inside the window does not forget them.
+
+use std::collections::{BTreeSet, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::atomic::write_atomic;
+
+/// How far a signed invoke's `ts_ms` may be from the daemon's clock, either way.
+pub const WINDOW_MS: u64 = 120_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    /// Carries the daemon's clock so the client can see how far off it is.
+    #[error("timestamp {ts_ms} is {} the daemon clock ({now_ms}) by {}s; allowed skew is {}s",
+        if ts_ms > now_ms { "ahead of" } else { "behind" }, ts_ms.abs_diff(*now_ms) / 1000, WINDOW_MS / 1000)]
+    OutsideWindow { ts_ms: u64, now_ms: u64 },
+    #[error("nonce already used")]
+    Replayed,
+}
+
+/// Principals hash to one of this many independently locked shards.
+const SHARDS: usize = 16;
+
+#[derive(Debug, Default)]
+pub struct ReplayCache {
+    shards: [Mutex<Seen>; SHARDS],
+    /// Entries across all shards, kept as they change so counting takes no shard locks.
+    count: AtomicUsize,
+    /// Locked only after a shard has been released.
+    spill: Mutex<Option<Spill>>,
+}
+
+#[derive(Debug, Default)]
+struct Seen {
+    keys: HashSet<(String, String)>,
+    /// Same entries ordered by timestamp, for expiry.
+    by_ts: BTreeSet<(u64, String, String)>,
+}
+
+/// One JSON line per accepted nonce; rewritten with only live entries once mostly expired.
+#[derive(Debug)]
+struct Spill {
+    path: PathBuf,
+    file: File,
+    lines: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SpillLine {
+    ts_ms: u64,
+    principal: String,
+    nonce: String,
+}
+
+impl ReplayCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the nonces in `path` still inside the window of `now_ms`, compact it, and append to it from now on.
+    /// Unparseable lines (a torn final write) are skipped.
+    pub fn open(path: impl Into<PathBuf>, now_ms: u64) -> io::Result<Self> {
+        let path = path.into();
+        let cache = Self::new();
+        match File::open(&path) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    let Ok(l) = serde_json::from_str::<SpillLine>(&line?) else {
+                        continue;
+                    };
+                    if l.ts_ms.abs_diff(now_ms) <= WINDOW_MS {
+                        let mut seen = cache.shard(&l.principal);
+                        if seen.keys.insert((l.principal.clone(), l.nonce.clone())) {
+                            seen.by_ts.insert((l.ts_ms, l.principal, l.nonce));
+                            cache.count.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let spill = Spill::rewrite(&path, &cache.live())?;
+        *cache.spill.lock().unwrap_or_else(|e| e.into_inner()) = Some(spill);
+        Ok(cache)
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        let spill = self.spill.lock().unwrap_or_else(|e| e.into_inner());
+        spill.as_ref().map(|s| s.path.clone())
+    }
+
+    /// Accept `nonce` from `principal` once, and only if `ts_ms` is within the window of `now_ms`.
+    pub fn check_and_record(&self, principal: &str, nonce: &str, ts_ms: u64, now_ms: u64) -> Result<(), ReplayError> {
+        if ts_ms.abs_diff(now_ms) > WINDOW_MS {
+            return Err(ReplayError::OutsideWindow { ts_ms, now_ms });
+        }
+        {
+            let mut seen = self.shard(principal);
+            let expired = seen.expire(now_ms.saturating_sub(WINDOW_MS));
+            self.count.fetch_sub(expired, Ordering::Relaxed);
+            if !seen.keys.insert((principal.to_string(), nonce.to_string())) {
+                return Err(ReplayError::Replayed);
+            }
+            seen.by_ts.insert((ts_ms, principal.to_string(), nonce.to_string()));
+            self.count.fetch_add(1, Ordering::Relaxed);
+        }
+        self.persist(principal, nonce, ts_ms);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard(&self, principal: &str) -> MutexGuard<'_, Seen> {
+        let mut h = DefaultHasher::new();
+        principal.hash(&mut h);
+        self.shards[h.finish() as usize % SHARDS]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Every remembered entry, one shard at a time.
+    fn live(&self) -> BTreeSet<(u64, String, String)> {
+        let mut all = BTreeSet::new();
+        for s in &self.shards {
+            all.extend(s.lock().unwrap_or_else(|e| e.into_inner()).by_ts.iter().cloned());
+        }
+        all
+    }
+
+    /// A failed write only weakens protection across a restart, so it is logged rather than refused.
+    /// An entry recorded during a compaction may land in the file twice, which loading tolerates.
+    fn persist(&self, principal: &str, nonce: &str, ts_ms: u64) {
+        let mut guard = self.spill.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(spill) = guard.as_mut() else {
+            return;
+        };
+        let res = if spill.lines > 2 * self.len() + 1024 {
+            Spill::rewrite(&spill.path, &self.live()).map(|fresh| *spill = fresh)
+        } else {
+            spill.append(principal, nonce, ts_ms)
+        };
+        if let Err(e) = res {
+            warn!(path = %spill.path.display(), "replay file: {e}");
+        }
+    }
+}
+
+impl Seen {
+    /// Anything stamped before `cutoff` would now fail the window check anyway. Returns how many went.
+    fn expire(&mut self, cutoff: u64) -> usize {
+        let mut expired = 0;
+        while let Some(first) = self.by_ts.first() {
+            if first.0 >= cutoff {
+                break;
+            }
+            let (_, principal, nonce) = self.by_ts.pop_first().expect("checked non-empty");
+            self.keys.remove(&(principal, nonce));
+            expired += 1;
+        }
+        expired
+    }
+}
+
+impl Spill {
+    /// Replace `path` with exactly `entries`.
+    fn rewrite(path: &Path, entries: &BTreeSet<(u64, String, String)>) -> io::Result<Self> {
+        let mut buf = Vec::new();
+        for (ts_ms, principal, nonce) in entries {
+            buf.extend(line(principal, nonce, *ts_ms));
+        }
+        write_atomic(path, &buf)?;
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            lines: entries.len(),
+        })
+    }
+
+    fn append(&mut self, principal: &str, nonce: &str, ts_ms: u64) -> io::Result<()> {
+        self.file.write_all(&line(principal, nonce, ts_ms))?;
+        self.lines += 1;
+        Ok(())
+    }
+}
+
+fn line(principal: &str, nonce: &str, ts_ms: u64) -> Vec<u8> {
+    let l = SpillLine {
+        ts_ms,
+        principal: principal.to_string(),
+        nonce: nonce.to_string(),
+    };
+    let mut out = serde_json::to_vec(&l).expect("strings and integers serialize");
+    out.push(b'\n');
+    out
+}
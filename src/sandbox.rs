@@ -0,0 +1,82 @@
+//! `sandbox`: confine a target's command with Landlock (paths) and seccomp (syscalls). Both are prepared in the
+//! daemon and applied in the child just before exec; a kernel that cannot enforce them fails the run.
+
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+use landlock::{
+    path_beneath_rules, Access, AccessFs, CompatLevel, Compatible, Ruleset, RulesetAttr, RulesetCreated,
+    RulesetCreatedAttr, ABI,
+};
+use seccompiler::{BpfProgram, TargetArch};
+
+use crate::bunker::TargetSandbox;
+
+/// Landlock ABI v1 (Linux 5.13) covers reading, writing and executing files.
+const ABI_VERSION: ABI = ABI::V1;
+
+/// Allowed even by a `syscalls` list, or the command could neither start nor exit.
+const ALWAYS_ALLOWED: &[&str] = &["execve", "exit", "exit_group"];
+
+/// The seccomp program for `sandbox` on this machine's architecture, if it filters syscalls.
+pub(crate) fn compile_filter(sandbox: &TargetSandbox) -> Result<Option<BpfProgram>, String> {
+    let (names, mismatch, matched): (Vec<&str>, _, _) = match &sandbox.syscalls {
+        Some(allowed) => (
+            allowed.iter().map(String::as_str).chain(ALWAYS_ALLOWED.iter().copied()).collect(),
+            serde_json::json!({ "errno": libc::EPERM }),
+            serde_json::json!("allow"),
+        ),
+        None if !sandbox.deny_syscalls.is_empty() => (
+            sandbox.deny_syscalls.iter().map(String::as_str).collect(),
+            serde_json::json!("allow"),
+            serde_json::json!({ "errno": libc::EPERM }),
+        ),
+        None => return Ok(None),
+    };
+    let arch = TargetArch::try_from(std::env::consts::ARCH).map_err(|e| format!("sandbox: {e}"))?;
+    let rules: Vec<_> = names.iter().map(|n| serde_json::json!({ "syscall": n })).collect();
+    let json = serde_json::json!({
+        "target": { "mismatch_action": mismatch, "match_action": matched, "filter": rules },
+    });
+    let mut map = seccompiler::compile_from_json(json.to_string().as_bytes(), arch)
+        .map_err(|e| format!("sandbox syscalls: {e}"))?;
+    Ok(map.remove("target"))
+}
+
+fn ruleset(sandbox: &TargetSandbox) -> Result<RulesetCreated, String> {
+    if let Some(p) = sandbox.read.iter().chain(&sandbox.write).find(|p| !Path::new(p).exists()) {
+        return Err(format!("sandbox path '{p}' does not exist"));
+    }
+    let err = |e: landlock::RulesetError| format!("sandbox paths: {e}");
+    Ruleset::default()
+        .set_compatibility(CompatLevel::HardRequirement)
+        .handle_access(AccessFs::from_all(ABI_VERSION))
+        .map_err(err)?
+        .create()
+        .map_err(err)?
+        .add_rules(path_beneath_rules(&sandbox.read, AccessFs::from_read(ABI_VERSION)))
+        .map_err(err)?
+        .add_rules(path_beneath_rules(&sandbox.write, AccessFs::from_all(ABI_VERSION)))
+        .map_err(err)
+}
+
+/// Have `cmd`'s child restrict itself to `sandbox` right before exec, after any uid switch.
+pub(crate) fn confine(cmd: &mut Command, sandbox: &TargetSandbox) -> Result<(), String> {
+    let mut ruleset = sandbox.restricts_paths().then(|| ruleset(sandbox)).transpose()?;
+    let filter = compile_filter(sandbox)?;
+    // SAFETY: both were built before the fork; applying them makes only prctl, landlock and seccomp syscalls.
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(r) = ruleset.take() {
+                r.restrict_self().map_err(io::Error::other)?;
+            }
+            if let Some(f) = &filter {
+                seccompiler::apply_filter(f).map_err(io::Error::other)?;
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,20 @@
+//! `stdin_schema`: target stdin checked against a JSON Schema before the target runs.
+
+use serde_json::Value;
+
+pub(crate) fn compile(schema: &str) -> Result<jsonschema::Validator, String> {
+    let schema: Value = serde_json::from_str(schema).map_err(|e| format!("stdin_schema: {e}"))?;
+    jsonschema::validator_for(&schema).map_err(|e| format!("stdin_schema: {e}"))
+}
+
+/// Errors name where in the document it failed and why, in the schema's terms.
+pub(crate) fn check(schema: &str, stdin: &str) -> Result<(), String> {
+    let validator = compile(schema)?;
+    let doc: Value =
+        serde_json::from_str(stdin).map_err(|e| format!("non-conforming payload: stdin is not JSON: {e}"))?;
+    validator.validate(&doc).map_err(|e| {
+        let at = e.instance_path().to_string();
+        let at = if at.is_empty() { "/".to_string() } else { at };
+        format!("non-conforming payload: stdin at {at} does not match its schema: {e}")
+    })
+}
@@ -0,0 +1,89 @@
+//! Target kinds that push bunker secrets to where other systems read them.
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use base64::Engine;
+
+use crate::invoke::run_target;
+
+/// `kubectl apply` an Opaque Secret built from `data`; the manifest goes over stdin, never argv.
+pub(crate) fn apply_k8s_secret(
+    namespace: &str,
+    name: &str,
+    data: &BTreeMap<String, String>,
+    kubeconfig: Option<&str>,
+    context: Option<&str>,
+) -> Result<Vec<u8>, String> {
+    let b64 = base64::engine::general_purpose::STANDARD;
+    let manifest = serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "type": "Opaque",
+        "metadata": {
+            "name": name,
+            "namespace": namespace,
+            "labels": { "app.kubernetes.io/managed-by": "turret" },
+        },
+        "data": data.iter().map(|(k, v)| (k.clone(), b64.encode(v))).collect::<BTreeMap<_, _>>(),
+    });
+    let manifest = serde_json::to_vec(&manifest).map_err(|e| e.to_string())?;
+
+    let mut argv = Vec::new();
+    if let Some(ctx) = context {
+        argv.extend(["--context".to_string(), ctx.to_string()]);
+    }
+    argv.extend(["apply", "-o", "name", "-f", "-"].map(String::from));
+
+    // Without an explicit kubeconfig, kubectl falls back to ~/.kube/config.
+    let mut env = BTreeMap::new();
+    match kubeconfig {
+        Some(path) => {
+            env.insert("KUBECONFIG".to_string(), path.to_string());
+        }
+        None => {
+            if let Ok(home) = std::env::var("HOME") {
+                env.insert("HOME".to_string(), home);
+            }
+        }
+    }
+    run_target("kubectl", &argv, &env, &manifest)
+}
+
+/// Write each file atomically with `mode`; returns the names written, one per line.
+pub(crate) fn write_secret_files(
+    dir: &str,
+    files: &BTreeMap<String, String>,
+    mode: u32,
+) -> Result<Vec<u8>, String> {
+    let dir = Path::new(dir);
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+    let mut out = Vec::new();
+    for (name, value) in files {
+        let path = dir.join(name);
+        let tmp = dir.join(format!(".{name}.turret-tmp"));
+        let write = || -> std::io::Result<()> {
+            let mut f = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&tmp)?;
+            f.write_all(value.as_bytes())?;
+            f.sync_data()?;
+            f.set_permissions(std::fs::Permissions::from_mode(mode))?;
+            std::fs::rename(&tmp, &path)
+        };
+        if let Err(e) = write() {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(format!("write {}: {e}", path.display()));
+        }
+        writeln!(out, "{name}").map_err(|e| e.to_string())?;
+    }
+    Ok(out)
+}
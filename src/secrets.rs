@@ -0,0 +1,297 @@
+//! External secret stores. Bunker `[secret_sources]` entries point into them, and `[secrets]` values may be
+//! `env:`/`file:`/`exec:` references; both are resolved into the in-memory `secrets` map at engage and
+//! never written back.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bunker::Bunker;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecretError {
+    #[error("secret '{name}': {message}")]
+    Fetch { name: String, message: String },
+    #[error("secret provider '{provider}': {message}")]
+    Provider { provider: String, message: String },
+}
+
+/// `[secret_providers.<name>]`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProviderConfig {
+    /// HashiCorp Vault KV (v1 or v2) over its HTTP API, via curl.
+    Vault {
+        addr: String,
+        /// File holding a Vault token.
+        #[serde(default)]
+        token_file: Option<String>,
+        /// AppRole login, used when `token_file` is absent.
+        #[serde(default)]
+        role_id: Option<String>,
+        #[serde(default)]
+        secret_id_file: Option<String>,
+        #[serde(default)]
+        namespace: Option<String>,
+    },
+    /// SOPS-encrypted files, decrypted with the `sops` binary and the daemon's key environment.
+    Sops,
+}
+
+/// `[secret_sources.<SECRET_NAME>]`: where a secret's value lives.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecretSource {
+    pub provider: String,
+    /// Vault: API path after `/v1/` (e.g. `secret/data/db`). SOPS: the encrypted file.
+    pub path: String,
+    /// Key in the secret; for SOPS a dotted path into the document.
+    pub field: String,
+}
+
+/// A `[secrets]` value that says where to find the secret instead of holding it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecretRef<'a> {
+    /// `env:NAME`: the daemon's environment variable.
+    Env(&'a str),
+    /// `file:/abs/path`: the file's contents, surrounding whitespace trimmed.
+    File(&'a str),
+    /// `exec:prog arg..`: stdout of the command, split on whitespace and run without a shell.
+    Exec(&'a str),
+}
+
+impl<'a> SecretRef<'a> {
+    /// `None` for plain values; `Some(Err)` for a reference prefix with a malformed rest.
+    pub fn parse(value: &'a str) -> Option<Result<Self, &'static str>> {
+        if let Some(name) = value.strip_prefix("env:") {
+            let ok = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            return Some(if ok { Ok(SecretRef::Env(name)) } else { Err("env: needs a variable name") });
+        }
+        if let Some(path) = value.strip_prefix("file:") {
+            let ok = path.starts_with('/');
+            return Some(if ok { Ok(SecretRef::File(path)) } else { Err("file: needs an absolute path") });
+        }
+        if let Some(cmd) = value.strip_prefix("exec:") {
+            let ok = !cmd.trim().is_empty();
+            return Some(if ok { Ok(SecretRef::Exec(cmd)) } else { Err("exec: needs a command") });
+        }
+        None
+    }
+
+    fn resolve(self) -> Result<String, String> {
+        match self {
+            SecretRef::Env(name) => std::env::var(name).map_err(|e| format!("${name}: {e}")),
+            SecretRef::File(path) => read_trimmed(path),
+            SecretRef::Exec(cmd) => {
+                let mut argv = cmd.split_whitespace();
+                let prog = argv.next().unwrap_or_default();
+                let out = Command::new(prog)
+                    .args(argv)
+                    .stdin(Stdio::null())
+                    .output()
+                    .map_err(|e| format!("spawn {prog} failed: {e}"))?;
+                if !out.status.success() {
+                    return Err(format!("{prog}: {}", String::from_utf8_lossy(&out.stderr).trim()));
+                }
+                let value = String::from_utf8(out.stdout).map_err(|_| format!("{prog}: output is not utf-8"))?;
+                Ok(value.trim_end_matches(['\n', '\r']).to_string())
+            }
+        }
+    }
+}
+
+pub trait SecretProvider {
+    fn fetch(&self, source: &SecretSource) -> Result<String, String>;
+}
+
+impl ProviderConfig {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        match self {
+            ProviderConfig::Vault {
+                addr,
+                token_file,
+                role_id,
+                secret_id_file,
+                ..
+            } => {
+                if !(addr.starts_with("https://") || addr.starts_with("http://")) {
+                    return Err("vault addr must be an http(s) url");
+                }
+                if token_file.is_none() && (role_id.is_none() || secret_id_file.is_none()) {
+                    return Err("vault provider needs token_file or role_id + secret_id_file");
+                }
+            }
+            ProviderConfig::Sops => {}
+        }
+        Ok(())
+    }
+
+    /// Build the provider, logging in where the backend needs it.
+    pub fn connect(&self) -> Result<Box<dyn SecretProvider>, String> {
+        match self {
+            ProviderConfig::Vault {
+                addr,
+                token_file,
+                role_id,
+                secret_id_file,
+                namespace,
+            } => {
+                let mut vault = VaultProvider {
+                    addr: addr.trim_end_matches('/').to_string(),
+                    namespace: namespace.clone(),
+                    token: String::new(),
+                };
+                vault.token = match (token_file, role_id, secret_id_file) {
+                    (Some(f), _, _) => read_trimmed(f)?,
+                    (None, Some(role), Some(f)) => vault.approle_login(role, &read_trimmed(f)?)?,
+                    _ => return Err("no vault credentials configured".to_string()),
+                };
+                Ok(Box::new(vault))
+            }
+            ProviderConfig::Sops => Ok(Box::new(SopsProvider)),
+        }
+    }
+}
+
+pub struct VaultProvider {
+    addr: String,
+    namespace: Option<String>,
+    token: String,
+}
+
+impl VaultProvider {
+    fn approle_login(&self, role_id: &str, secret_id: &str) -> Result<String, String> {
+        let body = serde_json::json!({ "role_id": role_id, "secret_id": secret_id }).to_string();
+        let url = format!("{}/v1/auth/approle/login", self.addr);
+        let mut args = vec!["-X".to_string(), "POST".to_string(), "--data-binary".to_string(), "@-".to_string()];
+        if let Some(ns) = &self.namespace {
+            args.extend(["-H".to_string(), format!("X-Vault-Namespace: {ns}")]);
+        }
+        let resp = curl(&url, &args, body.as_bytes())?;
+        resp.pointer("/auth/client_token")
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| "approle login returned no client_token".to_string())
+    }
+}
+
+impl SecretProvider for VaultProvider {
+    fn fetch(&self, source: &SecretSource) -> Result<String, String> {
+        let url = format!("{}/v1/{}", self.addr, source.path.trim_start_matches('/'));
+        // The token travels as a header read from stdin so it never shows up in argv.
+        let mut headers = format!("X-Vault-Token: {}\n", self.token);
+        if let Some(ns) = &self.namespace {
+            headers.push_str(&format!("X-Vault-Namespace: {ns}\n"));
+        }
+        let resp = curl(&url, &["-H".to_string(), "@-".to_string()], headers.as_bytes())?;
+        // KV v2 nests the secret under data.data; v1 has it under data.
+        let data = resp
+            .pointer("/data/data")
+            .filter(|v| v.is_object())
+            .or_else(|| resp.pointer("/data"))
+            .ok_or_else(|| "response has no data".to_string())?;
+        match data.get(&source.field) {
+            Some(serde_json::Value::String(s)) => Ok(s.clone()),
+            Some(v) => Ok(v.to_string()),
+            None => Err(format!("field '{}' not found", source.field)),
+        }
+    }
+}
+
+pub struct SopsProvider;
+
+impl SecretProvider for SopsProvider {
+    fn fetch(&self, source: &SecretSource) -> Result<String, String> {
+        let out = Command::new("sops")
+            .args(["--decrypt", "--output-type", "json"])
+            .arg(&source.path)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("spawn sops failed: {e}"))?;
+        if !out.status.success() {
+            return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+        }
+        let doc: serde_json::Value =
+            serde_json::from_slice(&out.stdout).map_err(|e| format!("sops output: {e}"))?;
+        let mut v = &doc;
+        for part in source.field.split('.') {
+            v = v
+                .get(part)
+                .ok_or_else(|| format!("field '{}' not found", source.field))?;
+        }
+        match v {
+            serde_json::Value::String(s) => Ok(s.clone()),
+            other => Ok(other.to_string()),
+        }
+    }
+}
+
+/// Replace `[secrets]` references with their values and fetch every `[secret_sources]` entry into
+/// `bunker.secrets`, connecting each provider once.
+pub fn resolve(bunker: &mut Bunker) -> Result<(), SecretError> {
+    for (name, value) in bunker.secrets.iter_mut() {
+        let Some(parsed) = SecretRef::parse(value) else {
+            continue;
+        };
+        let fetch_err = |message: String| SecretError::Fetch {
+            name: name.clone(),
+            message,
+        };
+        *value = parsed.map_err(|e| fetch_err(e.to_string()))?.resolve().map_err(fetch_err)?;
+    }
+
+    let mut connected: BTreeMap<&str, Box<dyn SecretProvider>> = BTreeMap::new();
+    for (name, source) in &bunker.secret_sources {
+        if !connected.contains_key(source.provider.as_str()) {
+            let cfg = bunker
+                .secret_providers
+                .get(&source.provider)
+                .ok_or_else(|| SecretError::Provider {
+                    provider: source.provider.clone(),
+                    message: "not configured".to_string(),
+                })?;
+            let p = cfg.connect().map_err(|message| SecretError::Provider {
+                provider: source.provider.clone(),
+                message,
+            })?;
+            connected.insert(source.provider.as_str(), p);
+        }
+        let provider = &connected[source.provider.as_str()];
+        let value = provider.fetch(source).map_err(|message| SecretError::Fetch {
+            name: name.clone(),
+            message,
+        })?;
+        bunker.secrets.insert(name.clone(), value);
+    }
+    Ok(())
+}
+
+fn read_trimmed(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(Path::new(path))
+        .map(|s| s.trim().to_string())
+        .map_err(|e| format!("read {path}: {e}"))
+}
+
+fn curl(url: &str, args: &[String], stdin: &[u8]) -> Result<serde_json::Value, String> {
+    let mut child = Command::new("curl")
+        .args(["-fsS", "-m", "10"])
+        .args(args)
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("spawn curl failed: {e}"))?;
+    if let Some(mut s) = child.stdin.take() {
+        s.write_all(stdin).map_err(|e| format!("write curl stdin failed: {e}"))?;
+    }
+    let out = child
+        .wait_with_output()
+        .map_err(|e| format!("wait curl failed: {e}"))?;
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+    }
+    serde_json::from_slice(&out.stdout).map_err(|e| format!("bad json from {url}: {e}"))
+}
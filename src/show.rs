@@ -0,0 +1,315 @@
+//! Human-readable bunker review for `turret <bunker> show` and `diff`. Secret values are masked unless
+//! revealed by name, and never appear in diffs.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::agent_secret;
+use crate::bunker::{glob_match, is_pattern, Bunker, SecretMeta, TargetDef, TargetKind};
+use crate::secrets::SecretRef;
+
+/// Stands in for secret values in `show` and `export --redact`.
+pub const MASK: &str = "********";
+
+/// Sections separated by blank lines; one tab-separated row per entry.
+pub fn render(b: &Bunker, reveal: &BTreeSet<String>, now_ms: u64) -> String {
+    let mut out = String::new();
+
+    out.push_str("operators\n");
+    for op in &b.operators {
+        let _ = writeln!(out, "  {}", key_label(op));
+    }
+
+    out.push_str("\nrecruits\n");
+    for agent in recruits(b) {
+        let cred = match b.agents.get(agent) {
+            Some(s) if agent_secret::is_hashed(s) => "secret (argon2id)".to_string(),
+            Some(_) => "secret (plaintext)".to_string(),
+            None => match (b.agent_keys.get(agent), b.agent_certs.get(agent)) {
+                (Some(k), _) => format!("key {}", key_label(k)),
+                (None, Some(pin)) => format!("cert sha256:{}", &pin[..16]),
+                (None, None) => String::new(),
+            },
+        };
+        let mut row = format!("  {agent}\t{cred}");
+        if let Some(l) = b.limits.get(agent) {
+            if let (Some(per_minute), Some(burst)) = (l.per_minute, l.burst()) {
+                let _ = write!(row, "\tlimit {per_minute}/min burst {burst}");
+            }
+            for (target, q) in &l.targets {
+                let _ = write!(row, "\tquota {target} {}/h", q.max_per_hour);
+            }
+        }
+        if let Some(p) = b.peers.get(agent) {
+            let _ = write!(row, "\tpeers uids={:?} gids={:?}", p.uids, p.gids);
+        }
+        out.push_str(&row);
+        out.push('\n');
+    }
+
+    out.push_str("\ntargets\n");
+    for (name, def) in &b.targets {
+        let mut row = format!("  {name}\t{}", target_label(def));
+        if let Some(secs) = def.two_person_window_secs {
+            let _ = write!(row, "\ttwo-person within {secs}s");
+        }
+        if def.require_approval {
+            row.push_str("\trequires approval");
+        }
+        if let Some(n) = def.max_concurrent {
+            let _ = write!(row, "\t{n} at once, {} queued", def.max_queued.unwrap_or(0));
+        }
+        out.push_str(&row);
+        out.push('\n');
+    }
+
+    if !b.roles.is_empty() {
+        out.push_str("\nroles\n");
+        for (role, targets) in &b.roles {
+            let names: Vec<&str> = targets.iter().map(String::as_str).collect();
+            let _ = writeln!(out, "  {role}\t{}", names.join(", "));
+        }
+    }
+
+    if !b.base_env.is_empty() {
+        out.push_str("\nbase env\n");
+        for (k, v) in &b.base_env {
+            let _ = writeln!(out, "  {k}\t{v}");
+        }
+    }
+
+    out.push_str("\npermissions\n");
+    let targets: Vec<&String> = b.targets.keys().collect();
+    let header: Vec<&str> = targets.iter().map(|t| t.as_str()).collect();
+    let _ = writeln!(out, "  \t{}", header.join("\t"));
+    for agent in recruits(b) {
+        let direct = b.permissions.get(agent);
+        let cells: Vec<&str> = targets
+            .iter()
+            .map(|t| {
+                let live = |g: &String| b.grant_expires(agent, g).is_none_or(|at| now_ms < at);
+                let names = |g: &String| g == *t || (is_pattern(g) && glob_match(g, t));
+                let by_name = direct.is_some_and(|d| d.iter().any(|g| live(g) && names(g)));
+                match (by_name, b.may_fire(agent, t, now_ms)) {
+                    (true, _) => "x",
+                    (false, true) => "r",
+                    (false, false) => "-",
+                }
+            })
+            .collect();
+        let _ = writeln!(out, "  {agent}\t{}", cells.join("\t"));
+    }
+    for (agent, expiring) in &b.grant_expiry {
+        for (grant, at) in expiring {
+            let _ = writeln!(out, "  {agent} -> {grant}\t{}", expiry_label(*at, now_ms));
+        }
+    }
+    for (agent, single) in &b.single_use {
+        for grant in single.keys() {
+            let _ = writeln!(out, "  {agent} -> {grant}\tsingle use");
+        }
+    }
+
+    out.push_str("\nsecrets\n");
+    let expiry = |name: &str| match b.secret_meta.get(name).and_then(SecretMeta::expires_ms) {
+        Some(at) => format!("\t{}", expiry_label(at, now_ms)),
+        None => String::new(),
+    };
+    for (name, value) in &b.secrets {
+        // References name where the secret lives, not the secret itself.
+        let is_ref = SecretRef::parse(value).is_some();
+        let shown = if reveal.contains(name) || is_ref { value.as_str() } else { MASK };
+        let _ = writeln!(out, "  {name}\t{shown}{}", expiry(name));
+    }
+    for (name, src) in &b.secret_sources {
+        let _ = writeln!(out, "  {name}\tfrom {} {}#{}{}", src.provider, src.path, src.field, expiry(name));
+    }
+
+    if let Some(a) = &b.alerts {
+        out.push_str("\nalerts\n");
+        if let Some(exec) = &a.exec {
+            let _ = writeln!(out, "  exec\t{}", exec.join(" "));
+        }
+        if a.webhook.is_some() {
+            let _ = writeln!(out, "  webhook\t{MASK}");
+        }
+        let _ = writeln!(out, "  auth_failures\t{} in {}s", a.auth_failures, a.auth_window_secs);
+    }
+    out
+}
+
+/// `+`, `-` and `~` (changed) lines from `a` to `b`, grouped by section.
+pub fn diff(a: &Bunker, b: &Bunker) -> Vec<String> {
+    let mut out = Vec::new();
+    for op in a.operators.difference(&b.operators) {
+        out.push(format!("- operator {}", key_label(op)));
+    }
+    for op in b.operators.difference(&a.operators) {
+        out.push(format!("+ operator {}", key_label(op)));
+    }
+
+    let creds = |x: &Bunker| -> BTreeMap<String, String> {
+        let secrets = x.agents.iter().map(|(k, v)| (k.clone(), format!("secret:{v}")));
+        let keys = x.agent_keys.iter().map(|(k, v)| (k.clone(), format!("key:{v}")));
+        secrets.chain(keys).chain(x.agent_certs.iter().map(|(k, v)| (k.clone(), format!("cert:{v}")))).collect()
+    };
+    diff_map(&mut out, "recruit", &creds(a), &creds(b), |_, _| vec!["credential"]);
+    diff_map(&mut out, "limit", &a.limits, &b.limits, |_, _| Vec::new());
+    diff_map(&mut out, "peers", &a.peers, &b.peers, |_, _| Vec::new());
+    diff_map(&mut out, "base_env", &a.base_env, &b.base_env, |_, _| vec!["value"]);
+
+    diff_map(&mut out, "role", &a.roles, &b.roles, |_, _| vec!["targets"]);
+    diff_map(&mut out, "target", &a.targets, &b.targets, |x, y| {
+        let mut parts = Vec::new();
+        if x.shape != y.shape {
+            parts.push("shape");
+        }
+        if x.transform != y.transform {
+            parts.push("transform");
+        }
+        if x.kind != y.kind {
+            parts.push("kind");
+        }
+        if x.allow_failure != y.allow_failure {
+            parts.push("allow_failure");
+        }
+        if x.require_approval != y.require_approval {
+            parts.push("require_approval");
+        }
+        if x.two_person_window_secs != y.two_person_window_secs {
+            parts.push("two_person_window_secs");
+        }
+        let queueing = |d: &TargetDef| (d.max_concurrent, d.max_queued, d.queue_timeout_secs);
+        if queueing(x) != queueing(y) {
+            parts.push("max_concurrent");
+        }
+        parts
+    });
+
+    let grants = |x: &Bunker| -> BTreeSet<(String, String)> {
+        x.permissions
+            .iter()
+            .flat_map(|(agent, targets)| targets.iter().map(|t| (agent.clone(), t.clone())))
+            .collect()
+    };
+    let (ga, gb) = (grants(a), grants(b));
+    for (agent, target) in ga.difference(&gb) {
+        out.push(format!("- allow {agent} {target}"));
+    }
+    for (agent, target) in gb.difference(&ga) {
+        out.push(format!("+ allow {agent} {target}"));
+    }
+    let terms = |x: &BTreeMap<String, BTreeMap<String, u64>>| -> BTreeMap<String, u64> {
+        x.iter()
+            .flat_map(|(agent, e)| e.iter().map(move |(g, at)| (format!("{agent} {g}"), *at)))
+            .collect()
+    };
+    diff_map(&mut out, "grant expiry", &terms(&a.grant_expiry), &terms(&b.grant_expiry), |_, _| Vec::new());
+    diff_map(&mut out, "single use", &terms(&a.single_use), &terms(&b.single_use), |_, _| Vec::new());
+
+    diff_map(&mut out, "secret", &a.secrets, &b.secrets, |_, _| vec!["value"]);
+    diff_map(&mut out, "secret expiry", &a.secret_meta, &b.secret_meta, |_, _| Vec::new());
+    diff_map(&mut out, "secret source", &a.secret_sources, &b.secret_sources, |_, _| Vec::new());
+    diff_map(&mut out, "secret provider", &a.secret_providers, &b.secret_providers, |_, _| Vec::new());
+    match (&a.alerts, &b.alerts) {
+        (None, Some(_)) => out.push("+ alerts".to_string()),
+        (Some(_), None) => out.push("- alerts".to_string()),
+        (Some(x), Some(y)) if x != y => out.push("~ alerts".to_string()),
+        _ => {}
+    }
+    out
+}
+
+/// Keys only in `a` or `b`, and those whose values differ with `what` naming the changed parts.
+fn diff_map<V: PartialEq>(
+    out: &mut Vec<String>,
+    label: &str,
+    a: &BTreeMap<String, V>,
+    b: &BTreeMap<String, V>,
+    what: impl Fn(&V, &V) -> Vec<&'static str>,
+) {
+    for (name, x) in a {
+        match b.get(name) {
+            None => out.push(format!("- {label} {name}")),
+            Some(y) if x != y => match what(x, y).as_slice() {
+                [] => out.push(format!("~ {label} {name}")),
+                parts => out.push(format!("~ {label} {name} ({})", parts.join(", "))),
+            },
+            Some(_) => {}
+        }
+    }
+    for name in b.keys().filter(|name| !a.contains_key(*name)) {
+        out.push(format!("+ {label} {name}"));
+    }
+}
+
+/// A copy safe to commit for review: `[secrets]` values and recruit secrets (hashed or not) become [`MASK`],
+/// and the signature, which no longer matches, is dropped.
+pub fn redacted(b: &Bunker) -> Bunker {
+    let mut b = b.clone();
+    b.secrets.values_mut().chain(b.agents.values_mut()).for_each(|v| *v = MASK.to_string());
+    if let Some(alerts) = &mut b.alerts {
+        if let Some(url) = &mut alerts.webhook {
+            *url = MASK.to_string();
+        }
+    }
+    b.signature = None;
+    b
+}
+
+/// Whether `b` came from `export --redact` and so cannot be imported.
+pub fn is_redacted(b: &Bunker) -> bool {
+    b.secrets.values().chain(b.agents.values()).any(|v| v == MASK)
+        || b.alerts.as_ref().and_then(|a| a.webhook.as_deref()) == Some(MASK)
+}
+
+/// Secrets within this long of expiring are flagged.
+const EXPIRY_WARN_MS: u64 = 14 * 24 * 3600 * 1000;
+
+pub(crate) fn expiry_label(at_ms: u64, now_ms: u64) -> String {
+    let span = |ms: u64| match (ms / 86_400_000, ms / 3_600_000) {
+        (0, 0) => format!("{}m", ms / 60_000),
+        (0, hours) => format!("{hours}h"),
+        (days, _) => format!("{days}d"),
+    };
+    match at_ms.checked_sub(now_ms).filter(|&left| left > 0) {
+        None => format!("EXPIRED {} ago", span(now_ms - at_ms)),
+        Some(left) if left <= EXPIRY_WARN_MS => format!("EXPIRES SOON, in {}", span(left)),
+        Some(left) => format!("expires in {}", span(left)),
+    }
+}
+
+/// Every recruit, sorted.
+fn recruits(b: &Bunker) -> BTreeSet<&String> {
+    b.recruits().collect()
+}
+
+/// What firing the target does, without secret values: templates stay as `{NAME}` tokens.
+pub fn target_label(def: &TargetDef) -> String {
+    match &def.kind {
+        None if def.transform.out_command_sha256.is_some() => format!("exec {} (pinned)", def.transform.out_command),
+        None => format!("exec {}", def.transform.out_command),
+        Some(TargetKind::Container { container, runtime, .. }) => {
+            format!("{} exec {container} {}", runtime.program(), def.transform.out_command)
+        }
+        Some(TargetKind::K8sSecret { namespace, name, .. }) => format!("k8s_secret {namespace}/{name}"),
+        Some(TargetKind::SecretFiles { dir, files, .. }) => {
+            let names: Vec<&str> = files.keys().map(String::as_str).collect();
+            format!("secret_files {dir} [{}]", names.join(", "))
+        }
+        Some(TargetKind::Remote { ssh, bunker, target, .. }) => format!("remote {ssh} {bunker}/{target}"),
+        Some(TargetKind::Wasm { module, .. }) => format!("wasm {module}"),
+        Some(TargetKind::FileRead { path, .. }) => format!("file_read {path}"),
+        Some(TargetKind::FileWrite { path, append, .. }) => {
+            format!("file_write {path}{}", if *append { " (append)" } else { "" })
+        }
+    }
+}
+
+/// An ssh key's fingerprint, or the recipient as given (`age1...`).
+pub fn key_label(key: &str) -> String {
+    match ssh_key::PublicKey::from_openssh(key) {
+        Ok(k) => k.fingerprint(Default::default()).to_string(),
+        Err(_) => key.to_string(),
+    }
+}
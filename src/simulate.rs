@@ -0,0 +1,177 @@
+//! `turret <bunker> simulate`: walk an invoke through the daemon's checks against the bunker, without a daemon,
+//! and say which one would refuse it. Checks that depend on live daemon state are reported as notes.
+
+use crate::bunker::Bunker;
+use crate::invoke::{self, InvokePayload};
+use crate::once::OnceStore;
+use crate::show::expiry_label;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    /// Refused, with the error code the daemon would answer.
+    Deny(&'static str),
+    /// Decided by the running daemon, not the bunker.
+    Note,
+}
+
+#[derive(Debug)]
+pub struct Step {
+    pub rule: &'static str,
+    pub verdict: Verdict,
+    pub detail: String,
+}
+
+impl Step {
+    fn pass(rule: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            rule,
+            verdict: Verdict::Pass,
+            detail: detail.into(),
+        }
+    }
+
+    fn deny(rule: &'static str, code: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            rule,
+            verdict: Verdict::Deny(code),
+            detail: detail.into(),
+        }
+    }
+
+    fn note(rule: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            rule,
+            verdict: Verdict::Note,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Every check in the daemon's order, up to and including the first that denies.
+pub fn simulate(b: &Bunker, once: &OnceStore, payload: &InvokePayload, now_ms: u64) -> Vec<Step> {
+    let mut steps = Vec::new();
+    if let Err(denied) = walk(b, once, payload, now_ms, &mut steps) {
+        steps.push(denied);
+    }
+    steps
+}
+
+fn walk(
+    b: &Bunker,
+    once: &OnceStore,
+    payload: &InvokePayload,
+    now_ms: u64,
+    steps: &mut Vec<Step>,
+) -> Result<(), Step> {
+    let agent = payload.agent_id.as_str();
+    let target = payload.target.as_str();
+
+    if let Some(allow) = b.peers.get(agent) {
+        let ids: Vec<String> = allow
+            .uids
+            .iter()
+            .map(|u| format!("uid {u}"))
+            .chain(allow.gids.iter().map(|g| format!("gid {g}")))
+            .collect();
+        steps.push(Step::note("peers", format!("only from {} on the unix socket", ids.join(", "))));
+    }
+
+    if b.agent_keys.contains_key(agent) {
+        steps.push(Step::note("authentication", "signs with a key; checked by the daemon"));
+    } else if b.agent_certs.contains_key(agent) {
+        steps.push(Step::note("authentication", "presents a TLS client certificate; checked by the daemon"));
+    } else if let Some(stored) = b.agents.get(agent) {
+        if payload.agent_secret.is_empty() {
+            steps.push(Step::note("authentication", "no agent_secret given; not checked"));
+        } else if crate::agent_secret::verify(stored, &payload.agent_secret) {
+            steps.push(Step::pass("authentication", "agent_secret matches"));
+        } else {
+            return Err(Step::deny("authentication", "unauthenticated", "agent_secret does not match"));
+        }
+    } else {
+        return Err(Step::deny("authentication", "unauthenticated", format!("no recruit '{agent}'")));
+    }
+
+    let limit = b.limits.get(agent);
+    if let Some(per_minute) = limit.and_then(|l| l.per_minute) {
+        let burst = limit.and_then(|l| l.burst()).unwrap_or(per_minute);
+        steps.push(Step::note("rate limit", format!("{per_minute}/min, burst {burst}")));
+    }
+
+    let grants: Vec<&String> = b.grants_for(agent, target, now_ms).collect();
+    if grants.is_empty() {
+        let lapsed = b.permissions.get(agent).into_iter().flatten().find_map(|g| {
+            let at = b.grant_expires(agent, g).filter(|_| b.grant_covers(g, target))?;
+            Some(format!("grant of '{g}' {}", expiry_label(at, now_ms)))
+        });
+        let detail = lapsed.unwrap_or_else(|| format!("'{agent}' has no grant covering '{target}'"));
+        return Err(Step::deny("permission", "denied", detail));
+    }
+    if let Some(g) = grants.iter().find(|g| b.single_use_issued(agent, g).is_none()) {
+        steps.push(Step::pass("permission", format!("granted by '{g}'")));
+    } else {
+        let unspent = grants.iter().find(|g| {
+            b.single_use_issued(agent, g)
+                .is_some_and(|issued| !once.is_spent(agent, g, issued))
+        });
+        let g = unspent.ok_or_else(|| Step::deny("permission", "denied", "every single-use grant is spent"))?;
+        steps.push(Step::pass("permission", format!("single-use grant '{g}', unspent")));
+    }
+
+    if let Some(quota) = limit.and_then(|l| l.targets.get(target)) {
+        steps.push(Step::note("quota", format!("{}/hour", quota.max_per_hour)));
+    }
+
+    let def = b
+        .targets
+        .get(target)
+        .ok_or_else(|| Step::deny("target", "unknown_target", format!("no target '{target}'")))?;
+    if let Some(name) = b.expired_secret(def, now_ms) {
+        return Err(Step::deny("secret expiry", "secret_expired", format!("secret '{name}' has expired")));
+    }
+
+    invoke::check_fields(def, payload).map_err(|e| Step::deny("shape", "bad_request", e))?;
+    steps.push(Step::pass("shape", "fields allowed"));
+    if def.shape.argv_placeholders.is_some() {
+        invoke::check_placeholders(def, payload).map_err(|e| Step::deny("placeholders", "bad_request", e))?;
+        steps.push(Step::pass("placeholders", "count matches"));
+    }
+    if def.shape.constrains_argv_values() {
+        invoke::check_argv_values(def, payload).map_err(|e| Step::deny("argv values", "bad_request", e))?;
+        steps.push(Step::pass("argv values", "patterns and choices match"));
+    }
+    if def.params_taken() > 0 || payload.params.is_some() {
+        invoke::check_params(def, payload).map_err(|e| Step::deny("params", "bad_request", e))?;
+        steps.push(Step::pass("params", format!("{} taken", def.params_taken())));
+    }
+    if def.shape.constrains_env() {
+        invoke::check_env(def, payload).map_err(|e| Step::deny("env", "bad_request", e))?;
+        steps.push(Step::pass("env", "keys and values allowed"));
+    }
+    if def.shape.max_stdin_bytes.is_some() || def.shape.stdin_schema.is_some() {
+        invoke::check_stdin(def, payload).map_err(|e| Step::deny("stdin", "bad_request", e))?;
+        steps.push(Step::pass("stdin", "size and schema allowed"));
+    }
+    let plan = invoke::resolve(def, payload.clone(), &b.secrets)
+        .map_err(|e| Step::deny("transform", e.code(), e.to_string()))?;
+    let argv = plan.argv.iter().map(|a| format!(" {a}")).collect::<String>();
+    let mut line = format!("{}{argv}", plan.command);
+    for step in &plan.pipe {
+        let argv = step.argv.iter().map(|a| format!(" {a}")).collect::<String>();
+        line.push_str(&format!(" | {}{argv}", step.command));
+    }
+    steps.push(Step::pass("transform", line));
+
+    if let Some(secs) = def.two_person_window_secs {
+        steps.push(Step::note("two-person", format!("a second recruit must send it within {secs}s")));
+    }
+    if def.require_approval {
+        steps.push(Step::note("approval", "an operator must approve it"));
+    }
+    if let Some(n) = def.max_concurrent {
+        let queued = def.max_queued.unwrap_or(0);
+        steps.push(Step::note("concurrency", format!("{n} at once; beyond that {queued} wait, the rest fail as busy")));
+    }
+    Ok(())
+}
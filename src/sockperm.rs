@@ -0,0 +1,84 @@
+//! Binding the fire and admin sockets with an explicit mode, owner and group.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+/// Unset fields keep what a plain bind would give: the umask-derived mode and our own uid/gid.
+#[derive(Clone, Debug, Default)]
+pub struct SocketPerms {
+    pub mode: Option<u32>,
+    /// User name or numeric uid.
+    pub owner: Option<String>,
+    /// Group name or numeric gid.
+    pub group: Option<String>,
+}
+
+/// Bind `path` reachable only by our own uid until `perms` are in place, so no other user
+/// can queue a connection in between. Mutates the process umask briefly.
+pub fn bind(path: &Path, perms: &SocketPerms) -> io::Result<UnixListener> {
+    let uid = perms.owner.as_deref().map(resolve_user).transpose()?;
+    let gid = perms.group.as_deref().map(resolve_group).transpose()?;
+    let old = unsafe { libc::umask(0o177) };
+    let bound = UnixListener::bind(path);
+    unsafe { libc::umask(old) };
+    let listener = bound?;
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(path, uid, gid)?;
+    }
+    let mode = perms.mode.unwrap_or(0o777 & !old);
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+fn resolve_user(name: &str) -> io::Result<u32> {
+    lookup_user(name).map(|(uid, _)| uid)
+}
+
+/// The uid of a user name or numeric uid, and for a name, its primary gid.
+pub(crate) fn lookup_user(name: &str) -> io::Result<(u32, Option<u32>)> {
+    if let Ok(uid) = name.parse() {
+        return Ok((uid, None));
+    }
+    let c = CString::new(name).map_err(|_| io::Error::other(format!("bad user name '{name}'")))?;
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut out = std::ptr::null_mut();
+    let ret = unsafe { libc::getpwnam_r(c.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut out) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    if out.is_null() {
+        return Err(io::Error::other(format!("no such user '{name}'")));
+    }
+    Ok((pwd.pw_uid, Some(pwd.pw_gid)))
+}
+
+pub(crate) fn resolve_group(name: &str) -> io::Result<u32> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let c = CString::new(name).map_err(|_| io::Error::other(format!("bad group name '{name}'")))?;
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut out = std::ptr::null_mut();
+    let ret = unsafe { libc::getgrnam_r(c.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut out) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    if out.is_null() {
+        return Err(io::Error::other(format!("no such group '{name}'")));
+    }
+    Ok(grp.gr_gid)
+}
+
+/// Parse an octal mode such as `660` or `0o660`.
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("'{s}' is not an octal mode like 0660")),
+    }
+}
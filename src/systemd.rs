@@ -0,0 +1,142 @@
+//! systemd socket activation (`LISTEN_FDS`) and readiness notification (`NOTIFY_SOCKET`).
+
+use std::io;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::path::Path;
+
+/// First fd passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Sockets handed over by a `.socket` unit.
+#[derive(Debug)]
+pub struct Activated {
+    pub fire: UnixListener,
+    pub admin: UnixListener,
+}
+
+/// Take the fire and admin listeners passed by systemd, if this process was socket-activated.
+/// They are matched by `FileDescriptorName=fire|admin`, or taken in that order when unnamed.
+/// Clears the `LISTEN_*` variables so children don't see them; call before spawning threads.
+pub fn listeners() -> io::Result<Option<Activated>> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let count = std::env::var("LISTEN_FDS").ok();
+    let names = std::env::var("LISTEN_FDNAMES").ok();
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    if pid.and_then(|p| p.parse::<u32>().ok()) != Some(std::process::id()) {
+        return Ok(None);
+    }
+    let count: RawFd = count
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| io::Error::other("LISTEN_FDS is not a number"))?;
+    if count != 2 {
+        return Err(io::Error::other(format!("expected 2 activated sockets (fire, admin), got {count}")));
+    }
+    let (fire, admin) = match names.as_deref().map(|n| n.split(':').collect::<Vec<_>>()).as_deref() {
+        Some(["admin", "fire"]) => (LISTEN_FDS_START + 1, LISTEN_FDS_START),
+        Some(["fire", "admin"]) | None => (LISTEN_FDS_START, LISTEN_FDS_START + 1),
+        Some(other) => {
+            return Err(io::Error::other(format!(
+                "activated sockets must be named fire and admin, got {}",
+                other.join(":")
+            )))
+        }
+    };
+    // SAFETY: systemd passes these fds to us alone, and nothing else in the process has claimed them.
+    let (fire, admin) = unsafe { (UnixListener::from_raw_fd(fire), UnixListener::from_raw_fd(admin)) };
+    for l in [&fire, &admin] {
+        set_cloexec(l)?;
+    }
+    Ok(Some(Activated { fire, admin }))
+}
+
+fn set_cloexec(l: &UnixListener) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    if unsafe { libc::fcntl(l.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Send `state` (e.g. `READY=1`) to the service manager. A no-op outside a `Type=notify` unit.
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let path = path.to_string_lossy();
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(path.as_ref()),
+    };
+    let sent = addr.and_then(|addr| UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr));
+    if let Err(e) = sent {
+        tracing::warn!("sd_notify {state}: {e}");
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SocketUnit {
+    Fire,
+    Admin,
+}
+
+/// `turret-<bunker>`; socket units add `-admin` for the admin socket.
+pub fn unit_name(bunker: &str) -> String {
+    format!("turret-{bunker}")
+}
+
+/// A `Type=notify` service running `exec_start` (already absolute) from `workdir`.
+/// With `socket_activated` it takes its sockets from the two [`socket_unit`]s.
+pub fn service_unit(bunker: &str, workdir: &Path, exec_start: &[String], socket_activated: bool) -> String {
+    let name = unit_name(bunker);
+    let mut unit = format!("[Unit]\nDescription=turret bunker {bunker}\nAfter=network.target\n");
+    if socket_activated {
+        unit.push_str(&format!("Requires={name}.socket {name}-admin.socket\n"));
+        unit.push_str(&format!("After={name}.socket {name}-admin.socket\n"));
+    }
+    unit.push_str("\n[Service]\nType=notify\nNotifyAccess=main\n");
+    if socket_activated {
+        unit.push_str(&format!("Sockets={name}.socket {name}-admin.socket\n"));
+    }
+    unit.push_str(&format!("WorkingDirectory={}\n", escape_path(workdir)));
+    let argv: Vec<String> = exec_start.iter().map(|a| quote(a)).collect();
+    unit.push_str(&format!("ExecStart={}\n", argv.join(" ")));
+    unit.push_str("ExecReload=/bin/kill -HUP $MAINPID\n");
+    // SIGTERM goes to turret alone so running targets get the shutdown grace.
+    unit.push_str("KillMode=mixed\nTimeoutStopSec=30\nRestart=on-failure\n");
+    unit.push_str("\n[Install]\nWantedBy=multi-user.target\n");
+    unit
+}
+
+/// Listening socket at the path `engage` would bind (`<workdir>/<bunker>.sock` or `.admin.sock`).
+pub fn socket_unit(bunker: &str, workdir: &Path, which: SocketUnit) -> String {
+    let name = unit_name(bunker);
+    let (desc, file, fd_name, mode) = match which {
+        SocketUnit::Fire => ("fire", format!("{bunker}.sock"), "fire", "0660"),
+        SocketUnit::Admin => ("admin", format!("{bunker}.admin.sock"), "admin", "0600"),
+    };
+    let path = workdir.join(file);
+    format!(
+        "[Unit]\nDescription=turret bunker {bunker} {desc} socket\n\n\
+         [Socket]\nListenStream={}\nFileDescriptorName={fd_name}\nSocketMode={mode}\nService={name}.service\n\n\
+         [Install]\nWantedBy=sockets.target\n",
+        escape_path(&path)
+    )
+}
+
+/// Paths take specifiers but no quoting.
+fn escape_path(path: &Path) -> String {
+    path.to_string_lossy().replace('%', "%%")
+}
+
+/// One `ExecStart=` word: specifiers and variables escaped, quoted when it has whitespace or quotes.
+fn quote(word: &str) -> String {
+    let escaped = word.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty() && !escaped.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,345 @@
+//! k-of-n bunkers: the bunker is encrypted to a data key whose seed is Shamir-split, one share
+//! age-encrypted to each operator, in `<bunker>.shares`. Opening it takes k operator identities.
+
+use std::collections::BTreeSet;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::write_atomic;
+use crate::rage;
+
+const SEED_LEN: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ThresholdError {
+    #[error("shares file {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("shares file {path}: {source}")]
+    Toml { path: PathBuf, source: toml::de::Error },
+    #[error("shares file: {0}")]
+    Bad(String),
+    #[error("need {need} operator shares, these identities opened {got}")]
+    NotEnough { need: u8, got: usize },
+    #[error("rage: {0}")]
+    Rage(#[from] rage::RageError),
+    #[error("/dev/urandom: {0}")]
+    Random(#[from] io::Error),
+}
+
+/// The recovered data key and the threshold to re-split it with.
+pub struct Seal {
+    seed: [u8; SEED_LEN],
+    pub threshold: u8,
+}
+
+impl std::fmt::Debug for Seal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Seal").field("threshold", &self.threshold).finish_non_exhaustive()
+    }
+}
+
+impl Drop for Seal {
+    fn drop(&mut self) {
+        self.seed.fill(0);
+    }
+}
+
+/// `<bunker>.shares`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SharesFile {
+    pub threshold: u8,
+    /// The data key's public half; the bunker file is encrypted to it alone.
+    pub recipient: String,
+    pub shares: Vec<EncryptedShare>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptedShare {
+    pub operator: String,
+    /// Base64 of the age file holding `x || y`.
+    pub age_b64: String,
+}
+
+pub fn shares_path(bunker: &Path) -> PathBuf {
+    let mut p = bunker.as_os_str().to_owned();
+    p.push(".shares");
+    PathBuf::from(p)
+}
+
+impl Seal {
+    pub fn generate(threshold: u8) -> io::Result<Self> {
+        let mut seed = [0u8; SEED_LEN];
+        std::fs::File::open("/dev/urandom")?.read_exact(&mut seed)?;
+        Ok(Self { seed, threshold })
+    }
+
+    fn key(&self) -> ssh_key::PrivateKey {
+        ssh_key::private::Ed25519Keypair::from_seed(&self.seed).into()
+    }
+
+    /// The openssh public key the bunker is encrypted to.
+    pub fn recipient(&self) -> Result<String, ThresholdError> {
+        self.key()
+            .public_key()
+            .to_openssh()
+            .map_err(|e| ThresholdError::Bad(e.to_string()))
+    }
+
+    /// Split afresh across `operators` and encrypt each share to its operator. `scratch` holds temp files.
+    pub fn split(&self, operators: &BTreeSet<String>, scratch: &Path) -> Result<SharesFile, ThresholdError> {
+        let n = u8::try_from(operators.len()).map_err(|_| ThresholdError::Bad("more than 255 operators".into()))?;
+        if self.threshold < 1 || self.threshold > n {
+            return Err(ThresholdError::Bad(format!("threshold {} with {n} operators", self.threshold)));
+        }
+        let coeffs = random_coefficients(self.threshold)?;
+        let mut shares = Vec::new();
+        for (op, x) in operators.iter().zip(1u8..) {
+            let mut share = Vec::with_capacity(1 + SEED_LEN);
+            share.push(x);
+            share.extend(self.seed.iter().zip(&coeffs).map(|(&s, c)| eval(s, c, x)));
+            let enc = encrypt_to(&share, op, scratch)?;
+            share.fill(0);
+            shares.push(EncryptedShare {
+                operator: op.clone(),
+                age_b64: base64::engine::general_purpose::STANDARD.encode(enc),
+            });
+        }
+        Ok(SharesFile {
+            threshold: self.threshold,
+            recipient: self.recipient()?,
+            shares,
+        })
+    }
+
+    /// Decrypt shares with whichever `identities` match until `threshold` are in hand.
+    pub fn unseal(file: &SharesFile, identities: &[&Path]) -> Result<Self, ThresholdError> {
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let mut points: Vec<(u8, Vec<u8>)> = Vec::new();
+        for id in identities {
+            for s in &file.shares {
+                if points.len() >= usize::from(file.threshold) {
+                    break;
+                }
+                let enc = b64.decode(&s.age_b64).map_err(|e| ThresholdError::Bad(e.to_string()))?;
+                let Ok(share) = rage::decrypt_with_identity_file(&enc, id) else {
+                    continue;
+                };
+                let Some((&x, y)) = share.split_first().filter(|(_, y)| y.len() == SEED_LEN) else {
+                    return Err(ThresholdError::Bad(format!("malformed share for {}", s.operator)));
+                };
+                if !points.iter().any(|(px, _)| *px == x) {
+                    points.push((x, y.to_vec()));
+                }
+            }
+        }
+        if points.len() < usize::from(file.threshold) {
+            return Err(ThresholdError::NotEnough {
+                need: file.threshold,
+                got: points.len(),
+            });
+        }
+        let mut seed = [0u8; SEED_LEN];
+        for (i, byte) in seed.iter_mut().enumerate() {
+            *byte = interpolate_at_zero(points.iter().map(|(x, y)| (*x, y[i])));
+        }
+        for (_, y) in &mut points {
+            y.fill(0);
+        }
+        let seal = Self {
+            seed,
+            threshold: file.threshold,
+        };
+        if seal.recipient()? != file.recipient {
+            return Err(ThresholdError::Bad("shares do not reconstruct the data key".into()));
+        }
+        Ok(seal)
+    }
+
+    /// Decrypt the bunker file's contents, handing rage the data key in a private temp file next to it.
+    pub fn decrypt(&self, bunker: &Path, enc: &[u8]) -> Result<Vec<u8>, ThresholdError> {
+        let dir = bunker.parent().unwrap_or_else(|| Path::new("."));
+        let path = dir.join(format!(".turret.datakey.{}", std::process::id()));
+        let pem = self
+            .key()
+            .to_openssh(ssh_key::LineEnding::LF)
+            .map_err(|e| ThresholdError::Bad(e.to_string()))?;
+        let io_err = |source| ThresholdError::Io {
+            path: path.clone(),
+            source,
+        };
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(io_err)?;
+        let written = file.write_all(pem.as_bytes());
+        let out = written.map(|()| rage::decrypt_with_identity_file(enc, &path));
+        let _ = std::fs::remove_file(&path);
+        Ok(out.map_err(io_err)??)
+    }
+}
+
+impl SharesFile {
+    pub fn load(path: &Path) -> Result<Option<Self>, ThresholdError> {
+        let txt = match std::fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(ThresholdError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+        toml::from_str(&txt).map(Some).map_err(|source| ThresholdError::Toml {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ThresholdError> {
+        let io_err = |source| ThresholdError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let txt = toml::to_string_pretty(self).map_err(|e| ThresholdError::Bad(e.to_string()))?;
+        write_atomic(path, txt.as_bytes()).map_err(io_err)
+    }
+}
+
+fn encrypt_to(plaintext: &[u8], recipient: &str, scratch: &Path) -> Result<Vec<u8>, ThresholdError> {
+    let recips = scratch.join(".turret.share-recipient.tmp");
+    let out = scratch.join(".turret.share.tmp");
+    let io_err = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| ThresholdError::Io { path, source }
+    };
+    std::fs::write(&recips, format!("{recipient}\n")).map_err(io_err(&recips))?;
+    let res = rage::encrypt_to_recipients_file(plaintext, &recips, &out);
+    let _ = std::fs::remove_file(&recips);
+    res?;
+    let enc = std::fs::read(&out).map_err(io_err(&out))?;
+    let _ = std::fs::remove_file(&out);
+    Ok(enc)
+}
+
+/// For each seed byte, the k-1 random higher-order coefficients of its polynomial.
+fn random_coefficients(threshold: u8) -> io::Result<Vec<Vec<u8>>> {
+    let per_byte = usize::from(threshold - 1);
+    let mut buf = vec![0u8; SEED_LEN * per_byte];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut buf)?;
+    Ok((0..SEED_LEN).map(|i| buf[i * per_byte..(i + 1) * per_byte].to_vec()).collect())
+}
+
+/// `secret + c1*x + c2*x^2 + ...` in GF(2^8).
+fn eval(secret: u8, coeffs: &[u8], x: u8) -> u8 {
+    let higher = coeffs.iter().rev().fold(0u8, |acc, &c| gf_mul(acc ^ c, x));
+    secret ^ higher
+}
+
+/// Lagrange interpolation of the shares' polynomial at x = 0.
+fn interpolate_at_zero(points: impl Iterator<Item = (u8, u8)> + Clone) -> u8 {
+    let mut acc = 0;
+    for (xi, yi) in points.clone() {
+        let mut num = 1;
+        let mut den = 1;
+        for (xj, _) in points.clone() {
+            if xj != xi {
+                num = gf_mul(num, xj);
+                den = gf_mul(den, xi ^ xj);
+            }
+        }
+        acc ^= gf_mul(yi, gf_mul(num, gf_inv(den)));
+    }
+    acc
+}
+
+/// Multiplication modulo the AES polynomial x^8 + x^4 + x^3 + x + 1.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut p = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            p ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    p
+}
+
+/// a^254 = a^-1 for nonzero a.
+fn gf_inv(a: u8) -> u8 {
+    let mut r = 1;
+    for _ in 0..254 {
+        r = gf_mul(r, a);
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shares `(x, y)` of `seed` for x = 1..=n, as `split` computes them before encrypting.
+    fn shares(seed: &[u8; SEED_LEN], k: u8, n: u8) -> Vec<(u8, Vec<u8>)> {
+        let coeffs = random_coefficients(k).unwrap();
+        (1..=n)
+            .map(|x| (x, seed.iter().zip(&coeffs).map(|(&s, c)| eval(s, c, x)).collect()))
+            .collect()
+    }
+
+    fn reconstruct(points: &[&(u8, Vec<u8>)]) -> Vec<u8> {
+        (0..SEED_LEN)
+            .map(|i| interpolate_at_zero(points.iter().map(|(x, y)| (*x, y[i]))))
+            .collect()
+    }
+
+    /// Every subset of `items` with `size` members.
+    fn subsets<T>(items: &[T], size: usize) -> Vec<Vec<&T>> {
+        (0u32..1 << items.len())
+            .filter(|mask| mask.count_ones() as usize == size)
+            .map(|mask| items.iter().enumerate().filter(|(i, _)| mask & (1 << i) != 0).map(|(_, t)| t).collect())
+            .collect()
+    }
+
+    fn check_split(k: u8, n: u8) {
+        let seed: [u8; SEED_LEN] = std::array::from_fn(|i| (i as u8).wrapping_mul(37) ^ 0xa5);
+        let all = shares(&seed, k, n);
+        for subset in subsets(&all, usize::from(k)) {
+            let xs: Vec<u8> = subset.iter().map(|s| s.0).collect();
+            assert_eq!(reconstruct(&subset), seed, "{k}-of-{n} from {xs:?}");
+        }
+        for subset in subsets(&all, usize::from(k - 1)) {
+            assert_ne!(reconstruct(&subset), seed, "{k}-of-{n} reconstructed from {} shares", k - 1);
+        }
+    }
+
+    #[test]
+    fn two_of_three() {
+        check_split(2, 3);
+    }
+
+    #[test]
+    fn three_of_five() {
+        check_split(3, 5);
+    }
+
+    #[test]
+    fn inverses() {
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1, "a = {a}");
+        }
+    }
+}
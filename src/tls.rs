@@ -0,0 +1,221 @@
+//! Mutual-TLS TCP listener for recruits on other machines. Client certificates are not checked against any CA: a
+//! recruit's certificate is pinned in the bunker by its SHA-256, and a connection whose certificate no recruit pins
+//! is dropped before its request is read. The request is the fire socket's JSON payload; since few TLS clients can
+//! half-close, it ends at the first complete JSON value as well as at close_notify.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{DigitallySignedStruct, DistinguishedName, ServerConfig, ServerConnection, SignatureScheme};
+use tracing::{info_span, warn};
+
+use crate::daemon::Daemon;
+use crate::invoke::{Caller, Hangup};
+
+/// Handshake plus request must arrive within this long of accept.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pause after a failed accept, so a full fd table is not spun on.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("{0}")]
+    Pem(String),
+    #[error(transparent)]
+    Rustls(#[from] rustls::Error),
+}
+
+/// The server's PEM certificate chain and key. With `require_client_cert` the handshake fails without a client
+/// certificate; otherwise one is asked for but optional.
+pub fn server_config(cert: &Path, key: &Path, require_client_cert: bool) -> Result<Arc<ServerConfig>, TlsError> {
+    let pem = |path: &Path, e: rustls::pki_types::pem::Error| TlsError::Pem(format!("{}: {e}", path.display()));
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| pem(cert, e))?;
+    if chain.is_empty() {
+        return Err(TlsError::Pem(format!("{}: no certificate", cert.display())));
+    }
+    let key_der = PrivateKeyDer::from_pem_file(key).map_err(|e| pem(key, e))?;
+
+    let provider = rustls::crypto::ring::default_provider();
+    let verifier = PinnedClients {
+        algs: provider.signature_verification_algorithms,
+        required: require_client_cert,
+    };
+    let config = ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_safe_default_protocol_versions()?
+        .with_client_cert_verifier(Arc::new(verifier))
+        .with_single_cert(chain, key_der)?;
+    Ok(Arc::new(config))
+}
+
+/// Takes any client certificate whose holder proves it has the key; whose it is, the bunker's pins decide.
+#[derive(Debug)]
+struct PinnedClients {
+    algs: WebPkiSupportedAlgorithms,
+    required: bool,
+}
+
+impl ClientCertVerifier for PinnedClients {
+    fn client_auth_mandatory(&self) -> bool {
+        self.required
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algs)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algs)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algs.supported_schemes()
+    }
+}
+
+/// Finish the handshake by `until`; the SHA-256 of the client's certificate, if it sent one.
+pub(crate) fn handshake(conn: &mut ServerConnection, io: &mut Deadlined) -> io::Result<Option<String>> {
+    while conn.is_handshaking() {
+        conn.complete_io(io)?;
+    }
+    Ok(conn.peer_certificates().and_then(|c| c.first()).map(|c| crate::pin::sha256_hex(c)))
+}
+
+/// Queue `bytes` and close_notify, and send them.
+pub(crate) fn finish(conn: &mut ServerConnection, mut stream: &TcpStream, bytes: &[u8]) -> io::Result<()> {
+    conn.writer().write_all(bytes)?;
+    conn.send_close_notify();
+    while conn.wants_write() {
+        conn.write_tls(&mut stream)?;
+    }
+    Ok(())
+}
+
+/// Serve the listener on its own thread, each connection on its own thread holding a fire slot.
+pub fn spawn(daemon: Arc<Daemon>, listener: TcpListener, config: Arc<ServerConfig>) {
+    std::thread::spawn(move || loop {
+        let (stream, peer) = match listener.accept() {
+            Ok(_) if daemon.is_shutting_down() => return,
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("tls accept: {e}");
+                std::thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        let accepted = Instant::now();
+        let slot = daemon.acquire_slot();
+        let config = Arc::clone(&config);
+        std::thread::spawn(move || {
+            let _span = info_span!("tls", %peer).entered();
+            if let Err(e) = handle(&slot.0, config, stream, accepted) {
+                warn!("tls connection: {e}");
+            }
+        });
+    });
+}
+
+fn handle(daemon: &Daemon, config: Arc<ServerConfig>, stream: TcpStream, accepted: Instant) -> io::Result<()> {
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut conn = ServerConnection::new(config).map_err(io::Error::other)?;
+    let mut io = Deadlined {
+        stream: &stream,
+        until: accepted + IO_TIMEOUT,
+    };
+    let cert = handshake(&mut conn, &mut io)?;
+    if cert.as_deref().is_none_or(|c| daemon.bunker().recruit_for_cert(c).is_none()) {
+        warn!(cert = cert.as_deref().unwrap_or("none"), "tls connection refused: certificate pinned by no recruit");
+        return Ok(());
+    }
+    let req = read_json(&mut rustls::Stream::new(&mut conn, &mut io), daemon.max_request_bytes())?;
+    let caller = Caller {
+        peer: None,
+        cert,
+        // The request may have ended with close_notify, but the TCP stream stays open for the response.
+        hangup: Some(Hangup::on_eof(stream.as_raw_fd())),
+    };
+    let resp = serde_json::to_vec(&daemon.handle_fire(&req, accepted, &caller))?;
+    finish(&mut conn, &stream, &resp)
+}
+
+/// Read until one complete JSON value has arrived, the client closes, or more than `limit` bytes came.
+fn read_json(stream: &mut impl Read, limit: usize) -> io::Result<Vec<u8>> {
+    let mut req = Vec::new();
+    let mut chunk = [0u8; 16 << 10];
+    loop {
+        let n = stream.read(&mut chunk)?;
+        req.extend_from_slice(&chunk[..n]);
+        if n == 0 || req.len() > limit || (chunk[..n].contains(&b'}') && complete(&req)) {
+            return Ok(req);
+        }
+    }
+}
+
+/// Whether `buf` starts with a whole JSON value, or with something no more bytes could make one.
+fn complete(buf: &[u8]) -> bool {
+    let mut values = serde_json::Deserializer::from_slice(buf).into_iter::<serde::de::IgnoredAny>();
+    match values.next() {
+        Some(Ok(_)) => true,
+        Some(Err(e)) => !e.is_eof(),
+        None => false,
+    }
+}
+
+/// Reads from `stream`, failing with `TimedOut` once `until` passes; writes go straight through.
+pub(crate) struct Deadlined<'a> {
+    pub stream: &'a TcpStream,
+    pub until: Instant,
+}
+
+impl Read for Deadlined<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero());
+        let mut stream = self.stream;
+        stream.set_read_timeout(Some(left.ok_or(io::ErrorKind::TimedOut)?))?;
+        stream.read(buf)
+    }
+}
+
+impl Write for Deadlined<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut stream = self.stream;
+        stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::write_atomic;
+use crate::audit::now_ms;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UsageError {
+    #[error("usage state {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("usage state {path}: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counter {
+    pub calls: u64,
+    pub ok: u64,
+    pub last_ts_ms: u64,
+}
+
+impl Counter {
+    fn bump(&mut self, ok: bool, ts_ms: u64) {
+        self.calls += 1;
+        if ok {
+            self.ok += 1;
+        }
+        self.last_ts_ms = ts_ms;
+    }
+}
+
+/// Cumulative counts since `since_ms`, keyed by agent id and target name.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageSnapshot {
+    pub since_ms: u64,
+    #[serde(default)]
+    pub agents: BTreeMap<String, Counter>,
+    #[serde(default)]
+    pub targets: BTreeMap<String, Counter>,
+}
+
+/// Usage counters that survive restarts when backed by a state file.
+pub struct UsageStore {
+    path: Option<PathBuf>,
+    counts: UsageSnapshot,
+}
+
+impl UsageStore {
+    /// In-memory only.
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            counts: UsageSnapshot {
+                since_ms: now_ms(),
+                ..UsageSnapshot::default()
+            },
+        }
+    }
+
+    /// Load `path` if it exists; every `record` rewrites it.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, UsageError> {
+        let path = path.into();
+        let counts = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|source| UsageError::Json {
+                path: path.clone(),
+                source,
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => UsageSnapshot {
+                since_ms: now_ms(),
+                ..UsageSnapshot::default()
+            },
+            Err(source) => return Err(UsageError::Io { path, source }),
+        };
+        Ok(Self {
+            path: Some(path),
+            counts,
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Counters are updated even if persisting fails; the error is returned for logging.
+    pub fn record(
+        &mut self,
+        agent: Option<&str>,
+        target: Option<&str>,
+        ok: bool,
+        ts_ms: u64,
+    ) -> Result<(), UsageError> {
+        if agent.is_none() && target.is_none() {
+            return Ok(());
+        }
+        if let Some(a) = agent {
+            self.counts.agents.entry(a.to_string()).or_default().bump(ok, ts_ms);
+        }
+        if let Some(t) = target {
+            self.counts.targets.entry(t.to_string()).or_default().bump(ok, ts_ms);
+        }
+        self.persist()
+    }
+
+    pub fn snapshot(&self) -> UsageSnapshot {
+        self.counts.clone()
+    }
+
+    fn persist(&self) -> Result<(), UsageError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let io_err = |source| UsageError::Io {
+            path: path.clone(),
+            source,
+        };
+        let body = serde_json::to_vec(&self.counts).map_err(|source| UsageError::Json {
+            path: path.clone(),
+            source,
+        })?;
+        write_atomic(path, &body).map_err(io_err)
+    }
+}
+
+impl Default for UsageStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,88 @@
+//! AF_VSOCK listener so agents in VMs on this host can fire without any network setup.
+//! Same request format as the unix fire socket: write the payload, half-close, read the response.
+
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::Arc;
+use std::time::Instant;
+
+use tracing::{info_span, warn};
+
+use crate::daemon::{reply, Daemon};
+use crate::invoke::{Caller, Hangup};
+
+pub struct VsockListener {
+    fd: OwnedFd,
+}
+
+impl VsockListener {
+    /// Listen on `port` for any context id.
+    pub fn bind(port: u32) -> io::Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd was just returned by socket() and is owned by nothing else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+        addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        addr.svm_port = port;
+        addr.svm_cid = libc::VMADDR_CID_ANY;
+        let len = std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+        let bound = unsafe { libc::bind(fd.as_raw_fd(), &addr as *const _ as *const libc::sockaddr, len) };
+        if bound == -1 || unsafe { libc::listen(fd.as_raw_fd(), 128) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd })
+    }
+
+    /// The connection, as a plain read/write handle, and the peer's context id.
+    pub fn accept(&self) -> io::Result<(File, u32)> {
+        let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+        let fd = unsafe {
+            libc::accept4(
+                self.fd.as_raw_fd(),
+                &mut addr as *mut _ as *mut libc::sockaddr,
+                &mut len,
+                libc::SOCK_CLOEXEC,
+            )
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: accept4 returned a new fd that nothing else owns.
+        Ok((unsafe { File::from_raw_fd(fd) }, addr.svm_cid))
+    }
+}
+
+/// Serve fire requests from the listener on their own threads, sharing the daemon's concurrency limit.
+pub fn spawn(daemon: Arc<Daemon>, listener: VsockListener) {
+    std::thread::spawn(move || loop {
+        let (stream, cid) = match listener.accept() {
+            Ok(_) if daemon.is_shutting_down() => return,
+            Ok(conn) => conn,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                warn!("vsock accept: {e}");
+                return;
+            }
+        };
+        let accepted = Instant::now();
+        let slot = daemon.acquire_slot();
+        std::thread::spawn(move || {
+            let _span = info_span!("vsock", cid).entered();
+            let d = &slot.0;
+            let limit = d.max_request_bytes();
+            let caller = Caller {
+                hangup: Some(Hangup::on_close(stream.as_raw_fd())),
+                ..Caller::default()
+            };
+            let fire = |req: &[u8]| serde_json::to_vec(&d.handle_fire(req, accepted, &caller));
+            if let Err(e) = reply(stream, limit, fire) {
+                warn!("vsock connection: {e}");
+            }
+        });
+    });
+}
@@ -0,0 +1,69 @@
+//! `wasm` targets: a WASI preview1 module run in-process under wasmtime, with fuel and memory caps.
+
+use std::collections::BTreeMap;
+
+use wasmtime::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+use wasmtime_wasi::pipe::{MemoryInputPipe, MemoryOutputPipe};
+use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+use wasmtime_wasi::{I32Exit, WasiCtxBuilder};
+
+/// Same bound for stdout and stderr; output past it is dropped.
+const MAX_OUTPUT: usize = 1 << 20;
+
+struct Guest {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+}
+
+/// Run `_start` with `stdin` and `env`; no preopened dirs, sockets or inherited host state.
+pub(crate) fn run_module(
+    module: &str,
+    fuel: u64,
+    max_memory: usize,
+    env: &BTreeMap<String, String>,
+    stdin: Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config).map_err(|e| e.to_string())?;
+    let module = Module::from_file(&engine, module).map_err(|e| format!("load {module}: {e:#}"))?;
+
+    let stdout = MemoryOutputPipe::new(MAX_OUTPUT);
+    let stderr = MemoryOutputPipe::new(MAX_OUTPUT);
+    let mut wasi = WasiCtxBuilder::new();
+    wasi.stdin(MemoryInputPipe::new(stdin))
+        .stdout(stdout.clone())
+        .stderr(stderr.clone())
+        .arg("target");
+    for (k, v) in env {
+        wasi.env(k, v);
+    }
+    let guest = Guest {
+        wasi: wasi.build_p1(),
+        limits: StoreLimitsBuilder::new().memory_size(max_memory).instances(1).build(),
+    };
+
+    let mut store = Store::new(&engine, guest);
+    store.limiter(|g| &mut g.limits);
+    store.set_fuel(fuel).map_err(|e| e.to_string())?;
+    let mut linker: Linker<Guest> = Linker::new(&engine);
+    preview1::add_to_linker_sync(&mut linker, |g| &mut g.wasi).map_err(|e| e.to_string())?;
+
+    let run = linker
+        .instantiate(&mut store, &module)
+        .and_then(|instance| instance.get_typed_func::<(), ()>(&mut store, "_start"))
+        .and_then(|start| start.call(&mut store, ()));
+    let code = match run {
+        Ok(()) => 0,
+        Err(e) => match e.downcast_ref::<I32Exit>() {
+            Some(exit) => exit.0,
+            None => return Err(format!("module trapped: {}", e.root_cause())),
+        },
+    };
+    drop(store);
+    if code != 0 {
+        let err = String::from_utf8_lossy(&stderr.contents()).trim().to_string();
+        return Err(format!("module exited {code}: {err}"));
+    }
+    Ok(stdout.contents().to_vec())
+}